@@ -0,0 +1,69 @@
+-- Every collection is stored as JSON documents keyed by their ObjectId,
+-- with the unique indexes the models declare as expression indexes.
+
+CREATE TABLE "Detail" (
+    seq BIGSERIAL PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "TechStack" (
+    seq BIGSERIAL PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "Project" (
+    seq BIGSERIAL PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "Experience" (
+    seq BIGSERIAL PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "User" (
+    seq BIGSERIAL PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "RefreshToken" (
+    seq BIGSERIAL PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "RevokedToken" (
+    seq BIGSERIAL PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "PasswordResetToken" (
+    seq BIGSERIAL PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "ApiKey" (
+    seq BIGSERIAL PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "OidcLogin" (
+    seq BIGSERIAL PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX "TechStack_name" ON "TechStack" ((data::jsonb ->> 'name'));
+CREATE UNIQUE INDEX "User_email" ON "User" ((data::jsonb ->> 'email'));
+CREATE UNIQUE INDEX "RefreshToken_token_hash" ON "RefreshToken" ((data::jsonb ->> 'token_hash'));
+CREATE UNIQUE INDEX "PasswordResetToken_token_hash" ON "PasswordResetToken" ((data::jsonb ->> 'token_hash'));
+CREATE UNIQUE INDEX "ApiKey_key_hash" ON "ApiKey" ((data::jsonb ->> 'key_hash'));
+CREATE UNIQUE INDEX "OidcLogin_state_hash" ON "OidcLogin" ((data::jsonb ->> 'state_hash'));
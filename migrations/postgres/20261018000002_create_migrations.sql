@@ -0,0 +1,9 @@
+-- The data migrations run at startup, each recorded once.
+
+CREATE TABLE "Migration" (
+    seq BIGSERIAL PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX "Migration_name" ON "Migration" ((data::jsonb ->> 'name'));
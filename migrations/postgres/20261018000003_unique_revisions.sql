@@ -0,0 +1,22 @@
+-- Each revision of a record is stored once. Revisions numbered alike by
+-- concurrent updates hold the same version, so only the first is kept.
+
+DELETE FROM "DetailRevision" WHERE seq NOT IN (
+    SELECT MIN(seq) FROM "DetailRevision" GROUP BY (data::jsonb ->> 'record_id'), (data::jsonb ->> 'revision')
+);
+CREATE UNIQUE INDEX "DetailRevision_record_id_revision" ON "DetailRevision" ((data::jsonb ->> 'record_id'), (data::jsonb ->> 'revision'));
+
+DELETE FROM "TechStackRevision" WHERE seq NOT IN (
+    SELECT MIN(seq) FROM "TechStackRevision" GROUP BY (data::jsonb ->> 'record_id'), (data::jsonb ->> 'revision')
+);
+CREATE UNIQUE INDEX "TechStackRevision_record_id_revision" ON "TechStackRevision" ((data::jsonb ->> 'record_id'), (data::jsonb ->> 'revision'));
+
+DELETE FROM "ProjectRevision" WHERE seq NOT IN (
+    SELECT MIN(seq) FROM "ProjectRevision" GROUP BY (data::jsonb ->> 'record_id'), (data::jsonb ->> 'revision')
+);
+CREATE UNIQUE INDEX "ProjectRevision_record_id_revision" ON "ProjectRevision" ((data::jsonb ->> 'record_id'), (data::jsonb ->> 'revision'));
+
+DELETE FROM "ExperienceRevision" WHERE seq NOT IN (
+    SELECT MIN(seq) FROM "ExperienceRevision" GROUP BY (data::jsonb ->> 'record_id'), (data::jsonb ->> 'revision')
+);
+CREATE UNIQUE INDEX "ExperienceRevision_record_id_revision" ON "ExperienceRevision" ((data::jsonb ->> 'record_id'), (data::jsonb ->> 'revision'));
@@ -0,0 +1,9 @@
+-- Action tokens that only work once, kept until they expire.
+
+CREATE TABLE "RedeemedToken" (
+    seq BIGSERIAL PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX "RedeemedToken_jti" ON "RedeemedToken" ((data::jsonb ->> 'jti'));
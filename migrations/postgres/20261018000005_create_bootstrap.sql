@@ -0,0 +1,9 @@
+-- Claimed once by the first user to register.
+
+CREATE TABLE "Bootstrap" (
+    seq BIGSERIAL PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX "Bootstrap_name" ON "Bootstrap" ((data::jsonb ->> 'name'));
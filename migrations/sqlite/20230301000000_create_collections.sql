@@ -0,0 +1,69 @@
+-- Every collection is stored as JSON documents keyed by their ObjectId,
+-- with the unique indexes the models declare as expression indexes.
+
+CREATE TABLE "Detail" (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "TechStack" (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "Project" (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "Experience" (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "User" (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "RefreshToken" (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "RevokedToken" (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "PasswordResetToken" (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "ApiKey" (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE TABLE "OidcLogin" (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX "TechStack_name" ON "TechStack" (json_extract(data, '$.name'));
+CREATE UNIQUE INDEX "User_email" ON "User" (json_extract(data, '$.email'));
+CREATE UNIQUE INDEX "RefreshToken_token_hash" ON "RefreshToken" (json_extract(data, '$.token_hash'));
+CREATE UNIQUE INDEX "PasswordResetToken_token_hash" ON "PasswordResetToken" (json_extract(data, '$.token_hash'));
+CREATE UNIQUE INDEX "ApiKey_key_hash" ON "ApiKey" (json_extract(data, '$.key_hash'));
+CREATE UNIQUE INDEX "OidcLogin_state_hash" ON "OidcLogin" (json_extract(data, '$.state_hash'));
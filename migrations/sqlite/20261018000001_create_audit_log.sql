@@ -0,0 +1,7 @@
+-- Every write to a record, appended and never changed.
+
+CREATE TABLE "AuditEntry" (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
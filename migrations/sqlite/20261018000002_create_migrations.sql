@@ -0,0 +1,9 @@
+-- The data migrations run at startup, each recorded once.
+
+CREATE TABLE "Migration" (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX "Migration_name" ON "Migration" (json_extract(data, '$.name'));
@@ -0,0 +1,22 @@
+-- Each revision of a record is stored once. Revisions numbered alike by
+-- concurrent updates hold the same version, so only the first is kept.
+
+DELETE FROM "DetailRevision" WHERE seq NOT IN (
+    SELECT MIN(seq) FROM "DetailRevision" GROUP BY json_extract(data, '$.record_id'), json_extract(data, '$.revision')
+);
+CREATE UNIQUE INDEX "DetailRevision_record_id_revision" ON "DetailRevision" (json_extract(data, '$.record_id'), json_extract(data, '$.revision'));
+
+DELETE FROM "TechStackRevision" WHERE seq NOT IN (
+    SELECT MIN(seq) FROM "TechStackRevision" GROUP BY json_extract(data, '$.record_id'), json_extract(data, '$.revision')
+);
+CREATE UNIQUE INDEX "TechStackRevision_record_id_revision" ON "TechStackRevision" (json_extract(data, '$.record_id'), json_extract(data, '$.revision'));
+
+DELETE FROM "ProjectRevision" WHERE seq NOT IN (
+    SELECT MIN(seq) FROM "ProjectRevision" GROUP BY json_extract(data, '$.record_id'), json_extract(data, '$.revision')
+);
+CREATE UNIQUE INDEX "ProjectRevision_record_id_revision" ON "ProjectRevision" (json_extract(data, '$.record_id'), json_extract(data, '$.revision'));
+
+DELETE FROM "ExperienceRevision" WHERE seq NOT IN (
+    SELECT MIN(seq) FROM "ExperienceRevision" GROUP BY json_extract(data, '$.record_id'), json_extract(data, '$.revision')
+);
+CREATE UNIQUE INDEX "ExperienceRevision_record_id_revision" ON "ExperienceRevision" (json_extract(data, '$.record_id'), json_extract(data, '$.revision'));
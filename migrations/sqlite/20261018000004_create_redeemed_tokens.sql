@@ -0,0 +1,9 @@
+-- Action tokens that only work once, kept until they expire.
+
+CREATE TABLE "RedeemedToken" (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL UNIQUE,
+    data TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX "RedeemedToken_jti" ON "RedeemedToken" (json_extract(data, '$.jti'));
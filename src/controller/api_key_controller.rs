@@ -0,0 +1,140 @@
+use crate::{
+    extractor::auth_extractor::AuthUser,
+    model::{
+        api_key_model::{ApiKey, ApiKeyCreate, ApiKeyCreated},
+        audit_model::AuditAction,
+    },
+    repository::Repository,
+    service::{api_key_service, audit_service, token_service},
+};
+use actix_web::{
+    delete, get, post,
+    web::{self, Data, Json, Path},
+    HttpRequest, HttpResponse, HttpResponseBuilder, Scope,
+};
+use mongodb::bson::{doc, DateTime};
+
+pub fn new() -> Scope {
+    web::scope("/api-keys")
+        .service(create_api_key)
+        .service(get_all_api_key)
+        .service(delete_api_key)
+}
+
+#[post("")]
+pub async fn create_api_key(
+    db: Data<dyn Repository<ApiKey>>,
+    auth_user: AuthUser,
+    req: HttpRequest,
+    new_api_key: Json<ApiKeyCreate>,
+) -> HttpResponse {
+    // Keys can't be used to mint more keys.
+    if auth_user.session.is_none() {
+        return HttpResponse::Forbidden().json("API keys can only be created with a bearer token");
+    }
+    if new_api_key.name.trim().is_empty() {
+        return HttpResponse::BadRequest().json("Invalid empty name");
+    }
+    if new_api_key.scopes.is_empty() {
+        return HttpResponse::BadRequest().json("At least one scope is required");
+    }
+    if let Some(scope) = new_api_key
+        .scopes
+        .iter()
+        .find(|scope| !api_key_service::is_valid_scope(scope))
+    {
+        return HttpResponse::BadRequest().json(format!("Invalid scope: {}", scope));
+    }
+
+    let key = api_key_service::generate_key();
+    let data = ApiKey {
+        _id: None,
+        user_id: auth_user.id.clone(),
+        name: new_api_key.name.trim().to_owned(),
+        prefix: api_key_service::displayed_prefix(&key),
+        key_hash: token_service::hash_token(&key),
+        scopes: new_api_key.scopes.to_owned(),
+        created_at: DateTime::now(),
+        last_used_at: None,
+    };
+    let prefix = data.prefix.clone();
+    let scopes = data.scopes.clone();
+
+    match db.create_record(data, Some(&auth_user.id)).await {
+        Ok(record) => {
+            audit_service::record(
+                &req,
+                db.as_ref(),
+                AuditAction::Create,
+                &record.inserted_id,
+                Some(&auth_user.id),
+                None,
+            )
+            .await;
+            HttpResponse::Ok().json(ApiKeyCreated {
+                id: record.inserted_id,
+                key,
+                prefix,
+                scopes,
+            })
+        }
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+#[get("")]
+pub async fn get_all_api_key(
+    db: Data<dyn Repository<ApiKey>>,
+    auth_user: AuthUser,
+) -> HttpResponse {
+    let result = db.find_record(doc! {"user_id": &auth_user.id}).await;
+
+    match result {
+        Ok(mut records) => {
+            records.iter_mut().for_each(|api_key| {
+                api_key.key_hash.clear();
+            });
+            HttpResponse::Ok().json(records)
+        }
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+#[delete("/{id}")]
+pub async fn delete_api_key(
+    db: Data<dyn Repository<ApiKey>>,
+    auth_user: AuthUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    if id.is_empty() {
+        return HttpResponse::BadRequest().json("Invalid ID");
+    }
+    let api_key = match db.get_record(&id).await {
+        Ok(api_key) => api_key,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    if api_key.user_id != auth_user.id && !auth_user.is_admin() {
+        return HttpResponse::NotFound().json("Specified ID not found!");
+    }
+
+    let before = audit_service::snapshot(db.as_ref(), &id).await;
+
+    match db.delete_record(&id).await {
+        Ok(res) if res.deleted_count == 1 => {
+            audit_service::record(
+                &req,
+                db.as_ref(),
+                AuditAction::Delete,
+                &id,
+                Some(&auth_user.id),
+                before,
+            )
+            .await;
+            HttpResponse::Ok().json("Successfully revoked!")
+        }
+        Ok(_) => HttpResponse::NotFound().json("Specified ID not found!"),
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
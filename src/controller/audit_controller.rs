@@ -0,0 +1,102 @@
+use crate::{
+    controller::crud_controller, extractor::auth_extractor::AdminUser,
+    model::audit_model::AuditEntry, repository::Repository,
+};
+use actix_web::{
+    get,
+    web::{self, Data, Query},
+    HttpRequest, HttpResponse, Scope,
+};
+
+pub fn new() -> Scope {
+    web::scope("/audit").service(get_all_audit_entry)
+}
+
+/// Lists the audit log like any other collection, e.g. filtered with
+/// `actor=...&resource=Project&created_at[gte]=...&created_at[lt]=...`.
+#[get("")]
+pub async fn get_all_audit_entry(
+    db: Data<dyn Repository<AuditEntry>>,
+    _auth: AdminUser,
+    req: HttpRequest,
+    params: Query<Vec<(String, String)>>,
+) -> HttpResponse {
+    crud_controller::get_all(db, req, params, &[]).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        model::{
+            audit_model::AuditAction, metadata_model::Metadata, token_model::RevokedToken,
+            user_model::Role,
+        },
+        repository::memory_repo::Memory,
+        service::token_service,
+    };
+    use actix_web::{
+        http::header,
+        test::{self, TestRequest},
+        App,
+    };
+    use mongodb::bson::Document;
+    use std::sync::Arc;
+
+    #[actix_web::test]
+    async fn lists_the_newest_entries_first() {
+        let audit_db: Arc<dyn Repository<AuditEntry>> =
+            Arc::new(Memory::<AuditEntry>::init("AuditEntry"));
+        for record_id in ["first", "second", "third"] {
+            let entry = AuditEntry {
+                _id: None,
+                actor: None,
+                action: AuditAction::Create,
+                resource: "Project".to_owned(),
+                record_id: record_id.to_owned(),
+                before: None,
+                after: None,
+                ip: None,
+                metadata: Metadata::default(),
+            };
+            audit_db.create_record(entry, None).await.unwrap();
+        }
+        let revoked_db: Arc<dyn Repository<RevokedToken>> =
+            Arc::new(Memory::<RevokedToken>::init("RevokedToken"));
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(audit_db))
+                .app_data(Data::from(revoked_db))
+                .service(new()),
+        )
+        .await;
+        let token = token_service::test_access_token("6ad4abb3557298608d132501", Role::Admin);
+
+        let get = |uri: &str| {
+            TestRequest::get()
+                .uri(uri)
+                .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+                .to_request()
+        };
+        let record_ids = |page: &Document| -> Vec<String> {
+            page.get_array("items")
+                .unwrap()
+                .iter()
+                .map(|entry| entry.as_document().unwrap().get_str("record_id").unwrap())
+                .map(str::to_owned)
+                .collect()
+        };
+
+        let page: Document = test::call_and_read_body_json(&app, get("/audit")).await;
+        assert_eq!(record_ids(&page), ["third", "second", "first"]);
+
+        let page: Document = test::call_and_read_body_json(&app, get("/audit?limit=2")).await;
+        assert_eq!(record_ids(&page), ["third", "second"]);
+        let uri = format!(
+            "/audit?limit=2&cursor={}",
+            page.get_str("next_cursor").unwrap()
+        );
+        let page: Document = test::call_and_read_body_json(&app, get(&uri)).await;
+        assert_eq!(record_ids(&page), ["first"]);
+    }
+}
@@ -1,41 +1,264 @@
-use crate::repository::mongodb_repo::MongoDB;
+use crate::{
+    model::{
+        audit_model::AuditAction,
+        page_model::Page,
+        revision_model::{FieldChange, Revision, RevisionDiffQuery},
+        QueryableModel, StampedModel,
+    },
+    repository::{version_of, RecordQuery, Repository, TrashRepository},
+    service::{
+        audit_service,
+        query_service::{self, render, ListQuery},
+        reference_service::{self, Reference},
+    },
+};
 use actix_web::{
-    web::{Data, Json, Path},
-    HttpResponse, HttpResponseBuilder,
+    http::{
+        header::{self, ETag, EntityTag, Header, IfMatch, IfNoneMatch},
+        StatusCode,
+    },
+    web::{Data, Json, Path, Query},
+    HttpRequest, HttpResponse, HttpResponseBuilder,
 };
-use mongodb::bson::{doc, to_document};
+use mongodb::bson::{doc, from_document, oid::ObjectId, to_document, Bson, Document};
 use serde::{de::DeserializeOwned, Serialize};
+use url::form_urlencoded;
 
-pub async fn create<T>(db: Data<MongoDB<T>>, data: T) -> HttpResponse
+/// Creates a record on behalf of the user with the ID `actor`, if any.
+pub async fn create<T>(
+    db: Data<dyn Repository<T>>,
+    req: HttpRequest,
+    data: T,
+    actor: Option<&str>,
+    references: &[Reference<'_>],
+) -> HttpResponse
 where
-    T: Serialize + DeserializeOwned + Unpin + Send + Sync,
+    T: Serialize + DeserializeOwned + Unpin + Send + Sync + 'static,
 {
-    let result = db.create_record(data).await;
+    let record = match to_document(&data) {
+        Ok(record) => record,
+        Err(err) => return HttpResponse::BadRequest().json(err.to_string()),
+    };
+    match reference_service::validate(references, &record).await {
+        Ok(errors) if !errors.is_empty() => {
+            return HttpResponse::UnprocessableEntity().json(errors)
+        }
+        Ok(_) => {}
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    }
+    let result = db.create_record(data, actor).await;
 
     match result {
-        Ok(record) => match record.inserted_id.as_object_id() {
-            Some(object_id) => HttpResponse::Ok().json(doc! { "id": object_id.to_string() }),
-            None => HttpResponse::Ok().json(record),
-        },
+        Ok(record) => {
+            let id = record.inserted_id;
+            audit_service::record(&req, db.as_ref(), AuditAction::Create, &id, actor, None).await;
+            HttpResponse::Ok().json(doc! { "id": id })
+        }
         Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
     }
 }
 
-pub async fn get_all<T>(db: Data<MongoDB<T>>) -> HttpResponse
+/// This request's path and query with the page position replaced.
+fn page_url(req: &HttpRequest, position: Option<(&str, String)>) -> String {
+    let params = form_urlencoded::parse(req.query_string().as_bytes())
+        .filter(|(key, _)| key != "cursor" && key != "offset");
+    let query = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .extend_pairs(position)
+        .finish();
+
+    match query.is_empty() {
+        true => req.path().to_owned(),
+        false => format!("{}?{}", req.path(), query),
+    }
+}
+
+/// Lists a page of records, filtered, sorted and projected as described in
+/// [`query_service::parse`] and with the `references` asked for expanded,
+/// with RFC 8288 `Link` headers to the first, previous and next pages.
+pub async fn get_all<T>(
+    db: Data<dyn Repository<T>>,
+    req: HttpRequest,
+    params: Query<Vec<(String, String)>>,
+    references: &[Reference<'_>],
+) -> HttpResponse
 where
-    T: Serialize + DeserializeOwned + Unpin + Send + Sync,
+    T: QueryableModel + Serialize + DeserializeOwned + Unpin + Send + Sync + 'static,
 {
-    let result = db.get_all_record().await;
+    let ListQuery {
+        records: mut query,
+        filter,
+        limit,
+    } = match query_service::parse(&params, T::FIELDS, T::DEFAULT_SORT) {
+        Ok(list) => list,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    // The next cursor needs the sort fields even if they weren't asked for.
+    let mut hidden = Vec::new();
+    if let Some(fields) = &mut query.projection {
+        for field in query.sort.keys() {
+            if field != "_id" && !fields.contains(field) {
+                fields.push(field.to_owned());
+                hidden.push(field.to_owned());
+            }
+        }
+    }
+    let sort = query.sort.clone();
+    let offset = query.skip;
+    let expand = query_service::expanded(&params);
+    let projected = query.projection.is_some() || !expand.is_empty();
 
-    match result {
-        Ok(records) => HttpResponse::Ok().json(records),
+    let total = match db.count_records(filter).await {
+        Ok(total) => total,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    let mut records = match db.query_records(query).await {
+        Ok(records) => records,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    let next_cursor = match records.len() as u64 > limit {
+        true => {
+            records.truncate(limit as usize);
+            match records
+                .last()
+                .map(|last| query_service::cursor(last, &sort))
+            {
+                Some(Ok(cursor)) => Some(cursor),
+                Some(Err((status_code, err))) => {
+                    return HttpResponseBuilder::new(status_code).json(err)
+                }
+                None => None,
+            }
+        }
+        false => None,
+    };
+
+    let mut links = vec![format!(r#"<{}>; rel="first""#, page_url(&req, None))];
+    if offset > 0 {
+        let previous = offset.saturating_sub(limit).to_string();
+        links.push(format!(
+            r#"<{}>; rel="prev""#,
+            page_url(&req, Some(("offset", previous)))
+        ));
+    }
+    if let Some(cursor) = &next_cursor {
+        links.push(format!(
+            r#"<{}>; rel="next""#,
+            page_url(&req, Some(("cursor", cursor.to_owned())))
+        ));
+    }
+    let mut response = HttpResponse::Ok();
+    response.insert_header((header::LINK, links.join(", ")));
+
+    if projected {
+        let mut items: Vec<Document> = records
+            .into_iter()
+            .map(|record| {
+                let mut item = render(record, &hidden);
+                T::compute(&mut item);
+                item
+            })
+            .collect();
+        if let Err((status_code, err)) =
+            reference_service::expand(references, &expand, &mut items).await
+        {
+            return HttpResponseBuilder::new(status_code).json(err);
+        }
+        return response.json(Page {
+            items,
+            total,
+            next_cursor,
+        });
+    }
+    match records
+        .into_iter()
+        .map(|record| {
+            from_document::<T>(record)
+                .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+                .and_then(|record| presented(&record))
+        })
+        .collect::<Result<Vec<Document>, _>>()
+    {
+        Ok(items) => response.json(Page {
+            items,
+            total,
+            next_cursor,
+        }),
         Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
     }
 }
 
-pub async fn get<T>(db: Data<MongoDB<T>>, path: Path<String>) -> HttpResponse
+/// `record` the way it is sent, with the fields `T` computes.
+fn presented<T: QueryableModel + Serialize>(record: &T) -> Result<Document, (StatusCode, String)> {
+    let mut rendered =
+        to_document(record).map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    T::compute(&mut rendered);
+    Ok(rendered)
+}
+
+/// The `ETag` of a record, its version.
+fn etag<T: StampedModel>(record: &T) -> ETag {
+    ETag(EntityTag::new_strong(record.metadata().version.to_string()))
+}
+
+/// The versions an `If-Match` header lets a write apply to, or `None` for
+/// any version.
+fn if_match(req: &HttpRequest) -> Result<Option<Vec<i64>>, (StatusCode, String)> {
+    if !req.headers().contains_key(header::IF_MATCH) {
+        return Ok(None);
+    }
+    match IfMatch::parse(req) {
+        Ok(IfMatch::Any) => Ok(None),
+        // Only strong tags can match, and ours are all version numbers.
+        Ok(IfMatch::Items(tags)) => Ok(Some(
+            tags.iter()
+                .filter(|tag| !tag.weak)
+                .filter_map(|tag| tag.tag().parse().ok())
+                .collect(),
+        )),
+        Err(_) => Err((
+            StatusCode::BAD_REQUEST,
+            "Invalid If-Match header".to_owned(),
+        )),
+    }
+}
+
+/// A filter for the record with `id` as long as it is at one of `versions`.
+fn versioned(id: &str, versions: Vec<i64>) -> Result<Document, (StatusCode, String)> {
+    let id =
+        ObjectId::parse_str(id).map_err(|_| (StatusCode::BAD_REQUEST, "Invalid ID".to_owned()))?;
+    // Records written before versions existed have none, which is version 0.
+    let versions: Vec<Bson> = versions
+        .into_iter()
+        .map(|version| match version {
+            0 => Bson::Null,
+            version => Bson::Int64(version),
+        })
+        .collect();
+
+    Ok(doc! {"_id": id, "version": {"$in": versions}})
+}
+
+/// Tells a conditional write that missed because the record has changed
+/// apart from one for a record that doesn't exist.
+async fn precondition_failed<T>(db: &dyn Repository<T>, id: &str) -> HttpResponse {
+    match db.get_record(id).await {
+        Ok(_) => HttpResponse::PreconditionFailed().json("Record has been modified"),
+        Err((StatusCode::NOT_FOUND, _)) => HttpResponse::NotFound().json("Specified ID not found"),
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+/// Gets a record with its version as `ETag`, or `304 Not Modified` if it
+/// matches `If-None-Match`, with the `references` asked for expanded.
+pub async fn get<T>(
+    db: Data<dyn Repository<T>>,
+    req: HttpRequest,
+    path: Path<String>,
+    references: &[Reference<'_>],
+) -> HttpResponse
 where
-    T: Serialize + DeserializeOwned + Unpin + Send + Sync,
+    T: QueryableModel + StampedModel + Serialize + DeserializeOwned + Unpin + Send + Sync + 'static,
 {
     let id = path.into_inner();
     if id.is_empty() {
@@ -44,14 +267,45 @@ where
     let result = db.get_record(&id).await;
 
     match result {
-        Ok(record) => HttpResponse::Ok().json(record),
+        Ok(record) => {
+            let etag = etag(&record);
+            let not_modified = match IfNoneMatch::parse(&req) {
+                Ok(IfNoneMatch::Any) => true,
+                Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+                Err(_) => false,
+            };
+            let expand = Query::<Vec<(String, String)>>::from_query(req.query_string())
+                .map(|params| query_service::expanded(&params))
+                .unwrap_or_default();
+            if not_modified {
+                return HttpResponse::NotModified().insert_header(etag).finish();
+            }
+            let mut records = match presented(&record) {
+                Ok(record) => vec![record],
+                Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+            };
+            match reference_service::expand(references, &expand, &mut records).await {
+                Ok(()) => HttpResponse::Ok().insert_header(etag).json(&records[0]),
+                Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+            }
+        }
         Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
     }
 }
 
-pub async fn update<T, U>(db: Data<MongoDB<T>>, path: Path<String>, new: Json<U>) -> HttpResponse
+/// Updates a record on behalf of the user with the ID `actor`, only if it is
+/// still at a version in `If-Match` when given, `412 Precondition Failed`
+/// otherwise.
+pub async fn update<T, U>(
+    db: Data<dyn Repository<T>>,
+    req: HttpRequest,
+    path: Path<String>,
+    new: Json<U>,
+    actor: &str,
+    references: &[Reference<'_>],
+) -> HttpResponse
 where
-    T: Serialize + DeserializeOwned + Unpin + Send + Sync,
+    T: QueryableModel + StampedModel + Serialize + DeserializeOwned + Unpin + Send + Sync + 'static,
     U: Serialize,
 {
     let id = path.into_inner();
@@ -62,17 +316,50 @@ where
         Ok(data) => data,
         Err(err) => return HttpResponse::BadRequest().json(err.to_string()),
     };
-    let result = db.update_record(&id, doc).await;
+    match reference_service::validate(references, &doc).await {
+        Ok(errors) if !errors.is_empty() => {
+            return HttpResponse::UnprocessableEntity().json(errors)
+        }
+        Ok(_) => {}
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    }
+    let versions = match if_match(&req) {
+        Ok(versions) => versions,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    let conditional = versions.is_some();
+    let before = audit_service::snapshot(db.as_ref(), &id).await;
+    let result = match versions {
+        Some(_) if doc.is_empty() => {
+            return HttpResponse::BadRequest().json("No schema data fields to update")
+        }
+        Some(versions) => match versioned(&id, versions) {
+            Ok(filter) => db.update_many_records(filter, doc, Some(actor)).await,
+            Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+        },
+        None => db.update_record(&id, doc, Some(actor)).await,
+    };
 
     match result {
         Ok(update) => {
             if update.matched_count == 1 {
+                audit_service::record(
+                    &req,
+                    db.as_ref(),
+                    AuditAction::Update,
+                    &id,
+                    Some(actor),
+                    before,
+                )
+                .await;
                 let updated = db.get_record(&id).await;
 
-                match updated {
-                    Ok(record) => HttpResponse::Ok().json(record),
+                match updated.and_then(|record| Ok((etag(&record), presented(&record)?))) {
+                    Ok((etag, record)) => HttpResponse::Ok().insert_header(etag).json(record),
                     Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
                 }
+            } else if conditional {
+                precondition_failed(db.as_ref(), &id).await
             } else {
                 HttpResponse::NotFound().json("Specified ID not found")
             }
@@ -81,20 +368,54 @@ where
     }
 }
 
-pub async fn delete<T>(db: Data<MongoDB<T>>, path: Path<String>) -> HttpResponse
+/// Moves a record to the trash on behalf of the user with the ID `actor`,
+/// only if it is still at a version in `If-Match` when given,
+/// `412 Precondition Failed` otherwise.
+pub async fn delete<T>(
+    db: Data<dyn TrashRepository<T>>,
+    req: HttpRequest,
+    path: Path<String>,
+    actor: &str,
+) -> HttpResponse
 where
-    T: Serialize + DeserializeOwned + Unpin + Send + Sync,
+    T: Serialize + DeserializeOwned + Unpin + Send + Sync + 'static,
 {
     let id = path.into_inner();
     if id.is_empty() {
         return HttpResponse::BadRequest().json("Invalid ID");
     };
-    let result = db.delete_record(&id).await;
+    let versions = match if_match(&req) {
+        Ok(versions) => versions,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    let conditional = versions.is_some();
+    let filter = match versions {
+        Some(versions) => versioned(&id, versions),
+        None => ObjectId::parse_str(&id)
+            .map(|id| doc! {"_id": id})
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid ID".to_owned())),
+    };
+    let before = audit_service::snapshot(db.as_ref(), &id).await;
+    let result = match filter {
+        Ok(filter) => db.trash_records(filter, Some(actor)).await,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
 
     match result {
         Ok(res) => {
             if res.deleted_count == 1 {
+                audit_service::record(
+                    &req,
+                    db.as_ref(),
+                    AuditAction::Delete,
+                    &id,
+                    Some(actor),
+                    before,
+                )
+                .await;
                 HttpResponse::Ok().json("Successfully deleted!")
+            } else if conditional {
+                precondition_failed(db.as_ref(), &id).await
             } else {
                 HttpResponse::NotFound().json("Specified ID not found!")
             }
@@ -102,3 +423,261 @@ where
         Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
     }
 }
+
+/// Lists a page of the trashed records like [`get_all`].
+pub async fn get_trash<T>(
+    db: Data<dyn TrashRepository<T>>,
+    req: HttpRequest,
+    params: Query<Vec<(String, String)>>,
+) -> HttpResponse
+where
+    T: QueryableModel + Serialize + DeserializeOwned + Unpin + Send + Sync + 'static,
+{
+    get_all(Data::from(db.trash()), req, params, &[]).await
+}
+
+/// Moves a record out of the trash on behalf of the user with the ID `actor`.
+pub async fn restore<T>(
+    db: Data<dyn TrashRepository<T>>,
+    req: HttpRequest,
+    path: Path<String>,
+    actor: &str,
+) -> HttpResponse
+where
+    T: QueryableModel + StampedModel + Serialize + DeserializeOwned + Unpin + Send + Sync + 'static,
+{
+    let id = path.into_inner();
+    let before = audit_service::snapshot(db.trash().as_ref(), &id).await;
+    let result = db.restore_record(&id, Some(actor)).await;
+    if matches!(&result, Ok(update) if update.matched_count == 1) {
+        audit_service::record(
+            &req,
+            db.as_ref(),
+            AuditAction::Restore,
+            &id,
+            Some(actor),
+            before,
+        )
+        .await;
+    }
+
+    match result {
+        Ok(update) if update.matched_count == 1 => match db
+            .get_record(&id)
+            .await
+            .and_then(|record| Ok((etag(&record), presented(&record)?)))
+        {
+            Ok((etag, record)) => HttpResponse::Ok().insert_header(etag).json(record),
+            Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+        },
+        Ok(_) => HttpResponse::NotFound().json("Specified ID not found in the trash"),
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+/// Permanently deletes a record from the trash on behalf of the user with
+/// the ID `actor`.
+pub async fn purge<T>(
+    db: Data<dyn TrashRepository<T>>,
+    req: HttpRequest,
+    path: Path<String>,
+    actor: &str,
+) -> HttpResponse
+where
+    T: Serialize + DeserializeOwned + Unpin + Send + Sync + 'static,
+{
+    let id = path.into_inner();
+    let trash = db.trash();
+    let before = audit_service::snapshot(trash.as_ref(), &id).await;
+    let result = trash.delete_record(&id).await;
+
+    match result {
+        Ok(res) if res.deleted_count == 1 => {
+            audit_service::record(
+                &req,
+                trash.as_ref(),
+                AuditAction::Purge,
+                &id,
+                Some(actor),
+                before,
+            )
+            .await;
+            HttpResponse::Ok().json("Successfully purged!")
+        }
+        Ok(_) => HttpResponse::NotFound().json("Specified ID not found in the trash"),
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+/// Renders a stored revision like [`render`] does records. Revisions are
+/// never updated, so only their creation metadata is kept.
+fn render_revision(mut revision: Document) -> Document {
+    if let Some(Bson::Document(record)) = revision.remove("record") {
+        revision.insert("record", render(record, &[]));
+    }
+    let hidden = ["updated_at", "updated_by", "version"].map(String::from);
+    render(revision, &hidden)
+}
+
+/// The current version of the record with `id`, `404 Not Found` if there is
+/// none.
+async fn current<T>(db: &dyn Repository<T>, id: &str) -> Result<Document, (StatusCode, String)> {
+    let id =
+        ObjectId::parse_str(id).map_err(|_| (StatusCode::BAD_REQUEST, "Invalid ID".to_owned()))?;
+    db.query_records(RecordQuery {
+        filter: doc! {"_id": id},
+        ..Default::default()
+    })
+    .await?
+    .pop()
+    .ok_or((StatusCode::NOT_FOUND, "Specified ID not found".to_owned()))
+}
+
+/// The stored revision `n` of the record with `id`.
+pub async fn revision<T>(
+    revisions: &dyn Repository<Revision<T>>,
+    id: &str,
+    n: i64,
+) -> Result<Document, (StatusCode, String)> {
+    revisions
+        .query_records(RecordQuery {
+            filter: doc! {"record_id": id, "revision": n},
+            limit: Some(1),
+            ..Default::default()
+        })
+        .await?
+        .pop()
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            "Specified revision not found".to_owned(),
+        ))
+}
+
+/// The record with `id` as it was at version `n`, or as it is now if `None`.
+async fn snapshot<T>(
+    db: &dyn Repository<T>,
+    revisions: &dyn Repository<Revision<T>>,
+    id: &str,
+    n: Option<i64>,
+) -> Result<Document, (StatusCode, String)> {
+    let current = current(db, id).await?;
+    let n = match n {
+        Some(n) if n != version_of(&current) => n,
+        _ => return Ok(current),
+    };
+    match revision(revisions, id, n).await?.remove("record") {
+        Some(Bson::Document(record)) => Ok(record),
+        _ => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Revision has no record".to_owned(),
+        )),
+    }
+}
+
+/// Lists the stored revisions of a record, oldest first.
+pub async fn get_revisions<T>(
+    db: Data<dyn Repository<T>>,
+    revisions: Data<dyn Repository<Revision<T>>>,
+    path: Path<String>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    if let Err((status_code, err)) = current(db.as_ref(), &id).await {
+        return HttpResponseBuilder::new(status_code).json(err);
+    }
+    let result = revisions
+        .query_records(RecordQuery {
+            filter: doc! {"record_id": &id},
+            sort: doc! {"revision": 1},
+            ..Default::default()
+        })
+        .await;
+
+    match result {
+        Ok(records) => {
+            let records: Vec<Document> = records.into_iter().map(render_revision).collect();
+            HttpResponse::Ok().json(records)
+        }
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+/// Gets the stored revision `n` of a record.
+pub async fn get_revision<T>(
+    db: Data<dyn Repository<T>>,
+    revisions: Data<dyn Repository<Revision<T>>>,
+    path: Path<(String, i64)>,
+) -> HttpResponse {
+    let (id, n) = path.into_inner();
+    if let Err((status_code, err)) = current(db.as_ref(), &id).await {
+        return HttpResponseBuilder::new(status_code).json(err);
+    }
+    let result = revision(revisions.as_ref(), &id, n).await;
+
+    match result {
+        Ok(revision) => HttpResponse::Ok().json(render_revision(revision)),
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+/// Lists the fields that changed from version `n` of a record to the one in
+/// `against`, the current one by default.
+pub async fn diff_revision<T>(
+    db: Data<dyn Repository<T>>,
+    revisions: Data<dyn Repository<Revision<T>>>,
+    path: Path<(String, i64)>,
+    query: Query<RevisionDiffQuery>,
+) -> HttpResponse
+where
+    T: QueryableModel,
+{
+    let (id, n) = path.into_inner();
+    let (from, to) = match (
+        snapshot(db.as_ref(), revisions.as_ref(), &id, Some(n)).await,
+        snapshot(db.as_ref(), revisions.as_ref(), &id, query.against).await,
+    ) {
+        (Ok(from), Ok(to)) => (render(from, &[]), render(to, &[])),
+        (Err((status_code, err)), _) | (_, Err((status_code, err))) => {
+            return HttpResponseBuilder::new(status_code).json(err)
+        }
+    };
+    let changes: Vec<FieldChange> = T::FIELDS
+        .iter()
+        .filter(|field| from.get(field) != to.get(field))
+        .map(|field| FieldChange {
+            field: field.to_string(),
+            from: from.get(field).cloned(),
+            to: to.get(field).cloned(),
+        })
+        .collect();
+
+    HttpResponse::Ok().json(changes)
+}
+
+/// Updates a record back to the fields it had at version `n`, like
+/// [`update`] does.
+pub async fn restore_revision<T>(
+    db: Data<dyn Repository<T>>,
+    revisions: Data<dyn Repository<Revision<T>>>,
+    req: HttpRequest,
+    path: Path<(String, i64)>,
+    actor: &str,
+    references: &[Reference<'_>],
+) -> HttpResponse
+where
+    T: QueryableModel + StampedModel + Serialize + DeserializeOwned + Unpin + Send + Sync + 'static,
+{
+    let (id, n) = path.into_inner();
+    let record = match revision(revisions.as_ref(), &id, n).await {
+        Ok(mut revision) => match revision.remove("record") {
+            Some(Bson::Document(record)) => record,
+            _ => return HttpResponse::InternalServerError().json("Revision has no record"),
+        },
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    let fields: Document = record
+        .into_iter()
+        .filter(|(field, _)| T::FIELDS.contains(&field.as_str()))
+        .collect();
+
+    update(db, req, Path::from(id), Json(fields), actor, references).await
+}
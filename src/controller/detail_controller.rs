@@ -1,54 +1,159 @@
 use crate::{
     controller::crud_controller,
-    model::detail_model::{Detail, DetailUpdate},
-    repository::mongodb_repo::MongoDB,
+    extractor::auth_extractor::{AdminUser, EditorUser},
+    model::{
+        detail_model::{Detail, DetailUpdate},
+        metadata_model::Metadata,
+        revision_model::{Revision, RevisionDiffQuery},
+    },
+    repository::{Repository, TrashRepository},
 };
 use actix_web::{
     delete, get, post, put,
-    web::{self, Data, Json, Path},
-    HttpResponse, Scope,
+    web::{self, Data, Json, Path, Query},
+    HttpRequest, HttpResponse, Scope,
 };
 
 pub fn new() -> Scope {
     web::scope("/details")
         .service(create_detail)
         .service(get_all_detail)
+        .service(get_trashed_detail)
         .service(get_detail)
         .service(update_detail)
         .service(delete_detail)
+        .service(restore_detail)
+        .service(purge_detail)
+        .service(get_detail_revisions)
+        .service(get_detail_revision)
+        .service(diff_detail_revision)
+        .service(restore_detail_revision)
 }
 
 #[post("")]
-pub async fn create_detail(db: Data<MongoDB<Detail>>, new_detail: Json<Detail>) -> HttpResponse {
+pub async fn create_detail(
+    db: Data<dyn Repository<Detail>>,
+    auth: EditorUser,
+    req: HttpRequest,
+    new_detail: Json<Detail>,
+) -> HttpResponse {
     let data = Detail {
         _id: None,
         name: new_detail.name.to_owned(),
         description: new_detail.description.to_owned(),
         image: new_detail.image.to_owned(),
+        metadata: Metadata::default(),
     };
-    crud_controller::create(db, data).await
+    crud_controller::create(db, req, data, Some(&auth.id), &[]).await
 }
 
 #[get("")]
-pub async fn get_all_detail(db: Data<MongoDB<Detail>>) -> HttpResponse {
-    crud_controller::get_all(db).await
+pub async fn get_all_detail(
+    db: Data<dyn Repository<Detail>>,
+    req: HttpRequest,
+    params: Query<Vec<(String, String)>>,
+) -> HttpResponse {
+    crud_controller::get_all(db, req, params, &[]).await
 }
 
 #[get("/{id}")]
-pub async fn get_detail(db: Data<MongoDB<Detail>>, path: Path<String>) -> HttpResponse {
-    crud_controller::get(db, path).await
+pub async fn get_detail(
+    db: Data<dyn Repository<Detail>>,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::get(db, req, path, &[]).await
 }
 
 #[put("/{id}")]
 pub async fn update_detail(
-    db: Data<MongoDB<Detail>>,
+    db: Data<dyn Repository<Detail>>,
+    auth: EditorUser,
+    req: HttpRequest,
     path: Path<String>,
     new_detail: Json<DetailUpdate>,
 ) -> HttpResponse {
-    crud_controller::update(db, path, new_detail).await
+    crud_controller::update(db, req, path, new_detail, &auth.id, &[]).await
 }
 
 #[delete("/{id}")]
-pub async fn delete_detail(db: Data<MongoDB<Detail>>, path: Path<String>) -> HttpResponse {
-    crud_controller::delete(db, path).await
+pub async fn delete_detail(
+    db: Data<dyn TrashRepository<Detail>>,
+    auth: EditorUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::delete(db, req, path, &auth.id).await
+}
+
+#[get("/trash")]
+pub async fn get_trashed_detail(
+    db: Data<dyn TrashRepository<Detail>>,
+    _auth: EditorUser,
+    req: HttpRequest,
+    params: Query<Vec<(String, String)>>,
+) -> HttpResponse {
+    crud_controller::get_trash(db, req, params).await
+}
+
+#[post("/{id}/restore")]
+pub async fn restore_detail(
+    db: Data<dyn TrashRepository<Detail>>,
+    auth: EditorUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::restore(db, req, path, &auth.id).await
+}
+
+#[delete("/trash/{id}")]
+pub async fn purge_detail(
+    db: Data<dyn TrashRepository<Detail>>,
+    admin: AdminUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::purge(db, req, path, &admin.id).await
+}
+
+#[get("/{id}/revisions")]
+pub async fn get_detail_revisions(
+    db: Data<dyn Repository<Detail>>,
+    revisions: Data<dyn Repository<Revision<Detail>>>,
+    _auth: EditorUser,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::get_revisions(db, revisions, path).await
+}
+
+#[get("/{id}/revisions/{n}")]
+pub async fn get_detail_revision(
+    db: Data<dyn Repository<Detail>>,
+    revisions: Data<dyn Repository<Revision<Detail>>>,
+    _auth: EditorUser,
+    path: Path<(String, i64)>,
+) -> HttpResponse {
+    crud_controller::get_revision(db, revisions, path).await
+}
+
+#[get("/{id}/revisions/{n}/diff")]
+pub async fn diff_detail_revision(
+    db: Data<dyn Repository<Detail>>,
+    revisions: Data<dyn Repository<Revision<Detail>>>,
+    _auth: EditorUser,
+    path: Path<(String, i64)>,
+    query: Query<RevisionDiffQuery>,
+) -> HttpResponse {
+    crud_controller::diff_revision(db, revisions, path, query).await
+}
+
+#[post("/{id}/revisions/{n}/restore")]
+pub async fn restore_detail_revision(
+    db: Data<dyn Repository<Detail>>,
+    revisions: Data<dyn Repository<Revision<Detail>>>,
+    auth: EditorUser,
+    req: HttpRequest,
+    path: Path<(String, i64)>,
+) -> HttpResponse {
+    crud_controller::restore_revision(db, revisions, req, path, &auth.id, &[]).await
 }
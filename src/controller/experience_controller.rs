@@ -1,28 +1,52 @@
 use crate::{
     controller::crud_controller,
-    model::experience_model::{Experience, ExperienceUpdate},
-    repository::mongodb_repo::MongoDB,
+    extractor::auth_extractor::{AdminUser, EditorUser},
+    model::{
+        experience_model::{Experience, ExperienceUpdate},
+        metadata_model::Metadata,
+        revision_model::{Revision, RevisionDiffQuery},
+        tech_stack_model::TechStack,
+    },
+    repository::{Repository, TrashRepository},
+    service::{reference_service::Reference, validation_service::validate_period},
 };
 use actix_web::{
-    delete, get, post, put,
-    web::{self, Data, Json, Path},
-    HttpResponse, Scope,
+    delete, get,
+    http::StatusCode,
+    post, put,
+    web::{self, Data, Json, Path, Query},
+    HttpRequest, HttpResponse, HttpResponseBuilder, Scope,
 };
+use mongodb::bson::{from_document, Bson};
 
 pub fn new() -> Scope {
     web::scope("/experiences")
         .service(create_experience)
         .service(get_all_experience)
+        .service(get_trashed_experience)
         .service(get_experience)
         .service(update_experience)
         .service(delete_experience)
+        .service(restore_experience)
+        .service(purge_experience)
+        .service(get_experience_revisions)
+        .service(get_experience_revision)
+        .service(diff_experience_revision)
+        .service(restore_experience_revision)
 }
 
 #[post("")]
 pub async fn create_experience(
-    db: Data<MongoDB<Experience>>,
+    db: Data<dyn Repository<Experience>>,
+    tech_stack_db: Data<dyn Repository<TechStack>>,
+    auth: EditorUser,
+    req: HttpRequest,
     new_experience: Json<Experience>,
 ) -> HttpResponse {
+    let errors = validate_period(new_experience.start.as_ref(), new_experience.end.as_ref());
+    if !errors.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(errors);
+    }
     let data = Experience {
         _id: None,
         role: new_experience.role.to_owned(),
@@ -31,30 +55,272 @@ pub async fn create_experience(
         start: new_experience.start.to_owned(),
         end: new_experience.end.to_owned(),
         tech_stack: new_experience.tech_stack.to_owned(),
+        metadata: Metadata::default(),
     };
-    crud_controller::create(db, data).await
+    crud_controller::create(
+        db,
+        req,
+        data,
+        Some(&auth.id),
+        &[Reference::new("tech_stack", tech_stack_db.as_ref())],
+    )
+    .await
 }
 
 #[get("")]
-pub async fn get_all_experience(db: Data<MongoDB<Experience>>) -> HttpResponse {
-    crud_controller::get_all(db).await
+pub async fn get_all_experience(
+    db: Data<dyn Repository<Experience>>,
+    tech_stack_db: Data<dyn Repository<TechStack>>,
+    req: HttpRequest,
+    params: Query<Vec<(String, String)>>,
+) -> HttpResponse {
+    crud_controller::get_all(
+        db,
+        req,
+        params,
+        &[Reference::new("tech_stack", tech_stack_db.as_ref())],
+    )
+    .await
 }
 
 #[get("/{id}")]
-pub async fn get_experience(db: Data<MongoDB<Experience>>, path: Path<String>) -> HttpResponse {
-    crud_controller::get(db, path).await
+pub async fn get_experience(
+    db: Data<dyn Repository<Experience>>,
+    tech_stack_db: Data<dyn Repository<TechStack>>,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::get(
+        db,
+        req,
+        path,
+        &[Reference::new("tech_stack", tech_stack_db.as_ref())],
+    )
+    .await
 }
 
 #[put("/{id}")]
 pub async fn update_experience(
-    db: Data<MongoDB<Experience>>,
+    db: Data<dyn Repository<Experience>>,
+    tech_stack_db: Data<dyn Repository<TechStack>>,
+    auth: EditorUser,
+    req: HttpRequest,
     path: Path<String>,
     new_experience: Json<ExperienceUpdate>,
 ) -> HttpResponse {
-    crud_controller::update(db, path, new_experience).await
+    // Dates left out keep their stored value, which the new ones have to fit.
+    if new_experience.start.is_some() || new_experience.end.is_some() {
+        match db.get_record(path.as_str()).await {
+            Ok(current) => {
+                let start = new_experience.start.or(current.start);
+                let end = new_experience.end.unwrap_or(current.end);
+                let errors = validate_period(start.as_ref(), end.as_ref());
+                if !errors.is_empty() {
+                    return HttpResponse::UnprocessableEntity().json(errors);
+                }
+            }
+            // Left for the update to answer.
+            Err((StatusCode::NOT_FOUND, _)) => {}
+            Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+        }
+    }
+    crud_controller::update(
+        db,
+        req,
+        path,
+        new_experience,
+        &auth.id,
+        &[Reference::new("tech_stack", tech_stack_db.as_ref())],
+    )
+    .await
 }
 
 #[delete("/{id}")]
-pub async fn delete_experience(db: Data<MongoDB<Experience>>, path: Path<String>) -> HttpResponse {
-    crud_controller::delete(db, path).await
+pub async fn delete_experience(
+    db: Data<dyn TrashRepository<Experience>>,
+    auth: EditorUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::delete(db, req, path, &auth.id).await
+}
+
+#[get("/trash")]
+pub async fn get_trashed_experience(
+    db: Data<dyn TrashRepository<Experience>>,
+    _auth: EditorUser,
+    req: HttpRequest,
+    params: Query<Vec<(String, String)>>,
+) -> HttpResponse {
+    crud_controller::get_trash(db, req, params).await
+}
+
+#[post("/{id}/restore")]
+pub async fn restore_experience(
+    db: Data<dyn TrashRepository<Experience>>,
+    auth: EditorUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::restore(db, req, path, &auth.id).await
+}
+
+#[delete("/trash/{id}")]
+pub async fn purge_experience(
+    db: Data<dyn TrashRepository<Experience>>,
+    admin: AdminUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::purge(db, req, path, &admin.id).await
+}
+
+#[get("/{id}/revisions")]
+pub async fn get_experience_revisions(
+    db: Data<dyn Repository<Experience>>,
+    revisions: Data<dyn Repository<Revision<Experience>>>,
+    _auth: EditorUser,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::get_revisions(db, revisions, path).await
+}
+
+#[get("/{id}/revisions/{n}")]
+pub async fn get_experience_revision(
+    db: Data<dyn Repository<Experience>>,
+    revisions: Data<dyn Repository<Revision<Experience>>>,
+    _auth: EditorUser,
+    path: Path<(String, i64)>,
+) -> HttpResponse {
+    crud_controller::get_revision(db, revisions, path).await
+}
+
+#[get("/{id}/revisions/{n}/diff")]
+pub async fn diff_experience_revision(
+    db: Data<dyn Repository<Experience>>,
+    revisions: Data<dyn Repository<Revision<Experience>>>,
+    _auth: EditorUser,
+    path: Path<(String, i64)>,
+    query: Query<RevisionDiffQuery>,
+) -> HttpResponse {
+    crud_controller::diff_revision(db, revisions, path, query).await
+}
+
+#[post("/{id}/revisions/{n}/restore")]
+pub async fn restore_experience_revision(
+    db: Data<dyn Repository<Experience>>,
+    revisions: Data<dyn Repository<Revision<Experience>>>,
+    tech_stack_db: Data<dyn Repository<TechStack>>,
+    auth: EditorUser,
+    req: HttpRequest,
+    path: Path<(String, i64)>,
+) -> HttpResponse {
+    // The restored dates have to make a period an update would accept.
+    let (id, n) = path.as_ref();
+    let restored = match crud_controller::revision(revisions.as_ref(), id, *n).await {
+        Ok(mut revision) => match revision.remove("record") {
+            Some(Bson::Document(record)) => from_document::<ExperienceUpdate>(record),
+            _ => return HttpResponse::InternalServerError().json("Revision has no record"),
+        },
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    let errors = match restored {
+        Ok(restored) => validate_period(restored.start.as_ref(), restored.end.flatten().as_ref()),
+        Err(err) => return HttpResponse::UnprocessableEntity().json(err.to_string()),
+    };
+    if !errors.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(errors);
+    }
+    crud_controller::restore_revision(
+        db,
+        revisions,
+        req,
+        path,
+        &auth.id,
+        &[Reference::new("tech_stack", tech_stack_db.as_ref())],
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        model::{token_model::RevokedToken, user_model::Role},
+        repository::memory_repo::Memory,
+        service::token_service,
+    };
+    use actix_web::{
+        http::header,
+        test::{self, TestRequest},
+        App,
+    };
+    use mongodb::bson::{doc, Document};
+    use std::sync::Arc;
+
+    #[actix_web::test]
+    async fn updated_dates_have_to_fit_the_stored_ones() {
+        let db: Arc<dyn Repository<Experience>> =
+            Arc::new(Memory::<Experience>::init("Experience"));
+        let experience = doc! {
+            "role": "Developer",
+            "company": "X",
+            "description": "",
+            "start": "2021-03",
+            "end": null,
+            "tech_stack": [],
+        };
+        let id = db
+            .create_record(from_document(experience).unwrap(), None)
+            .await
+            .unwrap()
+            .inserted_id;
+        let tech_stack_db: Arc<dyn Repository<TechStack>> =
+            Arc::new(Memory::<TechStack>::init("TechStack"));
+        let revoked_db: Arc<dyn Repository<RevokedToken>> =
+            Arc::new(Memory::<RevokedToken>::init("RevokedToken"));
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(db))
+                .app_data(Data::from(tech_stack_db))
+                .app_data(Data::from(revoked_db))
+                .service(new()),
+        )
+        .await;
+        let token = token_service::test_access_token("6ad4abb3557298608d132501", Role::Editor);
+        let update = |id: &str, body: Document| {
+            TestRequest::put()
+                .uri(&format!("/experiences/{}", id))
+                .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+                .set_json(body)
+                .to_request()
+        };
+
+        for (id, body, status) in [
+            (
+                id.as_str(),
+                doc! {"end": "2021-02"},
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                id.as_str(),
+                doc! {"start": "2021-05", "end": "2021-04"},
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                "not-an-id",
+                doc! {"end": "2021-02"},
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                "6ad4abb3557298608d132500",
+                doc! {"end": "2021-02"},
+                StatusCode::NOT_FOUND,
+            ),
+            (id.as_str(), doc! {"end": "2021-04"}, StatusCode::OK),
+        ] {
+            let response = test::call_service(&app, update(id, body.clone())).await;
+            assert_eq!(response.status(), status, "{} {}", id, body);
+        }
+    }
 }
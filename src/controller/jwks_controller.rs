@@ -0,0 +1,14 @@
+use crate::service::key_service;
+use actix_web::{get, web, HttpResponse, HttpResponseBuilder, Scope};
+
+pub fn new() -> Scope {
+    web::scope("/.well-known").service(jwks)
+}
+
+#[get("/jwks.json")]
+pub async fn jwks() -> HttpResponse {
+    match key_service::keys() {
+        Ok(keys) => HttpResponse::Ok().json(keys.jwks()),
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
@@ -1,6 +1,10 @@
+pub mod api_key_controller;
+pub mod audit_controller;
 pub mod crud_controller;
 pub mod detail_controller;
 pub mod experience_controller;
+pub mod jwks_controller;
+pub mod oidc_controller;
 pub mod project_controller;
 pub mod tech_stack_controller;
 pub mod user_controller;
@@ -0,0 +1,470 @@
+use crate::{
+    controller::user_controller,
+    model::{
+        oidc_model::{OidcCallback, OidcLogin},
+        token_model::RefreshToken,
+        user_model::User,
+    },
+    repository::Repository,
+    service::{oidc_service::OidcProvider, token_service, validation_service::normalize_email},
+};
+use actix_web::{
+    get,
+    http::{header, StatusCode},
+    web::{self, Data, Query},
+    HttpResponse, HttpResponseBuilder, Scope,
+};
+use log::warn;
+use mongodb::bson::{doc, DateTime};
+
+pub fn new() -> Scope {
+    web::scope("/oidc").service(login).service(callback)
+}
+
+fn not_configured() -> HttpResponse {
+    HttpResponse::NotFound().json("OIDC login is not configured")
+}
+
+#[get("/login")]
+pub async fn login(
+    provider: Data<Option<OidcProvider>>,
+    login_db: Data<dyn Repository<OidcLogin>>,
+) -> HttpResponse {
+    let provider = match provider.as_ref() {
+        Some(provider) => provider,
+        None => return not_configured(),
+    };
+    let state = token_service::random_token();
+    let nonce = token_service::random_token();
+    let code_verifier = token_service::random_token();
+
+    let url = match provider
+        .authorization_url(&state, &nonce, &code_verifier)
+        .await
+    {
+        Ok(url) => url,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+
+    let expires_at = (token_service::now() + provider.login_lifetime) * 1000;
+    let pending = OidcLogin {
+        _id: None,
+        state_hash: token_service::hash_token(&state),
+        nonce,
+        code_verifier,
+        expires_at: DateTime::from_millis(expires_at as i64),
+    };
+    if let Err((status_code, err)) = login_db.create_record(pending, None).await {
+        return HttpResponseBuilder::new(status_code).json(err);
+    }
+
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, url))
+        .finish()
+}
+
+#[get("/callback")]
+pub async fn callback(
+    provider: Data<Option<OidcProvider>>,
+    login_db: Data<dyn Repository<OidcLogin>>,
+    db: Data<dyn Repository<User>>,
+    refresh_db: Data<dyn Repository<RefreshToken>>,
+    query: Query<OidcCallback>,
+) -> HttpResponse {
+    let provider = match provider.as_ref() {
+        Some(provider) => provider,
+        None => return not_configured(),
+    };
+
+    // Use up the state first so a callback can never be replayed.
+    let state_hash = token_service::hash_token(&query.state);
+    let pending = match login_db
+        .find_one_record(doc! {"state_hash": state_hash})
+        .await
+    {
+        Ok(pending) => pending,
+        Err((StatusCode::NOT_FOUND, _)) => {
+            return HttpResponse::BadRequest().json("Invalid OIDC state")
+        }
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    let login_id = match pending._id {
+        Some(id) => id.to_string(),
+        None => {
+            return HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .json("OIDC login ID does not exist.")
+        }
+    };
+    match login_db.delete_record(&login_id).await {
+        Ok(res) if res.deleted_count == 1 => {}
+        Ok(_) => return HttpResponse::BadRequest().json("Invalid OIDC state"),
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    }
+    if pending.expires_at.timestamp_millis() <= (token_service::now() * 1000) as i64 {
+        return HttpResponse::BadRequest().json("OIDC login expired");
+    }
+
+    if let Some(error) = &query.error {
+        let description = query.error_description.as_deref().unwrap_or(error);
+        return HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json(format!(
+            "Identity provider denied the login: {}",
+            description
+        ));
+    }
+    let code = match &query.code {
+        Some(code) => code,
+        None => return HttpResponse::BadRequest().json("Missing authorization code"),
+    };
+
+    let id_token = match provider.exchange_code(code, &pending.code_verifier).await {
+        Ok(id_token) => id_token,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    let claims = match provider.verify_id_token(&id_token, &pending.nonce).await {
+        Ok(claims) => claims,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+
+    // Without the claim the provider hasn't vouched for the address.
+    let email = match (claims.email, claims.email_verified) {
+        (Some(email), Some(true)) => normalize_email(&email),
+        (Some(_), _) => {
+            return HttpResponse::Forbidden()
+                .json("Email address has not been verified by the identity provider")
+        }
+        (None, _) => {
+            return HttpResponse::Forbidden()
+                .json("Identity provider did not share an email address")
+        }
+    };
+    // Only existing users can log in; accounts are still created by invitation.
+    let user = match db.find_one_record(doc! {"email": &email}).await {
+        Ok(user) => user,
+        Err((StatusCode::NOT_FOUND, _)) => {
+            return HttpResponse::Forbidden().json("No user with this email address")
+        }
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    let id = match user._id {
+        Some(id) => id.to_string(),
+        None => {
+            return HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .json("User ID does not exist.")
+        }
+    };
+    // The provider only stands in for the password, not for the lockout or
+    // the second factor.
+    if let Some(response) = user_controller::locked_out(&user) {
+        return response;
+    }
+
+    // The provider vouches for the address.
+    if !user.email_verified {
+        if let Err((_, err)) = db
+            .update_record(&id, doc! {"email_verified": true}, Some(&id))
+            .await
+        {
+            warn!("Failed to mark email of user {} as verified: {}", id, err);
+        }
+    }
+
+    user_controller::complete_login(refresh_db.as_ref(), &user, &id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        repository::memory_repo::Memory,
+        service::{key_service, oidc_service::code_challenge},
+    };
+    use actix_web::{
+        test::{self, TestRequest},
+        web::Form,
+        App, HttpServer,
+    };
+    use data_encoding::BASE64URL_NOPAD;
+    use ed25519_dalek::{pkcs8::DecodePrivateKey, SigningKey};
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use mongodb::bson::{from_document, Document};
+    use reqwest::Url;
+    use serde::Deserialize;
+    use std::{collections::HashMap, net::TcpListener, sync::Arc, sync::Mutex};
+
+    const CLIENT_ID: &str = "ava";
+
+    /// What the user agreed to at the provider, redeemable once for an
+    /// `id_token`.
+    struct Grant {
+        code_challenge: String,
+        nonce: String,
+        email: &'static str,
+        email_verified: Option<bool>,
+    }
+
+    /// An identity provider serving discovery, its keys and the token
+    /// endpoint; the authorization step is up to the test.
+    struct Idp {
+        issuer: String,
+        key: EncodingKey,
+        jwk: Document,
+        grants: Mutex<HashMap<String, Grant>>,
+    }
+
+    impl Idp {
+        fn grant(&self, code: &str, grant: Grant) {
+            self.grants.lock().unwrap().insert(code.to_owned(), grant);
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct TokenRequest {
+        code: String,
+        code_verifier: String,
+        client_id: String,
+    }
+
+    async fn discovery(idp: Data<Idp>) -> HttpResponse {
+        HttpResponse::Ok().json(doc! {
+            "issuer": &idp.issuer,
+            "authorization_endpoint": format!("{}/authorize", idp.issuer),
+            "token_endpoint": format!("{}/token", idp.issuer),
+            "jwks_uri": format!("{}/jwks", idp.issuer),
+        })
+    }
+
+    async fn jwks(idp: Data<Idp>) -> HttpResponse {
+        HttpResponse::Ok().json(doc! {"keys": [&idp.jwk]})
+    }
+
+    async fn token(idp: Data<Idp>, form: Form<TokenRequest>) -> HttpResponse {
+        let grant = idp.grants.lock().unwrap().remove(&form.code);
+        let grant = match grant {
+            Some(grant)
+                if grant.code_challenge == code_challenge(&form.code_verifier)
+                    && form.client_id == CLIENT_ID =>
+            {
+                grant
+            }
+            _ => return HttpResponse::BadRequest().json(doc! {"error": "invalid_grant"}),
+        };
+        let mut claims = doc! {
+            "iss": &idp.issuer,
+            "aud": CLIENT_ID,
+            "sub": "idp-user",
+            "exp": token_service::now() as i64 + 300,
+            "nonce": grant.nonce,
+            "email": grant.email,
+        };
+        if let Some(email_verified) = grant.email_verified {
+            claims.insert("email_verified", email_verified);
+        }
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some("idp".to_owned());
+
+        HttpResponse::Ok().json(doc! {
+            "id_token": encode(&header, &claims, &idp.key).unwrap(),
+            "token_type": "Bearer",
+        })
+    }
+
+    fn start_idp() -> Data<Idp> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let pem = key_service::generate_test_key();
+        let public_key = SigningKey::from_pkcs8_pem(&pem).unwrap().verifying_key();
+        let idp = Data::new(Idp {
+            issuer: format!("http://{}", listener.local_addr().unwrap()),
+            key: EncodingKey::from_ed_pem(pem.as_bytes()).unwrap(),
+            jwk: doc! {
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "x": BASE64URL_NOPAD.encode(public_key.as_bytes()),
+                "kid": "idp",
+                "alg": "EdDSA",
+                "use": "sig",
+            },
+            grants: Mutex::new(HashMap::new()),
+        });
+
+        let data = idp.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .route(
+                    "/.well-known/openid-configuration",
+                    web::get().to(discovery),
+                )
+                .route("/jwks", web::get().to(jwks))
+                .route("/token", web::post().to(token))
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        actix_web::rt::spawn(server);
+
+        idp
+    }
+
+    async fn users() -> Arc<dyn Repository<User>> {
+        let db: Arc<dyn Repository<User>> = Arc::new(Memory::<User>::init("User"));
+        let locked_until = DateTime::from_millis(DateTime::now().timestamp_millis() + 60_000);
+        for user in [
+            doc! {"email": "a@x.io", "password": "unused", "email_verified": true},
+            doc! {"email": "locked@x.io", "password": "unused", "locked_until": locked_until},
+            doc! {"email": "totp@x.io", "password": "unused", "totp_enabled": true, "totp_secret": "GEZDGNBVGY3TQOJQ"},
+        ] {
+            db.create_record(from_document(user).unwrap(), None)
+                .await
+                .unwrap();
+        }
+        db
+    }
+
+    macro_rules! init_app {
+        ($idp:expr) => {{
+            key_service::init_test_keys();
+            let provider = OidcProvider::new(
+                $idp.issuer.clone(),
+                CLIENT_ID.to_owned(),
+                None,
+                "http://localhost/callback".to_owned(),
+            );
+            let login_db: Arc<dyn Repository<OidcLogin>> =
+                Arc::new(Memory::<OidcLogin>::init("OidcLogin"));
+            let refresh_db: Arc<dyn Repository<RefreshToken>> =
+                Arc::new(Memory::<RefreshToken>::init("RefreshToken"));
+            test::init_service(
+                App::new()
+                    .app_data(Data::new(Some(provider)))
+                    .app_data(Data::from(login_db))
+                    .app_data(Data::from(users().await))
+                    .app_data(Data::from(refresh_db))
+                    .service(new()),
+            )
+            .await
+        }};
+    }
+
+    /// Starts a login, returning the `state`, `nonce` and PKCE challenge the
+    /// app sends the user to the provider with.
+    macro_rules! login {
+        ($app:expr) => {{
+            let request = TestRequest::get().uri("/oidc/login").to_request();
+            let response = test::call_service(&$app, request).await;
+            assert_eq!(response.status(), StatusCode::FOUND);
+            let location = response.headers().get(header::LOCATION).unwrap();
+            let url = Url::parse(location.to_str().unwrap()).unwrap();
+            let param = |name: &str| {
+                url.query_pairs()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.into_owned())
+                    .unwrap()
+            };
+            (param("state"), param("nonce"), param("code_challenge"))
+        }};
+    }
+
+    macro_rules! callback {
+        ($app:expr, $state:expr, $code:expr) => {{
+            let uri = format!("/oidc/callback?state={}&code={}", $state, $code);
+            test::call_service(&$app, TestRequest::get().uri(&uri).to_request()).await
+        }};
+    }
+
+    #[actix_web::test]
+    async fn callback_checks_state_pkce_and_nonce() {
+        let idp = start_idp();
+        let app = init_app!(idp);
+
+        let (state, nonce, code_challenge) = login!(app);
+        idp.grant(
+            "code",
+            Grant {
+                code_challenge,
+                nonce,
+                email: "A@x.io",
+                email_verified: Some(true),
+            },
+        );
+        let response = callback!(app, state, "code");
+        assert_eq!(response.status(), StatusCode::OK);
+        let tokens: Document = test::read_body_json(response).await;
+        assert!(tokens.contains_key("access_token"));
+
+        // The state is used up by the first callback.
+        let response = callback!(app, state, "code");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = callback!(app, "unknown", "code");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // A code issued for another login's challenge fails the PKCE check.
+        let (_, _, other_challenge) = login!(app);
+        let (state, nonce, _) = login!(app);
+        idp.grant(
+            "stolen",
+            Grant {
+                code_challenge: other_challenge,
+                nonce,
+                email: "a@x.io",
+                email_verified: Some(true),
+            },
+        );
+        let response = callback!(app, state, "stolen");
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+
+        // An ID token minted for another login's nonce is refused.
+        let (state, _, code_challenge) = login!(app);
+        idp.grant(
+            "mismatched",
+            Grant {
+                code_challenge,
+                nonce: "another login".to_owned(),
+                email: "a@x.io",
+                email_verified: Some(true),
+            },
+        );
+        let response = callback!(app, state, "mismatched");
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn callback_applies_login_gates() {
+        let idp = start_idp();
+        let app = init_app!(idp);
+        let complete = |email, email_verified| {
+            let idp = idp.clone();
+            let app = &app;
+            async move {
+                let (state, nonce, code_challenge) = login!(app);
+                idp.grant(
+                    &state,
+                    Grant {
+                        code_challenge,
+                        nonce,
+                        email,
+                        email_verified,
+                    },
+                );
+                // Any unique value will do as the code.
+                callback!(app, state, state)
+            }
+        };
+
+        let response = complete("a@x.io", None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = complete("a@x.io", Some(false)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = complete("nobody@x.io", Some(true)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = complete("locked@x.io", Some(true)).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let response = complete("totp@x.io", Some(true)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Document = test::read_body_json(response).await;
+        assert!(body.contains_key("challenge"));
+        assert!(!body.contains_key("access_token"));
+    }
+}
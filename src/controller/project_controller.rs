@@ -1,26 +1,43 @@
 use crate::{
     controller::crud_controller,
-    model::project_model::{Project, ProjectUpdate},
-    repository::mongodb_repo::MongoDB,
+    extractor::auth_extractor::{AdminUser, EditorUser},
+    model::{
+        metadata_model::Metadata,
+        project_model::{Project, ProjectUpdate},
+        revision_model::{Revision, RevisionDiffQuery},
+        tech_stack_model::TechStack,
+    },
+    repository::{Repository, TrashRepository},
+    service::reference_service::Reference,
 };
 use actix_web::{
     delete, get, post, put,
-    web::{self, Data, Json, Path},
-    HttpResponse, Scope,
+    web::{self, Data, Json, Path, Query},
+    HttpRequest, HttpResponse, Scope,
 };
 
 pub fn new() -> Scope {
     web::scope("/projects")
         .service(create_project)
         .service(get_all_project)
+        .service(get_trashed_project)
         .service(get_project)
         .service(update_project)
         .service(delete_project)
+        .service(restore_project)
+        .service(purge_project)
+        .service(get_project_revisions)
+        .service(get_project_revision)
+        .service(diff_project_revision)
+        .service(restore_project_revision)
 }
 
 #[post("")]
 pub async fn create_project(
-    db: Data<MongoDB<Project>>,
+    db: Data<dyn Repository<Project>>,
+    tech_stack_db: Data<dyn Repository<TechStack>>,
+    auth: EditorUser,
+    req: HttpRequest,
     new_project: Json<Project>,
 ) -> HttpResponse {
     let data = Project {
@@ -30,30 +47,157 @@ pub async fn create_project(
         repo: new_project.repo.to_owned(),
         url: new_project.url.to_owned(),
         tech_stack: new_project.tech_stack.to_owned(),
+        metadata: Metadata::default(),
     };
-    crud_controller::create(db, data).await
+    crud_controller::create(
+        db,
+        req,
+        data,
+        Some(&auth.id),
+        &[Reference::new("tech_stack", tech_stack_db.as_ref())],
+    )
+    .await
 }
 
 #[get("")]
-pub async fn get_all_project(db: Data<MongoDB<Project>>) -> HttpResponse {
-    crud_controller::get_all(db).await
+pub async fn get_all_project(
+    db: Data<dyn Repository<Project>>,
+    tech_stack_db: Data<dyn Repository<TechStack>>,
+    req: HttpRequest,
+    params: Query<Vec<(String, String)>>,
+) -> HttpResponse {
+    crud_controller::get_all(
+        db,
+        req,
+        params,
+        &[Reference::new("tech_stack", tech_stack_db.as_ref())],
+    )
+    .await
 }
 
 #[get("/{id}")]
-pub async fn get_project(db: Data<MongoDB<Project>>, path: Path<String>) -> HttpResponse {
-    crud_controller::get(db, path).await
+pub async fn get_project(
+    db: Data<dyn Repository<Project>>,
+    tech_stack_db: Data<dyn Repository<TechStack>>,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::get(
+        db,
+        req,
+        path,
+        &[Reference::new("tech_stack", tech_stack_db.as_ref())],
+    )
+    .await
 }
 
 #[put("/{id}")]
 pub async fn update_project(
-    db: Data<MongoDB<Project>>,
+    db: Data<dyn Repository<Project>>,
+    tech_stack_db: Data<dyn Repository<TechStack>>,
+    auth: EditorUser,
+    req: HttpRequest,
     path: Path<String>,
     new_project: Json<ProjectUpdate>,
 ) -> HttpResponse {
-    crud_controller::update(db, path, new_project).await
+    crud_controller::update(
+        db,
+        req,
+        path,
+        new_project,
+        &auth.id,
+        &[Reference::new("tech_stack", tech_stack_db.as_ref())],
+    )
+    .await
 }
 
 #[delete("/{id}")]
-pub async fn delete_project(db: Data<MongoDB<Project>>, path: Path<String>) -> HttpResponse {
-    crud_controller::delete(db, path).await
+pub async fn delete_project(
+    db: Data<dyn TrashRepository<Project>>,
+    auth: EditorUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::delete(db, req, path, &auth.id).await
+}
+
+#[get("/trash")]
+pub async fn get_trashed_project(
+    db: Data<dyn TrashRepository<Project>>,
+    _auth: EditorUser,
+    req: HttpRequest,
+    params: Query<Vec<(String, String)>>,
+) -> HttpResponse {
+    crud_controller::get_trash(db, req, params).await
+}
+
+#[post("/{id}/restore")]
+pub async fn restore_project(
+    db: Data<dyn TrashRepository<Project>>,
+    auth: EditorUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::restore(db, req, path, &auth.id).await
+}
+
+#[delete("/trash/{id}")]
+pub async fn purge_project(
+    db: Data<dyn TrashRepository<Project>>,
+    admin: AdminUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::purge(db, req, path, &admin.id).await
+}
+
+#[get("/{id}/revisions")]
+pub async fn get_project_revisions(
+    db: Data<dyn Repository<Project>>,
+    revisions: Data<dyn Repository<Revision<Project>>>,
+    _auth: EditorUser,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::get_revisions(db, revisions, path).await
+}
+
+#[get("/{id}/revisions/{n}")]
+pub async fn get_project_revision(
+    db: Data<dyn Repository<Project>>,
+    revisions: Data<dyn Repository<Revision<Project>>>,
+    _auth: EditorUser,
+    path: Path<(String, i64)>,
+) -> HttpResponse {
+    crud_controller::get_revision(db, revisions, path).await
+}
+
+#[get("/{id}/revisions/{n}/diff")]
+pub async fn diff_project_revision(
+    db: Data<dyn Repository<Project>>,
+    revisions: Data<dyn Repository<Revision<Project>>>,
+    _auth: EditorUser,
+    path: Path<(String, i64)>,
+    query: Query<RevisionDiffQuery>,
+) -> HttpResponse {
+    crud_controller::diff_revision(db, revisions, path, query).await
+}
+
+#[post("/{id}/revisions/{n}/restore")]
+pub async fn restore_project_revision(
+    db: Data<dyn Repository<Project>>,
+    revisions: Data<dyn Repository<Revision<Project>>>,
+    tech_stack_db: Data<dyn Repository<TechStack>>,
+    auth: EditorUser,
+    req: HttpRequest,
+    path: Path<(String, i64)>,
+) -> HttpResponse {
+    crud_controller::restore_revision(
+        db,
+        revisions,
+        req,
+        path,
+        &auth.id,
+        &[Reference::new("tech_stack", tech_stack_db.as_ref())],
+    )
+    .await
 }
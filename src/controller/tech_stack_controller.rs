@@ -1,56 +1,353 @@
 use crate::{
     controller::crud_controller,
-    model::tech_stack_model::{TechStack, TechStackUpdate},
-    repository::mongodb_repo::MongoDB,
+    extractor::auth_extractor::{AdminUser, EditorUser},
+    model::{
+        experience_model::Experience,
+        metadata_model::Metadata,
+        project_model::Project,
+        revision_model::{Revision, RevisionDiffQuery},
+        tech_stack_model::{TechStack, TechStackDelete, TechStackUpdate},
+    },
+    repository::{Repository, TrashRepository},
+    service::reference_service,
 };
 use actix_web::{
-    delete, get, post, put,
-    web::{self, Data, Json, Path},
-    HttpResponse, Scope,
+    delete, get,
+    http::StatusCode,
+    post, put,
+    web::{self, Data, Json, Path, Query},
+    HttpRequest, HttpResponse, HttpResponseBuilder, Scope,
 };
+use log::error;
 
 pub fn new() -> Scope {
     web::scope("/tech-stack")
         .service(create_tech_stack)
         .service(get_all_tech_stack)
+        .service(get_trashed_tech_stack)
         .service(get_tech_stack)
         .service(update_tech_stack)
         .service(delete_tech_stack)
+        .service(restore_tech_stack)
+        .service(purge_tech_stack)
+        .service(get_tech_stack_revisions)
+        .service(get_tech_stack_revision)
+        .service(diff_tech_stack_revision)
+        .service(restore_tech_stack_revision)
 }
 
 #[post("")]
 pub async fn create_tech_stack(
-    db: Data<MongoDB<TechStack>>,
+    db: Data<dyn Repository<TechStack>>,
+    auth: EditorUser,
+    req: HttpRequest,
     new_tech_stack: Json<TechStack>,
 ) -> HttpResponse {
     let data = TechStack {
         _id: None,
         name: new_tech_stack.name.to_owned(),
         category: new_tech_stack.category.to_owned(),
+        metadata: Metadata::default(),
     };
-    crud_controller::create(db, data).await
+    crud_controller::create(db, req, data, Some(&auth.id), &[]).await
 }
 
 #[get("")]
-pub async fn get_all_tech_stack(db: Data<MongoDB<TechStack>>) -> HttpResponse {
-    crud_controller::get_all(db).await
+pub async fn get_all_tech_stack(
+    db: Data<dyn Repository<TechStack>>,
+    req: HttpRequest,
+    params: Query<Vec<(String, String)>>,
+) -> HttpResponse {
+    crud_controller::get_all(db, req, params, &[]).await
 }
 
 #[get("/{id}")]
-pub async fn get_tech_stack(db: Data<MongoDB<TechStack>>, path: Path<String>) -> HttpResponse {
-    crud_controller::get(db, path).await
+pub async fn get_tech_stack(
+    db: Data<dyn Repository<TechStack>>,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::get(db, req, path, &[]).await
 }
 
 #[put("/{id}")]
 pub async fn update_tech_stack(
-    db: Data<MongoDB<TechStack>>,
+    db: Data<dyn Repository<TechStack>>,
+    auth: EditorUser,
+    req: HttpRequest,
     path: Path<String>,
     new_tech_stack: Json<TechStackUpdate>,
 ) -> HttpResponse {
-    crud_controller::update(db, path, new_tech_stack).await
+    crud_controller::update(db, req, path, new_tech_stack, &auth.id, &[]).await
+}
+
+/// How many projects and experiences use the entry with `id`, trashed ones
+/// included.
+async fn usage(
+    project_db: &dyn TrashRepository<Project>,
+    experience_db: &dyn TrashRepository<Experience>,
+    id: &str,
+) -> Result<(u64, u64), (StatusCode, String)> {
+    Ok((
+        reference_service::usage(project_db, "tech_stack", id).await?,
+        reference_service::usage(experience_db, "tech_stack", id).await?,
+    ))
+}
+
+/// Removes the entry with `id` from every project and experience using it
+/// on behalf of `actor`.
+async fn detach(
+    req: &HttpRequest,
+    project_db: &dyn TrashRepository<Project>,
+    experience_db: &dyn TrashRepository<Experience>,
+    id: &str,
+    actor: &str,
+) -> Result<(), (StatusCode, String)> {
+    reference_service::detach(req, project_db, "tech_stack", id, actor).await?;
+    reference_service::detach(req, experience_db, "tech_stack", id, actor).await
 }
 
+fn in_use((projects, experiences): (u64, u64)) -> HttpResponse {
+    HttpResponse::Conflict().json(format!(
+        "Used by {} projects and {} experiences, trashed ones included, delete with cascade=true to remove it from them",
+        projects, experiences
+    ))
+}
+
+/// Moves the entry back out of the trash after its delete had to be undone,
+/// answering with `response` either way.
+async fn undo_delete(
+    db: Data<dyn TrashRepository<TechStack>>,
+    req: HttpRequest,
+    id: String,
+    actor: &str,
+    response: HttpResponse,
+) -> HttpResponse {
+    let restored = crud_controller::restore(db, req, Path::from(id.clone()), actor).await;
+    if !restored.status().is_success() {
+        error!(
+            "Failed to restore tech stack {} after undoing its delete",
+            id
+        );
+    }
+    response
+}
+
+/// Refuses to delete an entry projects or experiences use, trashed ones
+/// included, with `409 Conflict`, unless `cascade=true` asks to remove it
+/// from them as well. If that fails the entry is restored again. Restoring
+/// a deleted entry doesn't bring back the references removed from them.
 #[delete("/{id}")]
-pub async fn delete_tech_stack(db: Data<MongoDB<TechStack>>, path: Path<String>) -> HttpResponse {
-    crud_controller::delete(db, path).await
+pub async fn delete_tech_stack(
+    db: Data<dyn TrashRepository<TechStack>>,
+    project_db: Data<dyn TrashRepository<Project>>,
+    experience_db: Data<dyn TrashRepository<Experience>>,
+    auth: EditorUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    let cascade = Query::<TechStackDelete>::from_query(req.query_string())
+        .map(|query| query.cascade)
+        .unwrap_or_default();
+    if !cascade {
+        match usage(project_db.as_ref(), experience_db.as_ref(), &id).await {
+            Ok((0, 0)) => {}
+            Ok(usage) => return in_use(usage),
+            Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+        }
+    }
+
+    let response =
+        crud_controller::delete(db.clone(), req.clone(), Path::from(id.clone()), &auth.id).await;
+    if !response.status().is_success() {
+        return response;
+    }
+    let outcome = match cascade {
+        true => detach(
+            &req,
+            project_db.as_ref(),
+            experience_db.as_ref(),
+            &id,
+            &auth.id,
+        )
+        .await
+        .map(|()| None),
+        // Checked again, as the entry could have been picked in the meantime.
+        false => usage(project_db.as_ref(), experience_db.as_ref(), &id)
+            .await
+            .map(|usage| match usage {
+                (0, 0) => None,
+                usage => Some(in_use(usage)),
+            }),
+    };
+    match outcome {
+        Ok(None) => response,
+        Ok(Some(conflict)) => undo_delete(db, req, id, &auth.id, conflict).await,
+        Err((status_code, err)) => {
+            let failed = HttpResponseBuilder::new(status_code).json(err);
+            undo_delete(db, req, id, &auth.id, failed).await
+        }
+    }
+}
+
+#[get("/trash")]
+pub async fn get_trashed_tech_stack(
+    db: Data<dyn TrashRepository<TechStack>>,
+    _auth: EditorUser,
+    req: HttpRequest,
+    params: Query<Vec<(String, String)>>,
+) -> HttpResponse {
+    crud_controller::get_trash(db, req, params).await
+}
+
+/// Moves an entry out of the trash. References removed from projects and
+/// experiences when it was deleted with `cascade=true` stay removed.
+#[post("/{id}/restore")]
+pub async fn restore_tech_stack(
+    db: Data<dyn TrashRepository<TechStack>>,
+    auth: EditorUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::restore(db, req, path, &auth.id).await
+}
+
+#[delete("/trash/{id}")]
+pub async fn purge_tech_stack(
+    db: Data<dyn TrashRepository<TechStack>>,
+    admin: AdminUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::purge(db, req, path, &admin.id).await
+}
+
+#[get("/{id}/revisions")]
+pub async fn get_tech_stack_revisions(
+    db: Data<dyn Repository<TechStack>>,
+    revisions: Data<dyn Repository<Revision<TechStack>>>,
+    _auth: EditorUser,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::get_revisions(db, revisions, path).await
+}
+
+#[get("/{id}/revisions/{n}")]
+pub async fn get_tech_stack_revision(
+    db: Data<dyn Repository<TechStack>>,
+    revisions: Data<dyn Repository<Revision<TechStack>>>,
+    _auth: EditorUser,
+    path: Path<(String, i64)>,
+) -> HttpResponse {
+    crud_controller::get_revision(db, revisions, path).await
+}
+
+#[get("/{id}/revisions/{n}/diff")]
+pub async fn diff_tech_stack_revision(
+    db: Data<dyn Repository<TechStack>>,
+    revisions: Data<dyn Repository<Revision<TechStack>>>,
+    _auth: EditorUser,
+    path: Path<(String, i64)>,
+    query: Query<RevisionDiffQuery>,
+) -> HttpResponse {
+    crud_controller::diff_revision(db, revisions, path, query).await
+}
+
+#[post("/{id}/revisions/{n}/restore")]
+pub async fn restore_tech_stack_revision(
+    db: Data<dyn Repository<TechStack>>,
+    revisions: Data<dyn Repository<Revision<TechStack>>>,
+    auth: EditorUser,
+    req: HttpRequest,
+    path: Path<(String, i64)>,
+) -> HttpResponse {
+    crud_controller::restore_revision(db, revisions, req, path, &auth.id, &[]).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        model::{token_model::RevokedToken, user_model::Role},
+        repository::{memory_repo::Memory, trash_repo::Trash},
+        service::token_service,
+    };
+    use actix_web::{
+        http::header,
+        test::{self, TestRequest},
+        App,
+    };
+    use mongodb::bson::{doc, from_document};
+    use std::sync::Arc;
+
+    fn trash<T>(inner: Memory<T>) -> Arc<Trash<T>>
+    where
+        Memory<T>: Repository<T> + 'static,
+    {
+        Arc::new(Trash::init(Arc::new(inner)))
+    }
+
+    #[actix_web::test]
+    async fn deletes_used_entries_only_with_cascade() {
+        let db = trash(Memory::<TechStack>::init("TechStack"));
+        let project_db = trash(Memory::<Project>::init("Project"));
+        let experience_db = trash(Memory::<Experience>::init("Experience"));
+        let revoked_db: Arc<dyn Repository<RevokedToken>> =
+            Arc::new(Memory::<RevokedToken>::init("RevokedToken"));
+        let tech_stack = doc! {"name": "Rust", "category": "Language"};
+        let id = db
+            .create_record(from_document(tech_stack).unwrap(), None)
+            .await
+            .unwrap()
+            .inserted_id;
+        let project = doc! {
+            "name": "AVA",
+            "description": "",
+            "repo": "",
+            "url": "",
+            "tech_stack": [&id, "6ad4abb3557298608d132500"],
+        };
+        let project_id = project_db
+            .create_record(from_document(project).unwrap(), None)
+            .await
+            .unwrap()
+            .inserted_id;
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::from(db.clone() as Arc<dyn TrashRepository<TechStack>>))
+                .app_data(Data::from(
+                    project_db.clone() as Arc<dyn TrashRepository<Project>>
+                ))
+                .app_data(Data::from(
+                    experience_db as Arc<dyn TrashRepository<Experience>>,
+                ))
+                .app_data(Data::from(revoked_db))
+                .service(new()),
+        )
+        .await;
+        let token = token_service::test_access_token("6ad4abb3557298608d132501", Role::Editor);
+        let delete = |uri: String| {
+            TestRequest::delete()
+                .uri(&uri)
+                .insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+                .to_request()
+        };
+
+        let response = test::call_service(&app, delete(format!("/tech-stack/{}", id))).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert!(db.get_record(&id).await.is_ok());
+
+        let uri = format!("/tech-stack/{}?cascade=true", id);
+        let response = test::call_service(&app, delete(uri)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(db.trash().get_record(&id).await.is_ok());
+        let project = project_db.get_record(&project_id).await.unwrap();
+        assert_eq!(project.tech_stack, ["6ad4abb3557298608d132500"]);
+
+        // Restoring leaves the references removed.
+        db.restore_record(&id, None).await.unwrap();
+        let project = project_db.get_record(&project_id).await.unwrap();
+        assert_eq!(project.tech_stack, ["6ad4abb3557298608d132500"]);
+    }
 }
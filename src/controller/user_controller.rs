@@ -1,95 +1,408 @@
 use crate::{
-    controller::crud_controller,
-    model::user_model::{encrypt_password, verify_password, PasswordUpdate, User, UserUpdate},
-    repository::mongodb_repo::MongoDB,
+    controller::{crud_controller, oidc_controller},
+    extractor::auth_extractor::{AdminUser, AuthUser},
+    mailer::{Email, Mailer},
+    model::{
+        audit_model::AuditAction,
+        bootstrap_model::{Bootstrap, FIRST_ADMIN},
+        metadata_model::Metadata,
+        password_reset_model::{PasswordResetConfirm, PasswordResetRequest, PasswordResetToken},
+        token_model::{LogoutRequest, RedeemedToken, RefreshRequest, RefreshToken, RevokedToken},
+        totp_model::{RecoveryCodes, TotpChallenge, TotpCode, TotpEnrollment, TotpLogin},
+        user_model::{
+            encrypt_password, needs_rehash, verify_password, EmailVerification,
+            EmailVerificationRequest, Invitation, PasswordUpdate, Role, User, UserRegistration,
+            UserUpdate,
+        },
+    },
+    repository::{id_filter, Repository, TrashRepository},
+    service::{
+        audit_service,
+        config_service::env_or,
+        throttle_service::LoginThrottle,
+        token_service, totp_service,
+        validation_service::{normalize_email, validate_email, PasswordPolicy},
+    },
 };
 use actix_web::{
     delete, get,
-    http::StatusCode,
+    http::{header, StatusCode},
     post, put,
-    web::{self, Data, Json, Path},
-    HttpResponse, HttpResponseBuilder, Scope,
+    web::{self, Data, Json, Path, Query},
+    HttpRequest, HttpResponse, HttpResponseBuilder, Scope,
 };
-use dotenv;
-use hmac::{Hmac, Mac};
-use jwt::SignWithKey;
-use mongodb::bson::{doc, to_document};
-use sha2::Sha256;
-use std::collections::BTreeMap;
+use log::{error, warn};
+use mongodb::bson::{doc, to_document, DateTime, Document};
+use std::sync::{Arc, OnceLock};
+
+const DEFAULT_PASSWORD_RESET_LIFETIME: u64 = 60 * 60;
+const DEFAULT_INVITATION_LIFETIME: u64 = 7 * 24 * 60 * 60;
+const DEFAULT_EMAIL_VERIFICATION_LIFETIME: u64 = 2 * 24 * 60 * 60;
 
 pub fn new() -> Scope {
     web::scope("/users")
         .service(auth)
+        .service(auth_totp)
+        .service(refresh)
+        .service(logout)
+        .service(revoke_sessions)
         .service(update_password)
+        .service(request_password_reset)
+        .service(confirm_password_reset)
+        .service(invite)
+        .service(verify_email)
+        .service(resend_verification)
+        .service(oidc_controller::new())
+        .service(enroll_totp)
+        .service(confirm_totp)
+        .service(disable_totp)
+        .service(reset_totp)
         .service(create)
         .service(get_all)
+        .service(get_trash)
         .service(get)
         .service(update)
         .service(delete)
+        .service(restore)
+        .service(purge)
+}
+
+/// Builds the link sent by email from the base URL in `var`, or falls back to
+/// the bare token when it isn't configured.
+fn link(var: &str, token: &str) -> String {
+    match dotenv::var(var) {
+        Ok(url) => format!("{}?token={}", url, token),
+        Err(_) => token.to_owned(),
+    }
+}
+
+fn too_many_attempts(retry_after: u64) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+        .json("Too many login attempts, try again later")
+}
+
+/// The response refusing to log `user` in, by any means, while failed logins
+/// keep them locked out.
+pub(crate) fn locked_out(user: &User) -> Option<HttpResponse> {
+    let now = token_service::now();
+    let locked_until = (user.locked_until?.timestamp_millis() / 1000) as u64;
+    (locked_until > now).then(|| too_many_attempts(locked_until - now))
+}
+
+/// A hash of no one's password, checked against for unknown addresses.
+fn unknown_user_hash() -> Option<&'static str> {
+    static HASH: OnceLock<Option<String>> = OnceLock::new();
+    HASH.get_or_init(|| encrypt_password(&token_service::random_token()).ok())
+        .as_deref()
+}
+
+/// Finishes a login `user` passed the first factor of: a challenge for the
+/// second one if they enabled TOTP, their tokens otherwise.
+pub(crate) async fn complete_login(
+    refresh_db: &dyn Repository<RefreshToken>,
+    user: &User,
+    id: &str,
+) -> HttpResponse {
+    if user.totp_enabled {
+        return match token_service::sign_totp_challenge(id) {
+            Ok((challenge, expires_in)) => HttpResponse::Ok().json(TotpChallenge {
+                challenge,
+                expires_in,
+            }),
+            Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+        };
+    }
+
+    match token_service::issue_token_pair(refresh_db, user).await {
+        Ok(tokens) => HttpResponse::Ok().json(tokens),
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
 }
 
 #[post("/auth")]
-pub async fn auth(db: Data<MongoDB<User>>, credentials: Json<User>) -> HttpResponse {
-    let user = match db
-        .find_one_record(doc! {"email": credentials.email.clone()})
-        .await
-    {
+pub async fn auth(
+    req: HttpRequest,
+    db: Data<dyn Repository<User>>,
+    refresh_db: Data<dyn Repository<RefreshToken>>,
+    throttle: Data<LoginThrottle>,
+    credentials: Json<User>,
+) -> HttpResponse {
+    let email = normalize_email(&credentials.email);
+    let mut keys = vec![format!("email:{}", email)];
+    if let Some(addr) = req.peer_addr() {
+        keys.push(format!("ip:{}", addr.ip()));
+    }
+    if let Some(retry_after) = throttle.retry_after(&keys) {
+        return too_many_attempts(retry_after);
+    }
+
+    let user = match db.find_one_record(doc! {"email": &email}).await {
         Ok(user) => user,
+        Err((StatusCode::NOT_FOUND, _)) => {
+            // Takes as long as checking the password of an account would.
+            if let Some(hash) = unknown_user_hash() {
+                let _ = verify_password(&credentials.password, hash);
+            }
+            throttle.record_failure(&keys);
+            return HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json("Invalid credentials");
+        }
         Err((status_code, message)) => return HttpResponseBuilder::new(status_code).json(message),
     };
-
-    let is_verified = match verify_password(&credentials.password, &user.password) {
-        Ok(val) => val,
-        Err(error) => {
+    let (user_id, id) = match user._id {
+        Some(user_id) => (user_id, user_id.to_string()),
+        None => {
             return HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
-                .json(error.to_string())
+                .json("User ID does not exist.")
         }
     };
 
+    // A hash that can't be checked, like one naming a pepper no longer
+    // configured, must not lock the user out with a server error.
+    let is_verified =
+        verify_password(&credentials.password, &user.password).unwrap_or_else(|error| {
+            error!("Cannot verify password hash of user {}: {}", id, error);
+            false
+        });
+
+    // Only the right password learns that the account is locked, so the
+    // lockout doesn't tell which addresses have one.
+    let locked_out = locked_out(&user);
     if !is_verified {
+        throttle.record_failure(&keys);
+        if locked_out.is_some() {
+            return HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json("Invalid credentials");
+        }
+        let failed_logins = user.failed_logins + 1;
+        let doc = if failed_logins >= throttle.max_attempts {
+            let now = token_service::now();
+            let locked_until = DateTime::from_millis(((now + throttle.lockout) * 1000) as i64);
+            doc! {"failed_logins": 0, "locked_until": locked_until}
+        } else {
+            doc! {"failed_logins": failed_logins}
+        };
+        if let Err((status_code, err)) = db.update_unstamped(doc! {"_id": user_id}, doc).await {
+            return HttpResponseBuilder::new(status_code).json(err);
+        }
         return HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json("Invalid credentials");
     }
+    if let Some(response) = locked_out {
+        return response;
+    }
 
-    let secret = match dotenv::var("JWT_SECRET") {
-        Ok(secret) => secret,
-        Err(error) => {
-            return HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
-                .json(error.to_string())
+    throttle.record_success(&keys);
+    if user.failed_logins > 0 || user.locked_until.is_some() {
+        let doc = doc! {"failed_logins": 0, "locked_until": null};
+        if let Err((status_code, err)) = db.update_unstamped(doc! {"_id": user_id}, doc).await {
+            return HttpResponseBuilder::new(status_code).json(err);
+        }
+    }
+
+    // Upgrade hashes made with older Argon2 settings while the plain password
+    // is at hand; a failure here must not prevent the login.
+    if needs_rehash(&user.password).unwrap_or(false) {
+        match encrypt_password(&credentials.password) {
+            Ok(password) => {
+                if let Err((_, err)) = db
+                    .update_unstamped(doc! {"_id": user_id}, doc! {"password": password})
+                    .await
+                {
+                    warn!("Failed to upgrade password hash of user {}: {}", id, err);
+                }
+            }
+            Err(error) => warn!("Failed to upgrade password hash of user {}: {}", id, error),
         }
+    }
+
+    if !user.email_verified {
+        return HttpResponse::Forbidden().json("Email address has not been verified");
+    }
+
+    complete_login(refresh_db.as_ref(), &user, &id).await
+}
+
+/// Checks a TOTP code, or failing that a recovery code, for a user with TOTP
+/// enabled, consuming whichever was used.
+async fn verify_second_factor(
+    db: &dyn Repository<User>,
+    id: &str,
+    user: &User,
+    code: &str,
+) -> Result<bool, (StatusCode, String)> {
+    let secret = match (&user.totp_secret, user.totp_enabled) {
+        (Some(secret), true) => secret,
+        _ => return Err((StatusCode::BAD_REQUEST, "TOTP is not enabled".to_owned())),
     };
 
-    let key: Hmac<Sha256> = match Hmac::new_from_slice(secret.as_bytes()) {
-        Ok(key) => key,
-        Err(error) => {
+    let last_step = user.totp_last_step.map(|step| step as u64);
+    if let Some(step) = totp_service::verify(secret, code, last_step) {
+        db.update_unstamped(id_filter(id)?, doc! {"totp_last_step": step as i64})
+            .await?;
+        return Ok(true);
+    }
+
+    let code_hash = token_service::hash_token(code.trim());
+    if user.recovery_codes.contains(&code_hash) {
+        let recovery_codes: Vec<&String> = user
+            .recovery_codes
+            .iter()
+            .filter(|recovery_code| **recovery_code != code_hash)
+            .collect();
+        db.update_unstamped(id_filter(id)?, doc! {"recovery_codes": recovery_codes})
+            .await?;
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+#[post("/auth/totp")]
+pub async fn auth_totp(
+    db: Data<dyn Repository<User>>,
+    refresh_db: Data<dyn Repository<RefreshToken>>,
+    throttle: Data<LoginThrottle>,
+    request: Json<TotpLogin>,
+) -> HttpResponse {
+    let id = match token_service::verify_totp_challenge(&request.challenge) {
+        Ok(id) => id,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    let keys = vec![format!("totp:{}", id)];
+    if let Some(retry_after) = throttle.retry_after(&keys) {
+        return too_many_attempts(retry_after);
+    }
+
+    let user = match db.get_record(&id).await {
+        Ok(user) => user,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+
+    match verify_second_factor(db.as_ref(), &id, &user, &request.code).await {
+        Ok(true) => throttle.record_success(&keys),
+        Ok(false) => {
+            throttle.record_failure(&keys);
+            return HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json("Invalid code");
+        }
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    }
+
+    match token_service::issue_token_pair(refresh_db.as_ref(), &user).await {
+        Ok(tokens) => HttpResponse::Ok().json(tokens),
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+#[post("/auth/refresh")]
+pub async fn refresh(
+    db: Data<dyn Repository<User>>,
+    refresh_db: Data<dyn Repository<RefreshToken>>,
+    request: Json<RefreshRequest>,
+) -> HttpResponse {
+    let token_hash = token_service::hash_token(&request.refresh_token);
+    let refresh_token = match refresh_db
+        .find_one_record(doc! {"token_hash": token_hash})
+        .await
+    {
+        Ok(refresh_token) => refresh_token,
+        Err((StatusCode::NOT_FOUND, _)) => {
+            return HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json("Invalid refresh token")
+        }
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+
+    let refresh_id = match refresh_token._id {
+        Some(id) => id.to_string(),
+        None => {
             return HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
-                .json(error.to_string())
+                .json("Refresh token ID does not exist.")
         }
     };
 
-    let mut claims = BTreeMap::new();
-    claims.insert("email", user.email);
-    claims.insert(
-        "id",
-        match user._id {
-            Some(id) => id.to_string(),
-            None => {
-                return HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
-                    .json("User ID does not exist.")
-            }
-        },
-    );
+    // Refresh tokens are single use: whoever deletes it first gets to rotate it.
+    match refresh_db.delete_record(&refresh_id).await {
+        Ok(res) if res.deleted_count == 1 => {}
+        Ok(_) => {
+            return HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json("Invalid refresh token")
+        }
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    }
 
-    match claims.sign_with_key(&key) {
-        Ok(token_str) => HttpResponse::Ok().json(token_str),
-        Err(error) => {
-            HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR).json(error.to_string())
+    if refresh_token.expires_at.timestamp_millis() <= (token_service::now() * 1000) as i64 {
+        return HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json("Refresh token expired");
+    }
+
+    let user = match db.get_record(&refresh_token.user_id).await {
+        Ok(user) => user,
+        Err((StatusCode::NOT_FOUND, _)) => {
+            return HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json("Invalid refresh token")
+        }
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+
+    match token_service::issue_token_pair(refresh_db.as_ref(), &user).await {
+        Ok(tokens) => HttpResponse::Ok().json(tokens),
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+#[post("/auth/logout")]
+pub async fn logout(
+    db: Data<dyn Repository<User>>,
+    refresh_db: Data<dyn Repository<RefreshToken>>,
+    revoked_db: Data<dyn Repository<RevokedToken>>,
+    auth_user: AuthUser,
+    req: HttpRequest,
+    request: Option<Json<LogoutRequest>>,
+) -> HttpResponse {
+    let session = match auth_user.session {
+        Some(session) => session,
+        None => return HttpResponse::BadRequest().json("Only bearer tokens can be logged out"),
+    };
+
+    if let Some(refresh_token) = request.and_then(|request| request.into_inner().refresh_token) {
+        let filter = doc! {
+            "token_hash": token_service::hash_token(&refresh_token),
+            "user_id": &auth_user.id,
+        };
+        if let Err((status_code, err)) = refresh_db.delete_many_records(filter).await {
+            return HttpResponseBuilder::new(status_code).json(err);
         }
     }
+
+    match token_service::revoke_token(
+        revoked_db.as_ref(),
+        &auth_user.id,
+        &session.jti,
+        session.exp,
+    )
+    .await
+    {
+        Ok(_) => {
+            let before = audit_service::snapshot(db.as_ref(), &auth_user.id).await;
+            audit_service::record(
+                &req,
+                db.as_ref(),
+                AuditAction::Logout,
+                &auth_user.id,
+                Some(&auth_user.id),
+                before,
+            )
+            .await;
+            HttpResponse::Ok().json("Successfully logged out!")
+        }
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
 }
 
 #[put("/auth/{id}")]
+#[allow(clippy::too_many_arguments)]
 pub async fn update_password(
-    db: Data<MongoDB<User>>,
+    db: Data<dyn Repository<User>>,
+    refresh_db: Data<dyn Repository<RefreshToken>>,
+    revoked_db: Data<dyn Repository<RevokedToken>>,
+    policy: Data<PasswordPolicy>,
+    auth_user: AuthUser,
+    req: HttpRequest,
     path: Path<String>,
     passwords: Json<PasswordUpdate>,
 ) -> HttpResponse {
@@ -97,6 +410,9 @@ pub async fn update_password(
     if id.is_empty() {
         return HttpResponse::BadRequest().json("Invalid ID");
     }
+    if auth_user.id != id {
+        return HttpResponse::Forbidden().json("Cannot change another user's password");
+    }
     let result = db.get_record(&id).await;
 
     let mut user = match result {
@@ -104,18 +420,21 @@ pub async fn update_password(
         Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
     };
 
-    let is_verified = match verify_password(&passwords.old_password, &user.password) {
-        Ok(val) => val,
-        Err(error) => {
-            return HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
-                .json(error.to_string())
-        }
-    };
+    let is_verified =
+        verify_password(&passwords.old_password, &user.password).unwrap_or_else(|error| {
+            error!("Cannot verify password hash of user {}: {}", id, error);
+            false
+        });
 
     if !is_verified {
         return HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json("Invalid credentials");
     }
 
+    let errors = policy.validate(&passwords.new_password, Some(&user.email));
+    if !errors.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(errors);
+    }
+
     let doc = match encrypt_password(&passwords.new_password) {
         Ok(password) => doc! { "password": password },
         Err(error) => {
@@ -124,14 +443,33 @@ pub async fn update_password(
         }
     };
 
-    let result = db.update_record(&id, doc).await;
+    let before = audit_service::snapshot(db.as_ref(), &id).await;
+    let result = db.update_record(&id, doc, Some(&auth_user.id)).await;
 
-    user.password.clear();
+    user.redact();
 
     match result {
         Ok(update_result) => {
             if update_result.matched_count == 1 {
-                HttpResponse::Ok().json(user)
+                audit_service::record(
+                    &req,
+                    db.as_ref(),
+                    AuditAction::PasswordChange,
+                    &id,
+                    Some(&auth_user.id),
+                    before,
+                )
+                .await;
+                match token_service::revoke_all_sessions(
+                    revoked_db.as_ref(),
+                    refresh_db.as_ref(),
+                    &id,
+                )
+                .await
+                {
+                    Ok(_) => HttpResponse::Ok().json(user),
+                    Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+                }
             } else {
                 HttpResponse::NotFound().json("Specified ID not found")
             }
@@ -140,10 +478,552 @@ pub async fn update_password(
     }
 }
 
+#[post("/password-reset")]
+pub async fn request_password_reset(
+    db: Data<dyn Repository<User>>,
+    reset_db: Data<dyn Repository<PasswordResetToken>>,
+    mailer: Data<dyn Mailer>,
+    request: Json<PasswordResetRequest>,
+) -> HttpResponse {
+    // Always answer the same way so the endpoint can't be used to find out
+    // which emails have an account.
+    let accepted = HttpResponse::Accepted()
+        .json("If the account exists, a password reset email has been sent");
+
+    let email = normalize_email(&request.email);
+    let user = match db.find_one_record(doc! {"email": email}).await {
+        Ok(user) => user,
+        Err((StatusCode::NOT_FOUND, _)) => return accepted,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    let id = match user._id {
+        Some(id) => id.to_string(),
+        None => {
+            return HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .json("User ID does not exist.")
+        }
+    };
+
+    // Only the most recently requested token stays usable.
+    if let Err((status_code, err)) = reset_db.delete_many_records(doc! {"user_id": &id}).await {
+        return HttpResponseBuilder::new(status_code).json(err);
+    }
+
+    let token = token_service::random_token();
+    let lifetime = env_or("PASSWORD_RESET_LIFETIME", DEFAULT_PASSWORD_RESET_LIFETIME);
+    let expires_at = DateTime::from_millis(((token_service::now() + lifetime) * 1000) as i64);
+    if let Err((status_code, err)) = reset_db
+        .create_record(
+            PasswordResetToken {
+                _id: None,
+                user_id: id,
+                token_hash: token_service::hash_token(&token),
+                expires_at,
+            },
+            None,
+        )
+        .await
+    {
+        return HttpResponseBuilder::new(status_code).json(err);
+    }
+
+    let email = Email {
+        to: user.email,
+        subject: "Password reset".to_owned(),
+        body: format!(
+            "Use the following to reset your password within {} minutes:\n\n{}\n\nIf you did not request a password reset you can ignore this email.",
+            lifetime / 60,
+            link("PASSWORD_RESET_URL", &token)
+        ),
+    };
+    if let Err((_, err)) = mailer.send(email).await {
+        error!("Failed to send password reset email: {}", err);
+    }
+
+    accepted
+}
+
+#[post("/password-reset/confirm")]
+pub async fn confirm_password_reset(
+    db: Data<dyn Repository<User>>,
+    reset_db: Data<dyn Repository<PasswordResetToken>>,
+    refresh_db: Data<dyn Repository<RefreshToken>>,
+    revoked_db: Data<dyn Repository<RevokedToken>>,
+    policy: Data<PasswordPolicy>,
+    req: HttpRequest,
+    request: Json<PasswordResetConfirm>,
+) -> HttpResponse {
+    let token_hash = token_service::hash_token(&request.token);
+    let reset_token = match reset_db
+        .find_one_record(doc! {"token_hash": token_hash})
+        .await
+    {
+        Ok(reset_token) => reset_token,
+        Err((StatusCode::NOT_FOUND, _)) => {
+            return HttpResponse::BadRequest().json("Invalid password reset token")
+        }
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    let reset_id = match reset_token._id {
+        Some(id) => id.to_string(),
+        None => {
+            return HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .json("Password reset token ID does not exist.")
+        }
+    };
+
+    if reset_token.expires_at.timestamp_millis() <= (token_service::now() * 1000) as i64 {
+        return HttpResponse::BadRequest().json("Password reset token expired");
+    }
+
+    // Validate before using up the token so a rejected password can be retried.
+    let user = match db.get_record(&reset_token.user_id).await {
+        Ok(user) => user,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    let errors = policy.validate(&request.new_password, Some(&user.email));
+    if !errors.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(errors);
+    }
+
+    match reset_db.delete_record(&reset_id).await {
+        Ok(res) if res.deleted_count == 1 => {}
+        Ok(_) => return HttpResponse::BadRequest().json("Invalid password reset token"),
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    }
+
+    let password = match encrypt_password(&request.new_password) {
+        Ok(password) => password,
+        Err(error) => {
+            return HttpResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .json(error.to_string())
+        }
+    };
+    let doc = doc! {"password": password, "failed_logins": 0, "locked_until": null};
+
+    let before = audit_service::snapshot(db.as_ref(), &reset_token.user_id).await;
+    match db
+        .update_record(&reset_token.user_id, doc, Some(&reset_token.user_id))
+        .await
+    {
+        Ok(update_result) if update_result.matched_count == 1 => {
+            audit_service::record(
+                &req,
+                db.as_ref(),
+                AuditAction::PasswordReset,
+                &reset_token.user_id,
+                Some(&reset_token.user_id),
+                before,
+            )
+            .await;
+        }
+        Ok(_) => return HttpResponse::NotFound().json("Specified ID not found"),
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    }
+
+    match token_service::revoke_all_sessions(
+        revoked_db.as_ref(),
+        refresh_db.as_ref(),
+        &reset_token.user_id,
+    )
+    .await
+    {
+        Ok(_) => HttpResponse::Ok().json("Password successfully reset!"),
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+#[post("/totp/enroll")]
+pub async fn enroll_totp(
+    db: Data<dyn Repository<User>>,
+    auth_user: AuthUser,
+    req: HttpRequest,
+) -> HttpResponse {
+    let before = audit_service::snapshot(db.as_ref(), &auth_user.id).await;
+    let user = match db.get_record(&auth_user.id).await {
+        Ok(user) => user,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    if user.totp_enabled {
+        return HttpResponse::Conflict().json("TOTP is already enabled");
+    }
+
+    let secret = totp_service::generate_secret();
+    let doc = doc! {"totp_pending_secret": &secret};
+    match db
+        .update_record(&auth_user.id, doc, Some(&auth_user.id))
+        .await
+    {
+        Ok(_) => {
+            audit_user_update(&req, db.as_ref(), &auth_user.id, &auth_user.id, before).await;
+            HttpResponse::Ok().json(TotpEnrollment {
+                otpauth_uri: totp_service::otpauth_uri(&secret, &user.email),
+                secret,
+            })
+        }
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+#[post("/totp/confirm")]
+pub async fn confirm_totp(
+    db: Data<dyn Repository<User>>,
+    auth_user: AuthUser,
+    req: HttpRequest,
+    request: Json<TotpCode>,
+) -> HttpResponse {
+    let before = audit_service::snapshot(db.as_ref(), &auth_user.id).await;
+    let user = match db.get_record(&auth_user.id).await {
+        Ok(user) => user,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    let secret = match user.totp_pending_secret {
+        Some(secret) => secret,
+        None => return HttpResponse::BadRequest().json("No pending TOTP enrollment"),
+    };
+    let step = match totp_service::verify(&secret, &request.code, None) {
+        Some(step) => step,
+        None => return HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json("Invalid code"),
+    };
+
+    let recovery_codes = totp_service::generate_recovery_codes();
+    let recovery_code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|recovery_code| token_service::hash_token(recovery_code))
+        .collect();
+    let doc = doc! {
+        "totp_enabled": true,
+        "totp_secret": secret,
+        "totp_pending_secret": null,
+        "totp_last_step": step as i64,
+        "recovery_codes": recovery_code_hashes,
+    };
+    match db
+        .update_record(&auth_user.id, doc, Some(&auth_user.id))
+        .await
+    {
+        Ok(_) => {
+            audit_user_update(&req, db.as_ref(), &auth_user.id, &auth_user.id, before).await;
+            HttpResponse::Ok().json(RecoveryCodes { recovery_codes })
+        }
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+/// Records an update `actor` made to the user with `id` in the audit log.
+async fn audit_user_update(
+    req: &HttpRequest,
+    db: &dyn Repository<User>,
+    id: &str,
+    actor: &str,
+    before: Option<Document>,
+) {
+    audit_service::record(req, db, AuditAction::Update, id, Some(actor), before).await
+}
+
+fn totp_disabled() -> Document {
+    doc! {
+        "totp_enabled": false,
+        "totp_secret": null,
+        "totp_pending_secret": null,
+        "totp_last_step": null,
+        "recovery_codes": [],
+    }
+}
+
+#[delete("/totp")]
+pub async fn disable_totp(
+    db: Data<dyn Repository<User>>,
+    auth_user: AuthUser,
+    req: HttpRequest,
+    request: Json<TotpCode>,
+) -> HttpResponse {
+    let before = audit_service::snapshot(db.as_ref(), &auth_user.id).await;
+    let user = match db.get_record(&auth_user.id).await {
+        Ok(user) => user,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    match verify_second_factor(db.as_ref(), &auth_user.id, &user, &request.code).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return HttpResponseBuilder::new(StatusCode::UNAUTHORIZED).json("Invalid code")
+        }
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    }
+
+    match db
+        .update_record(&auth_user.id, totp_disabled(), Some(&auth_user.id))
+        .await
+    {
+        Ok(_) => {
+            audit_user_update(&req, db.as_ref(), &auth_user.id, &auth_user.id, before).await;
+            HttpResponse::Ok().json("TOTP successfully disabled!")
+        }
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+/// Lets an admin turn off TOTP for a user who lost their authenticator and
+/// recovery codes.
+#[delete("/{id}/totp")]
+pub async fn reset_totp(
+    db: Data<dyn Repository<User>>,
+    admin: AdminUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    if id.is_empty() {
+        return HttpResponse::BadRequest().json("Invalid ID");
+    }
+
+    let before = audit_service::snapshot(db.as_ref(), &id).await;
+    match db
+        .update_record(&id, totp_disabled(), Some(&admin.id))
+        .await
+    {
+        Ok(update_result) if update_result.matched_count == 1 => {
+            audit_user_update(&req, db.as_ref(), &id, &admin.id, before).await;
+            HttpResponse::Ok().json("TOTP successfully disabled!")
+        }
+        Ok(_) => HttpResponse::NotFound().json("Specified ID not found"),
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+#[delete("/{id}/sessions")]
+pub async fn revoke_sessions(
+    db: Data<dyn Repository<User>>,
+    refresh_db: Data<dyn Repository<RefreshToken>>,
+    revoked_db: Data<dyn Repository<RevokedToken>>,
+    admin: AdminUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    if id.is_empty() {
+        return HttpResponse::BadRequest().json("Invalid ID");
+    }
+    if let Err((status_code, err)) = db.get_record(&id).await {
+        return HttpResponseBuilder::new(status_code).json(err);
+    }
+
+    match token_service::revoke_all_sessions(revoked_db.as_ref(), refresh_db.as_ref(), &id).await {
+        Ok(_) => {
+            let before = audit_service::snapshot(db.as_ref(), &id).await;
+            audit_service::record(
+                &req,
+                db.as_ref(),
+                AuditAction::RevokeSessions,
+                &id,
+                Some(&admin.id),
+                before,
+            )
+            .await;
+            HttpResponse::Ok().json("Successfully revoked all sessions!")
+        }
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+#[post("/invitations")]
+pub async fn invite(
+    db: Data<dyn Repository<User>>,
+    mailer: Data<dyn Mailer>,
+    _auth: AdminUser,
+    invitation: Json<Invitation>,
+) -> HttpResponse {
+    let email = normalize_email(&invitation.email);
+    let errors = validate_email(&email);
+    if !errors.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(errors);
+    }
+    match db.find_one_record(doc! {"email": &email}).await {
+        Ok(_) => return HttpResponse::Conflict().json("A user with this email already exists"),
+        Err((StatusCode::NOT_FOUND, _)) => {}
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    }
+
+    let lifetime = env_or("INVITATION_LIFETIME", DEFAULT_INVITATION_LIFETIME);
+    let token = match token_service::sign_action(
+        token_service::INVITATION,
+        &email,
+        None,
+        Some(invitation.role),
+        lifetime,
+    ) {
+        Ok(token) => token,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    let email = Email {
+        to: email,
+        subject: "You have been invited".to_owned(),
+        body: format!(
+            "You have been invited to create an account. Use the following to register within {} hours:\n\n{}",
+            lifetime / (60 * 60),
+            link("INVITATION_URL", &token)
+        ),
+    };
+
+    match mailer.send(email).await {
+        Ok(_) => HttpResponse::Accepted().json("Invitation sent"),
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+/// Mails the user a link proving they own `email`.
+async fn send_verification(
+    mailer: &dyn Mailer,
+    id: &str,
+    email: &str,
+) -> Result<(), (StatusCode, String)> {
+    let lifetime = env_or(
+        "EMAIL_VERIFICATION_LIFETIME",
+        DEFAULT_EMAIL_VERIFICATION_LIFETIME,
+    );
+    let token = token_service::sign_action(
+        token_service::EMAIL_VERIFICATION,
+        id,
+        Some(email.to_owned()),
+        None,
+        lifetime,
+    )?;
+
+    mailer
+        .send(Email {
+            to: email.to_owned(),
+            subject: "Verify your email address".to_owned(),
+            body: format!(
+                "Use the following to verify your email address within {} hours:\n\n{}",
+                lifetime / (60 * 60),
+                link("EMAIL_VERIFICATION_URL", &token)
+            ),
+        })
+        .await
+}
+
+#[get("/verify-email")]
+pub async fn verify_email(
+    db: Data<dyn Repository<User>>,
+    req: HttpRequest,
+    verification: Query<EmailVerification>,
+) -> HttpResponse {
+    let claims = match token_service::verify_action(
+        &verification.token,
+        token_service::EMAIL_VERIFICATION,
+    ) {
+        Ok(claims) => claims,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+
+    // The link is only good for the address it was sent to.
+    let before = audit_service::snapshot(db.as_ref(), &claims.sub).await;
+    match db.get_record(&claims.sub).await {
+        Ok(user) if Some(&user.email) == claims.email.as_ref() => {}
+        Ok(_) => return HttpResponse::BadRequest().json("Email address has changed"),
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    }
+
+    match db
+        .update_record(
+            &claims.sub,
+            doc! {"email_verified": true},
+            Some(&claims.sub),
+        )
+        .await
+    {
+        Ok(_) => {
+            audit_user_update(&req, db.as_ref(), &claims.sub, &claims.sub, before).await;
+            HttpResponse::Ok().json("Email address verified!")
+        }
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+#[post("/verify-email")]
+pub async fn resend_verification(
+    db: Data<dyn Repository<User>>,
+    mailer: Data<dyn Mailer>,
+    request: Json<EmailVerificationRequest>,
+) -> HttpResponse {
+    // Respond the same way whether or not the account exists.
+    let email = normalize_email(&request.email);
+    match db.find_one_record(doc! {"email": &email}).await {
+        Ok(User {
+            _id: Some(id),
+            email_verified: false,
+            ..
+        }) => {
+            if let Err((_, err)) = send_verification(mailer.as_ref(), &id.to_string(), &email).await
+            {
+                error!("Failed to send verification email: {}", err);
+            }
+        }
+        Ok(_) | Err((StatusCode::NOT_FOUND, _)) => {}
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    }
+
+    HttpResponse::Accepted()
+        .json("If the account exists and is unverified, a verification email has been sent")
+}
+
+/// Whether any user exists, counting trashed ones since they can be restored.
+async fn has_users(db: &dyn TrashRepository<User>) -> Result<bool, (StatusCode, String)> {
+    Ok(db.count_records(doc! {}).await? + db.trash().count_records(doc! {}).await? > 0)
+}
+
+/// Claims the [`Bootstrap`] of the first admin, failing with `409 Conflict`
+/// if someone else got it.
+async fn claim_first_admin(db: &dyn Repository<Bootstrap>) -> Result<(), (StatusCode, String)> {
+    let bootstrap = Bootstrap {
+        _id: None,
+        name: FIRST_ADMIN.to_owned(),
+        metadata: Metadata::default(),
+    };
+    match db.create_record(bootstrap, None).await {
+        Ok(_) => Ok(()),
+        Err((StatusCode::CONFLICT, _)) => Err((
+            StatusCode::CONFLICT,
+            "The first user has already registered".to_owned(),
+        )),
+        Err(err) => Err(err),
+    }
+}
+
 #[post("")]
-pub async fn create(db: Data<MongoDB<User>>, new_user: Json<User>) -> HttpResponse {
-    if new_user.password.is_empty() {
-        return HttpResponse::BadRequest().json("Invalid empty password");
+pub async fn create(
+    db: Data<dyn TrashRepository<User>>,
+    redeemed_db: Data<dyn Repository<RedeemedToken>>,
+    bootstrap_db: Data<dyn Repository<Bootstrap>>,
+    policy: Data<PasswordPolicy>,
+    req: HttpRequest,
+    new_user: Json<UserRegistration>,
+) -> HttpResponse {
+    // Registering requires an invitation, except for the very first user so a
+    // fresh deployment can be bootstrapped, who becomes an admin.
+    let invitation = match &new_user.invitation {
+        Some(invitation) => {
+            match token_service::verify_action(invitation, token_service::INVITATION) {
+                Ok(claims) => Some(claims),
+                Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+            }
+        }
+        None => None,
+    };
+    let (email, role) = match &invitation {
+        Some(claims) => (claims.sub.clone(), claims.role.unwrap_or_default()),
+        None => match has_users(db.as_ref()).await {
+            Ok(true) => {
+                return HttpResponse::Forbidden().json("Registration requires an invitation")
+            }
+            Ok(false) => (normalize_email(&new_user.email), Role::Admin),
+            Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+        },
+    };
+    let mut errors = validate_email(&email);
+    errors.extend(policy.validate(&new_user.password, Some(&email)));
+    if !errors.is_empty() {
+        return HttpResponse::UnprocessableEntity().json(errors);
     }
     let password = match encrypt_password(&new_user.password) {
         Ok(password) => password,
@@ -152,49 +1032,106 @@ pub async fn create(db: Data<MongoDB<User>>, new_user: Json<User>) -> HttpRespon
                 .json(error.to_string())
         }
     };
+    // The invitation was delivered to the address, which proves ownership.
     let data = User {
         _id: None,
-        email: new_user.email.to_owned(),
+        email,
         password,
+        role,
+        email_verified: true,
+        failed_logins: 0,
+        locked_until: None,
+        totp_enabled: false,
+        totp_secret: None,
+        totp_pending_secret: None,
+        totp_last_step: None,
+        recovery_codes: Vec::new(),
+        deleted_email: None,
+        metadata: Metadata::default(),
+    };
+    // Each invitation registers one account, even once that one is deleted,
+    // and only one user can bootstrap.
+    let claimed = match &invitation {
+        Some(claims) => token_service::redeem(redeemed_db.as_ref(), claims).await,
+        None => claim_first_admin(bootstrap_db.as_ref()).await,
     };
-    crud_controller::create(db, data).await
+    if let Err((status_code, err)) = claimed {
+        return HttpResponseBuilder::new(status_code).json(err);
+    }
+    let response = crud_controller::create(
+        Data::from(db.into_inner() as Arc<dyn Repository<User>>),
+        req,
+        data,
+        None,
+        &[],
+    )
+    .await;
+    if !response.status().is_success() {
+        let released = match &invitation {
+            Some(claims) => token_service::release(redeemed_db.as_ref(), claims).await,
+            None => bootstrap_db
+                .delete_many_records(doc! {"name": FIRST_ADMIN})
+                .await
+                .map(|_| ()),
+        };
+        if let Err((_, err)) = released {
+            error!(
+                "Failed to release the claim of a failed registration: {}",
+                err
+            );
+        }
+    }
+    response
 }
 
 #[get("")]
-pub async fn get_all(db: Data<MongoDB<User>>) -> HttpResponse {
+pub async fn get_all(db: Data<dyn Repository<User>>, _auth: AdminUser) -> HttpResponse {
     let result = db.get_all_record().await;
 
     match result {
         Ok(mut records) => {
             records.iter_mut().for_each(|user| {
-                user.password.clear();
+                user.redact();
             });
-            return HttpResponse::Ok().json(records);
+            HttpResponse::Ok().json(records)
         }
         Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
     }
 }
 
 #[get("/{id}")]
-pub async fn get(db: Data<MongoDB<User>>, path: Path<String>) -> HttpResponse {
+pub async fn get(
+    db: Data<dyn Repository<User>>,
+    auth_user: AuthUser,
+    path: Path<String>,
+) -> HttpResponse {
     let id = path.into_inner();
     if id.is_empty() {
         return HttpResponse::BadRequest().json("Invalid ID");
     }
+    if auth_user.id != id && !auth_user.is_admin() {
+        return HttpResponse::Forbidden().json("Insufficient permissions");
+    }
     let result = db.get_record(&id).await;
 
     match result {
         Ok(mut record) => {
-            record.password.clear();
-            return HttpResponse::Ok().json(record);
+            record.redact();
+            HttpResponse::Ok().json(record)
         }
         Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
     }
 }
 
 #[put("/{id}")]
+#[allow(clippy::too_many_arguments)]
 pub async fn update(
-    db: Data<MongoDB<User>>,
+    db: Data<dyn Repository<User>>,
+    refresh_db: Data<dyn Repository<RefreshToken>>,
+    revoked_db: Data<dyn Repository<RevokedToken>>,
+    mailer: Data<dyn Mailer>,
+    admin: AdminUser,
+    req: HttpRequest,
     path: Path<String>,
     new_user: Json<UserUpdate>,
 ) -> HttpResponse {
@@ -202,21 +1139,63 @@ pub async fn update(
     if id.is_empty() {
         return HttpResponse::BadRequest().json("Invalid ID");
     };
-    let doc = match to_document(&new_user) {
+    let mut new_user = new_user.into_inner();
+    if let Some(email) = new_user.email.as_mut() {
+        *email = normalize_email(email);
+        let errors = validate_email(email);
+        if !errors.is_empty() {
+            return HttpResponse::UnprocessableEntity().json(errors);
+        }
+    }
+    let mut doc = match to_document(&new_user) {
         Ok(data) => data,
         Err(err) => return HttpResponse::BadRequest().json(err.to_string()),
     };
-    let result = db.update_record(&id, doc).await;
+    let current = match db.get_record(&id).await {
+        Ok(user) => user,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    // A new address has to be verified again before the user can log in.
+    let email_changed = new_user
+        .email
+        .as_ref()
+        .is_some_and(|email| *email != current.email);
+    // Tokens carry the role, so ones issued before the change must not outlive it.
+    let role_changed = new_user.role.is_some_and(|role| role != current.role);
+    if email_changed {
+        doc.insert("email_verified", false);
+    }
+    let before = audit_service::snapshot(db.as_ref(), &id).await;
+    let result = db.update_record(&id, doc, Some(&admin.id)).await;
 
     match result {
         Ok(update) => {
             if update.matched_count == 1 {
+                audit_user_update(&req, db.as_ref(), &id, &admin.id, before).await;
+                if role_changed {
+                    if let Err((status_code, err)) = token_service::revoke_all_sessions(
+                        revoked_db.as_ref(),
+                        refresh_db.as_ref(),
+                        &id,
+                    )
+                    .await
+                    {
+                        return HttpResponseBuilder::new(status_code).json(err);
+                    }
+                }
                 let updated = db.get_record(&id).await;
 
                 match updated {
                     Ok(mut record) => {
-                        record.password.clear();
-                        return HttpResponse::Ok().json(record);
+                        if email_changed {
+                            if let Err((_, err)) =
+                                send_verification(mailer.as_ref(), &id, &record.email).await
+                            {
+                                error!("Failed to send verification email: {}", err);
+                            }
+                        }
+                        record.redact();
+                        HttpResponse::Ok().json(record)
                     }
                     Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
                 }
@@ -228,7 +1207,292 @@ pub async fn update(
     }
 }
 
+/// Trashes the user, ends their sessions and frees their address.
 #[delete("/{id}")]
-pub async fn delete(db: Data<MongoDB<User>>, path: Path<String>) -> HttpResponse {
-    crud_controller::delete(db, path).await
+pub async fn delete(
+    db: Data<dyn TrashRepository<User>>,
+    refresh_db: Data<dyn Repository<RefreshToken>>,
+    revoked_db: Data<dyn Repository<RevokedToken>>,
+    admin: AdminUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    let user = match db.get_record(&id).await {
+        Ok(user) => user,
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    let response =
+        crud_controller::delete(db.clone(), req, Path::from(id.clone()), &admin.id).await;
+    if !response.status().is_success() {
+        return response;
+    }
+
+    if let Some(user_id) = user._id {
+        let tombstone = User::tombstone(&user_id, &user.email);
+        if let Err((status_code, err)) = db
+            .trash()
+            .update_unstamped(doc! {"_id": user_id}, tombstone)
+            .await
+        {
+            return HttpResponseBuilder::new(status_code).json(err);
+        }
+    }
+    match token_service::revoke_all_sessions(revoked_db.as_ref(), refresh_db.as_ref(), &id).await {
+        Ok(()) => response,
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+#[get("/trash")]
+pub async fn get_trash(db: Data<dyn TrashRepository<User>>, _auth: AdminUser) -> HttpResponse {
+    let result = db.trash().get_all_record().await;
+
+    match result {
+        Ok(mut records) => {
+            records.iter_mut().for_each(|user| {
+                user.redact();
+            });
+            HttpResponse::Ok().json(records)
+        }
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+#[post("/{id}/restore")]
+pub async fn restore(
+    db: Data<dyn TrashRepository<User>>,
+    admin: AdminUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    let id = path.into_inner();
+    let before = audit_service::snapshot(db.trash().as_ref(), &id).await;
+    let user = match db.trash().get_record(&id).await {
+        Ok(user) => user,
+        Err((StatusCode::NOT_FOUND, _)) => {
+            return HttpResponse::NotFound().json("Specified ID not found in the trash")
+        }
+        Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+    };
+    // Give the user their address back before restoring them, so the unique
+    // index refuses it if another account took it in the meantime.
+    if let (Some(user_id), Some(email)) = (user._id, user.deleted_email) {
+        match db.count_records(doc! {"email": &email}).await {
+            Ok(0) => {}
+            Ok(_) => return HttpResponse::Conflict().json("A user with this email already exists"),
+            Err((status_code, err)) => return HttpResponseBuilder::new(status_code).json(err),
+        }
+        if let Err((status_code, err)) = db
+            .trash()
+            .update_unstamped(
+                doc! {"_id": user_id},
+                doc! {"email": &email, "deleted_email": null},
+            )
+            .await
+        {
+            return HttpResponseBuilder::new(status_code).json(err);
+        }
+    }
+    let result = db.restore_record(&id, Some(&admin.id)).await;
+
+    match result {
+        Ok(restored) if restored.matched_count == 1 => match db.get_record(&id).await {
+            Ok(mut record) => {
+                audit_service::record(
+                    &req,
+                    db.as_ref(),
+                    AuditAction::Restore,
+                    &id,
+                    Some(&admin.id),
+                    before,
+                )
+                .await;
+                record.redact();
+                HttpResponse::Ok().json(record)
+            }
+            Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+        },
+        Ok(_) => HttpResponse::NotFound().json("Specified ID not found in the trash"),
+        Err((status_code, err)) => HttpResponseBuilder::new(status_code).json(err),
+    }
+}
+
+#[delete("/trash/{id}")]
+pub async fn purge(
+    db: Data<dyn TrashRepository<User>>,
+    admin: AdminUser,
+    req: HttpRequest,
+    path: Path<String>,
+) -> HttpResponse {
+    crud_controller::purge(db, req, path, &admin.id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        repository::{memory_repo::Memory, trash_repo::Trash},
+        service::key_service,
+    };
+    use actix_web::{
+        body::to_bytes,
+        dev::ServiceResponse,
+        test::{self, TestRequest},
+        App,
+    };
+    use mongodb::bson::from_document;
+
+    const PASSWORD: &str = "correct horse battery staple";
+
+    fn users() -> Arc<dyn TrashRepository<User>> {
+        Arc::new(Trash::init(Arc::new(Memory::<User>::init("User"))))
+    }
+
+    macro_rules! init_app {
+        ($user_db:expr) => {
+            init_app!($user_db, Arc::new(Memory::<Bootstrap>::init("Bootstrap")))
+        };
+        ($user_db:expr, $bootstrap_db:expr) => {{
+            let user_db: Arc<dyn TrashRepository<User>> = $user_db;
+            let redeemed_db: Arc<dyn Repository<RedeemedToken>> =
+                Arc::new(Memory::<RedeemedToken>::init("RedeemedToken"));
+            let bootstrap_db: Arc<dyn Repository<Bootstrap>> = $bootstrap_db;
+            let refresh_db: Arc<dyn Repository<RefreshToken>> =
+                Arc::new(Memory::<RefreshToken>::init("RefreshToken"));
+            let revoked_db: Arc<dyn Repository<RevokedToken>> =
+                Arc::new(Memory::<RevokedToken>::init("RevokedToken"));
+            test::init_service(
+                App::new()
+                    .app_data(Data::from(user_db.clone() as Arc<dyn Repository<User>>))
+                    .app_data(Data::from(user_db))
+                    .app_data(Data::from(redeemed_db))
+                    .app_data(Data::from(bootstrap_db))
+                    .app_data(Data::from(refresh_db))
+                    .app_data(Data::from(revoked_db))
+                    .app_data(Data::new(PasswordPolicy::from_env()))
+                    .app_data(Data::new(LoginThrottle::from_env()))
+                    .service(new()),
+            )
+            .await
+        }};
+    }
+
+    async fn body(response: ServiceResponse) -> String {
+        let bytes = to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    fn register(password: &str, invitation: &str) -> TestRequest {
+        TestRequest::post()
+            .uri("/users")
+            .set_json(doc! {"password": password, "invitation": invitation})
+    }
+
+    #[actix_web::test]
+    async fn invitations_register_one_account() {
+        key_service::init_test_keys();
+        let app = init_app!(users());
+        let invitation =
+            token_service::sign_action(token_service::INVITATION, "b@x.io", None, None, 60)
+                .unwrap();
+
+        // Failed registrations leave the invitation usable.
+        let response = test::call_service(&app, register("short", &invitation).to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let response = test::call_service(&app, register(PASSWORD, &invitation).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let created: Document = test::read_body_json(response).await;
+        let id = created.get_str("id").unwrap().to_owned();
+
+        let response = test::call_service(&app, register(PASSWORD, &invitation).to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(body(response).await, "\"Token already used\"");
+
+        // Not even once the account is deleted and its address free again.
+        let admin = token_service::test_access_token("6ad4abb3557298608d132501", Role::Admin);
+        let request = TestRequest::delete()
+            .uri(&format!("/users/{}", id))
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", admin)))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, request).await.status(),
+            StatusCode::OK
+        );
+        let response = test::call_service(&app, register(PASSWORD, &invitation).to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn lockouts_only_show_to_the_right_password() {
+        key_service::init_test_keys();
+        let db = users();
+        let locked_until = DateTime::from_millis(DateTime::now().timestamp_millis() + 60_000);
+        let user = doc! {
+            "email": "locked@x.io",
+            "password": encrypt_password(PASSWORD).unwrap(),
+            "email_verified": true,
+            "locked_until": locked_until,
+        };
+        db.create_record(from_document(user).unwrap(), None)
+            .await
+            .unwrap();
+        let app = init_app!(db.clone());
+        let login = |email: &str, password: &str| {
+            TestRequest::post()
+                .uri("/users/auth")
+                .set_json(doc! {"email": email, "password": password})
+                .to_request()
+        };
+
+        let unknown = test::call_service(&app, login("unknown@x.io", PASSWORD)).await;
+        let wrong = test::call_service(&app, login("locked@x.io", "wrong password")).await;
+        assert_eq!(unknown.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(body(unknown).await, body(wrong).await);
+
+        let right = test::call_service(&app, login("locked@x.io", PASSWORD)).await;
+        assert_eq!(right.status(), StatusCode::TOO_MANY_REQUESTS);
+        // Wrong passwords don't count towards another lockout meanwhile.
+        let user = db
+            .find_one_record(doc! {"email": "locked@x.io"})
+            .await
+            .unwrap();
+        assert_eq!(user.failed_logins, 0);
+        assert_eq!(user.locked_until, Some(locked_until));
+    }
+
+    #[actix_web::test]
+    async fn only_one_first_user_becomes_admin() {
+        let bootstrap = |email: &str| {
+            TestRequest::post()
+                .uri("/users")
+                .set_json(doc! {"email": email, "password": PASSWORD})
+                .to_request()
+        };
+
+        // Another registration claimed it but hasn't stored its user yet.
+        let bootstrap_db: Arc<dyn Repository<Bootstrap>> =
+            Arc::new(Memory::<Bootstrap>::init("Bootstrap"));
+        claim_first_admin(bootstrap_db.as_ref()).await.unwrap();
+        let app = init_app!(users(), bootstrap_db);
+        let response = test::call_service(&app, bootstrap("a@x.io")).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let db = users();
+        let app = init_app!(db.clone());
+        let (first, second) = futures::join!(
+            test::call_service(&app, bootstrap("a@x.io")),
+            test::call_service(&app, bootstrap("b@x.io")),
+        );
+        let mut statuses = [first.status(), second.status()];
+        statuses.sort();
+        assert_eq!(statuses[0], StatusCode::OK);
+        assert!(statuses[1].is_client_error());
+        let admins = db.find_record(doc! {"role": "admin"}).await.unwrap();
+        assert_eq!(admins.len(), 1);
+
+        let response = test::call_service(&app, bootstrap("c@x.io")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
 }
@@ -0,0 +1,197 @@
+use crate::{
+    model::{
+        api_key_model::ApiKey,
+        token_model::RevokedToken,
+        user_model::{Role, User},
+    },
+    repository::Repository,
+    service::{api_key_service, token_service},
+};
+use actix_web::{
+    dev::Payload,
+    error::InternalError,
+    http::{header, StatusCode},
+    web::Data,
+    Error, FromRequest, HttpRequest, HttpResponseBuilder,
+};
+use futures::future::LocalBoxFuture;
+use std::ops::Deref;
+
+/// The access token an [`AuthUser`] authenticated with.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub jti: String,
+    pub exp: u64,
+}
+
+/// Identity of the caller, taken from a verified `Authorization: Bearer` JWT
+/// or an `X-API-Key` header.
+///
+/// Adding this as a handler argument makes the route reject requests without a
+/// valid, unrevoked token or key with `401 Unauthorized`, and requests made with
+/// an API key lacking the scope for the route with `403 Forbidden`.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: String,
+    pub role: Role,
+    /// Set when authenticated with a JWT rather than an API key.
+    pub session: Option<Session>,
+}
+
+const API_KEY_HEADER: &str = "X-API-Key";
+
+async fn authenticate_api_key(
+    req: &HttpRequest,
+    key: &str,
+) -> Result<AuthUser, (StatusCode, String)> {
+    let (api_key_db, user_db) = match (
+        req.app_data::<Data<dyn Repository<ApiKey>>>(),
+        req.app_data::<Data<dyn Repository<User>>>(),
+    ) {
+        (Some(api_key_db), Some(user_db)) => (api_key_db, user_db),
+        _ => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "API key store is not configured".to_owned(),
+            ))
+        }
+    };
+    let (api_key, user) =
+        api_key_service::authenticate(api_key_db.as_ref(), user_db.as_ref(), key).await?;
+
+    if !api_key_service::allows(&api_key.scopes, req) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "API key is missing the required scope".to_owned(),
+        ));
+    }
+
+    Ok(AuthUser {
+        id: api_key.user_id,
+        role: user.role,
+        session: None,
+    })
+}
+
+async fn authenticate(req: HttpRequest) -> Result<AuthUser, (StatusCode, String)> {
+    if let Some(key) = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        return authenticate_api_key(&req, key.trim()).await;
+    }
+
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or((StatusCode::UNAUTHORIZED, "Missing bearer token".to_owned()))?;
+
+    let claims = token_service::verify(token.trim())?;
+
+    let revoked_db = req
+        .app_data::<Data<dyn Repository<RevokedToken>>>()
+        .ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Revocation store is not configured".to_owned(),
+        ))?;
+    if token_service::is_revoked(revoked_db.as_ref(), &claims).await? {
+        return Err((StatusCode::UNAUTHORIZED, "Token revoked".to_owned()));
+    }
+
+    Ok(AuthUser {
+        id: claims.id,
+        role: claims.role,
+        session: Some(Session {
+            jti: claims.jti,
+            exp: claims.exp,
+        }),
+    })
+}
+
+fn into_error((status_code, message): (StatusCode, String)) -> Error {
+    InternalError::from_response(
+        message.clone(),
+        HttpResponseBuilder::new(status_code).json(message),
+    )
+    .into()
+}
+
+async fn authorize(req: HttpRequest, roles: &[Role]) -> Result<AuthUser, Error> {
+    let auth_user = authenticate(req).await.map_err(into_error)?;
+    if !roles.contains(&auth_user.role) {
+        return Err(into_error((
+            StatusCode::FORBIDDEN,
+            "Insufficient permissions".to_owned(),
+        )));
+    }
+
+    Ok(auth_user)
+}
+
+impl AuthUser {
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+}
+
+impl FromRequest for AuthUser {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move { authenticate(req).await.map_err(into_error) })
+    }
+}
+
+/// An [`AuthUser`] with the `admin` role, otherwise `403 Forbidden`.
+#[derive(Debug, Clone)]
+pub struct AdminUser(pub AuthUser);
+
+impl Deref for AdminUser {
+    type Target = AuthUser;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromRequest for AdminUser {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move { authorize(req, &[Role::Admin]).await.map(AdminUser) })
+    }
+}
+
+/// An [`AuthUser`] allowed to change portfolio content, i.e. an `admin` or an
+/// `editor`, otherwise `403 Forbidden`.
+#[derive(Debug, Clone)]
+pub struct EditorUser(pub AuthUser);
+
+impl Deref for EditorUser {
+    type Target = AuthUser;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromRequest for EditorUser {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            authorize(req, &[Role::Admin, Role::Editor])
+                .await
+                .map(EditorUser)
+        })
+    }
+}
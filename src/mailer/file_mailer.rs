@@ -0,0 +1,43 @@
+use super::{Email, Mailer};
+use crate::service::token_service::{now, random_token};
+use actix_web::{http::StatusCode, web};
+use async_trait::async_trait;
+use log::info;
+use std::{fs, path::PathBuf};
+
+/// Writes every email into a file under `MAIL_DIR` instead of delivering it,
+/// for local development and tests.
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn from_env() -> Self {
+        let dir = PathBuf::from(dotenv::var("MAIL_DIR").unwrap_or_else(|_| "mail".to_owned()));
+        info!("Initializing file mailer at {}", dir.display());
+        FileMailer { dir }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> Result<(), (StatusCode, String)> {
+        let path = self
+            .dir
+            .join(format!("{}-{}.eml", now(), &random_token()[..8]));
+        let contents = format!(
+            "To: {}\nSubject: {}\n\n{}\n",
+            email.to, email.subject, email.body
+        );
+        info!("Writing email to {} into {}", email.to, path.display());
+
+        let dir = self.dir.clone();
+        web::block(move || {
+            fs::create_dir_all(dir)?;
+            fs::write(path, contents)
+        })
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+    }
+}
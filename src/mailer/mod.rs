@@ -0,0 +1,27 @@
+use actix_web::http::StatusCode;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+pub mod file_mailer;
+pub mod smtp_mailer;
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), (StatusCode, String)>;
+}
+
+/// Builds the mailer selected by `MAILER`: `smtp` for [`smtp_mailer::SmtpMailer`],
+/// anything else for [`file_mailer::FileMailer`].
+pub fn from_env() -> Arc<dyn Mailer> {
+    match dotenv::var("MAILER").as_deref() {
+        Ok("smtp") => Arc::new(smtp_mailer::SmtpMailer::from_env()),
+        _ => Arc::new(file_mailer::FileMailer::from_env()),
+    }
+}
@@ -1,36 +1,179 @@
 mod controller;
+mod extractor;
+mod mailer;
 mod model;
 mod repository;
+mod service;
 
 use actix_web::{
     middleware::Logger,
+    rt,
     web::{self, Data},
     App, HttpServer,
 };
 use controller::{
-    detail_controller, experience_controller, project_controller, tech_stack_controller,
-    user_controller,
+    api_key_controller, audit_controller, detail_controller, experience_controller,
+    jwks_controller, project_controller, tech_stack_controller, user_controller,
 };
 use dotenv::dotenv;
 use env_logger::Env;
-use log::info;
+use log::{error, info};
+use mailer::Mailer;
 use model::{
-    detail_model::Detail, experience_model::Experience, project_model::Project,
-    tech_stack_model::TechStack, user_model::User,
+    api_key_model::ApiKey,
+    audit_model::AuditEntry,
+    bootstrap_model::Bootstrap,
+    detail_model::Detail,
+    experience_model::Experience,
+    migration_model::Migration,
+    oidc_model::OidcLogin,
+    password_reset_model::PasswordResetToken,
+    project_model::Project,
+    tech_stack_model::TechStack,
+    token_model::{RedeemedToken, RefreshToken, RevokedToken},
+    user_model::{check_hash_config, User},
 };
-use repository::mongodb_repo::{new, MongoDB};
+use mongodb::bson::doc;
+use repository::{Repository, Storage};
+use service::{
+    experience_service, key_service, migration_service, oidc_service::OidcProvider,
+    reference_service, throttle_service::LoginThrottle, trash_service,
+    validation_service::PasswordPolicy,
+};
+use std::sync::Arc;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
     env_logger::init_from_env(Env::default().default_filter_or("info"));
+    if let Err((_, err)) = key_service::keys() {
+        error!("Cannot load the keys to sign tokens with: {}", err);
+        std::process::exit(1);
+    }
+    if let Err(err) = check_hash_config() {
+        error!("Invalid password hashing settings: {}", err);
+        std::process::exit(1);
+    }
     info!("Initializing database...");
-    let mut db = new("ava").await;
-    let detail_db_data = Data::new(MongoDB::<Detail>::init(&mut db, "Detail").await);
-    let tech_stack_db_data = Data::new(MongoDB::<TechStack>::init(&mut db, "TechStack").await);
-    let project_db_data = Data::new(MongoDB::<Project>::init(&mut db, "Project").await);
-    let experience_db_data = Data::new(MongoDB::<Experience>::init(&mut db, "Experience").await);
-    let user_db_data = Data::new(MongoDB::<User>::init(&mut db, "User").await);
+    let mut storage = Storage::from_env("ava").await;
+    let (detail_trash, detail_revisions) = storage.history_repository::<Detail>("Detail").await;
+    let (tech_stack_trash, tech_stack_revisions) =
+        storage.history_repository::<TechStack>("TechStack").await;
+    let (project_trash, project_revisions) = storage.history_repository::<Project>("Project").await;
+    let (experience_trash, experience_revisions) =
+        storage.history_repository::<Experience>("Experience").await;
+    let user_trash = storage.trash_repository::<User>("User").await;
+    let detail_db_data = Data::from(detail_trash.clone() as Arc<dyn Repository<Detail>>);
+    let tech_stack_db_data = Data::from(tech_stack_trash.clone() as Arc<dyn Repository<TechStack>>);
+    let project_db_data = Data::from(project_trash.clone() as Arc<dyn Repository<Project>>);
+    let experience_db_data =
+        Data::from(experience_trash.clone() as Arc<dyn Repository<Experience>>);
+    let user_db_data = Data::from(user_trash.clone() as Arc<dyn Repository<User>>);
+    let migration_db = storage.repository::<Migration>("Migration").await;
+    // Users created before roles existed had full access, so keep it that way.
+    migration_service::run_once(migration_db.as_ref(), "user_roles", async {
+        user_db_data
+            .update_unstamped(doc! {"role": {"$exists": false}}, doc! {"role": "admin"})
+            .await
+            .map(|_| ())
+    })
+    .await
+    .expect("error migrating User roles");
+    // Existing accounts were created by an admin, so trust their addresses.
+    migration_service::run_once(migration_db.as_ref(), "user_email_verified", async {
+        user_db_data
+            .update_unstamped(
+                doc! {"email_verified": {"$exists": false}},
+                doc! {"email_verified": true},
+            )
+            .await
+            .map(|_| ())
+    })
+    .await
+    .expect("error migrating User email verification");
+    // Emails are matched case-insensitively by storing them normalized.
+    migration_service::run_once(
+        migration_db.as_ref(),
+        "user_normalized_emails",
+        migration_service::normalize_user_emails(user_trash.as_ref()),
+    )
+    .await
+    .expect("error migrating User emails");
+    migration_service::run_once(
+        migration_db.as_ref(),
+        "user_trash_tombstones",
+        migration_service::tombstone_trashed_users(user_trash.as_ref()),
+    )
+    .await
+    .expect("error freeing the addresses of trashed Users");
+    // Tech stacks used to be listed by name, so point them at the entries.
+    migration_service::run_once(
+        migration_db.as_ref(),
+        "project_tech_stack_ids",
+        reference_service::link_tech_stack_names(tech_stack_trash.as_ref(), project_trash.as_ref()),
+    )
+    .await
+    .expect("error migrating Project tech stacks");
+    migration_service::run_once(
+        migration_db.as_ref(),
+        "experience_tech_stack_ids",
+        reference_service::link_tech_stack_names(
+            tech_stack_trash.as_ref(),
+            experience_trash.as_ref(),
+        ),
+    )
+    .await
+    .expect("error migrating Experience tech stacks");
+    // Experience dates used to be free text.
+    migration_service::run_once(
+        migration_db.as_ref(),
+        "experience_partial_dates",
+        experience_service::migrate_dates(experience_trash.as_ref(), experience_revisions.as_ref()),
+    )
+    .await
+    .expect("error migrating Experience dates");
+    rt::spawn(trash_service::purge_expired(detail_trash.clone(), "Detail"));
+    rt::spawn(trash_service::purge_expired(
+        tech_stack_trash.clone(),
+        "TechStack",
+    ));
+    rt::spawn(trash_service::purge_expired(
+        project_trash.clone(),
+        "Project",
+    ));
+    rt::spawn(trash_service::purge_expired(
+        experience_trash.clone(),
+        "Experience",
+    ));
+    rt::spawn(trash_service::purge_expired(user_trash.clone(), "User"));
+    let detail_trash_data = Data::from(detail_trash);
+    let detail_revision_data = Data::from(detail_revisions);
+    let tech_stack_trash_data = Data::from(tech_stack_trash);
+    let tech_stack_revision_data = Data::from(tech_stack_revisions);
+    let project_trash_data = Data::from(project_trash);
+    let project_revision_data = Data::from(project_revisions);
+    let experience_trash_data = Data::from(experience_trash);
+    let experience_revision_data = Data::from(experience_revisions);
+    let user_trash_data = Data::from(user_trash);
+    let refresh_token_db_data =
+        Data::from(storage.repository::<RefreshToken>("RefreshToken").await);
+    let revoked_token_db_data =
+        Data::from(storage.repository::<RevokedToken>("RevokedToken").await);
+    let redeemed_token_db_data =
+        Data::from(storage.repository::<RedeemedToken>("RedeemedToken").await);
+    let bootstrap_db_data = Data::from(storage.repository::<Bootstrap>("Bootstrap").await);
+    let password_reset_db_data = Data::from(
+        storage
+            .repository::<PasswordResetToken>("PasswordResetToken")
+            .await,
+    );
+    let api_key_db_data = Data::from(storage.repository::<ApiKey>("ApiKey").await);
+    let audit_db_data = Data::from(storage.repository::<AuditEntry>("AuditEntry").await);
+    let oidc_login_db_data = Data::from(storage.repository::<OidcLogin>("OidcLogin").await);
+    let login_throttle_data = Data::new(LoginThrottle::from_env());
+    let mailer_data: Data<dyn Mailer> = Data::from(mailer::from_env());
+    let password_policy_data = Data::new(PasswordPolicy::from_env());
+    let oidc_provider_data = Data::new(OidcProvider::from_env());
     info!("Starting server...");
     HttpServer::new(move || {
         App::new()
@@ -40,13 +183,37 @@ async fn main() -> std::io::Result<()> {
             .app_data(project_db_data.clone())
             .app_data(experience_db_data.clone())
             .app_data(user_db_data.clone())
+            .app_data(detail_trash_data.clone())
+            .app_data(detail_revision_data.clone())
+            .app_data(tech_stack_trash_data.clone())
+            .app_data(tech_stack_revision_data.clone())
+            .app_data(project_trash_data.clone())
+            .app_data(project_revision_data.clone())
+            .app_data(experience_trash_data.clone())
+            .app_data(experience_revision_data.clone())
+            .app_data(user_trash_data.clone())
+            .app_data(refresh_token_db_data.clone())
+            .app_data(revoked_token_db_data.clone())
+            .app_data(redeemed_token_db_data.clone())
+            .app_data(bootstrap_db_data.clone())
+            .app_data(password_reset_db_data.clone())
+            .app_data(api_key_db_data.clone())
+            .app_data(audit_db_data.clone())
+            .app_data(oidc_login_db_data.clone())
+            .app_data(login_throttle_data.clone())
+            .app_data(mailer_data.clone())
+            .app_data(password_policy_data.clone())
+            .app_data(oidc_provider_data.clone())
+            .service(jwks_controller::new())
             .service(
                 web::scope("/api")
                     .service(detail_controller::new())
                     .service(tech_stack_controller::new())
                     .service(project_controller::new())
                     .service(experience_controller::new())
-                    .service(user_controller::new()),
+                    .service(user_controller::new())
+                    .service(api_key_controller::new())
+                    .service(audit_controller::new()),
             )
     })
     .bind(("0.0.0.0", 8080))?
@@ -0,0 +1,49 @@
+use super::{index, serialize_object_id, unique_index, IndexedModel};
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime},
+    IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKey {
+    #[serde(
+        rename(deserialize = "_id", serialize = "id"),
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub _id: Option<ObjectId>,
+    pub user_id: String,
+    pub name: String,
+    /// The first characters of the key, so it can be recognized in listings.
+    pub prefix: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime,
+    #[serde(default)]
+    pub last_used_at: Option<DateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKeyCreate {
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKeyCreated {
+    pub id: String,
+    pub key: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+}
+
+impl IndexedModel for ApiKey {
+    fn indexes() -> Vec<IndexModel> {
+        vec![
+            unique_index(doc! {"key_hash": 1}),
+            index(doc! {"user_id": 1}),
+        ]
+    }
+}
@@ -0,0 +1,62 @@
+use super::{index, metadata_model::Metadata, serialize_object_id, IndexedModel, QueryableModel};
+use mongodb::{
+    bson::{doc, oid::ObjectId, Document},
+    IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+    Restore,
+    Purge,
+    PasswordChange,
+    PasswordReset,
+    Logout,
+    RevokeSessions,
+}
+
+/// A write to a record or to the sessions of a user, appended to the audit
+/// log and never changed. Its `created_at` is when the write happened.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+    #[serde(
+        rename(deserialize = "_id", serialize = "id"),
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub _id: Option<ObjectId>,
+    /// The ID of the user who made the write, `None` for anonymous ones such
+    /// as registering.
+    pub actor: Option<String>,
+    pub action: AuditAction,
+    /// The model of the record, e.g. `Project`.
+    pub resource: String,
+    pub record_id: String,
+    /// The record before and after the write, rendered and without
+    /// credentials, `None` where it didn't exist.
+    pub before: Option<Document>,
+    pub after: Option<Document>,
+    pub ip: Option<String>,
+    #[serde(flatten)]
+    pub metadata: Metadata,
+}
+
+impl IndexedModel for AuditEntry {
+    fn indexes() -> Vec<IndexModel> {
+        vec![
+            index(doc! {"actor": 1, "created_at": 1}),
+            index(doc! {"resource": 1, "created_at": 1}),
+            index(doc! {"created_at": 1}),
+        ]
+    }
+}
+
+impl QueryableModel for AuditEntry {
+    const FIELDS: &'static [&'static str] = &["actor", "action", "resource", "record_id", "ip"];
+    /// Newest first, also among entries written in the same millisecond.
+    const DEFAULT_SORT: &'static [(&'static str, i32)] = &[("created_at", -1), ("_id", -1)];
+}
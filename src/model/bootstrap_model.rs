@@ -0,0 +1,31 @@
+use super::{metadata_model::Metadata, serialize_object_id, unique_index, IndexedModel};
+use mongodb::{
+    bson::{doc, oid::ObjectId},
+    IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+/// The name of the only [`Bootstrap`] record.
+pub const FIRST_ADMIN: &str = "first_admin";
+
+/// Claimed by the first user to register without an invitation, who becomes
+/// an admin. Its name is unique, so of several first registrations at the
+/// same time only one gets it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Bootstrap {
+    #[serde(
+        rename(deserialize = "_id", serialize = "id"),
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub _id: Option<ObjectId>,
+    pub name: String,
+    #[serde(flatten)]
+    pub metadata: Metadata,
+}
+
+impl IndexedModel for Bootstrap {
+    fn indexes() -> Vec<IndexModel> {
+        vec![unique_index(doc! {"name": 1})]
+    }
+}
@@ -1,4 +1,6 @@
-use super::serialize_object_id;
+use super::{
+    metadata_model::Metadata, serialize_object_id, IndexedModel, QueryableModel, StampedModel,
+};
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 
@@ -13,6 +15,8 @@ pub struct Detail {
     pub name: String,
     pub description: String,
     pub image: String,
+    #[serde(flatten)]
+    pub metadata: Metadata,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -24,3 +28,15 @@ pub struct DetailUpdate {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub image: Option<String>,
 }
+
+impl IndexedModel for Detail {}
+
+impl QueryableModel for Detail {
+    const FIELDS: &'static [&'static str] = &["name", "description", "image"];
+}
+
+impl StampedModel for Detail {
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
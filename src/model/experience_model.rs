@@ -1,6 +1,111 @@
-use super::serialize_object_id;
-use mongodb::bson::oid::ObjectId;
-use serde::{Deserialize, Serialize};
+use super::{
+    metadata_model::Metadata, serialize_object_id, IndexedModel, QueryableModel, StampedModel,
+};
+use mongodb::bson::{doc, oid::ObjectId, Bson, DateTime, Document};
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+/// A calendar date whose day may be left out, written `YYYY-MM-DD` or
+/// `YYYY-MM` so that the stored strings sort chronologically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartialDate {
+    pub year: u16,
+    pub month: u8,
+    pub day: Option<u8>,
+}
+
+impl PartialDate {
+    /// Today's date in UTC.
+    pub fn today() -> Option<Self> {
+        let now = DateTime::now().try_to_rfc3339_string().ok()?;
+        now.get(..10)?.parse().ok()
+    }
+
+    /// Whether this date is before `other`, days only counting when both
+    /// have one.
+    pub fn is_before(&self, other: &PartialDate) -> bool {
+        match (self.year, self.month).cmp(&(other.year, other.month)) {
+            Ordering::Equal => matches!(
+                (self.day, other.day),
+                (Some(day), Some(other_day)) if day < other_day
+            ),
+            ordering => ordering.is_lt(),
+        }
+    }
+
+    /// The months from this date up to `end`, counting both the first and
+    /// the last one.
+    pub fn months_until(&self, end: &PartialDate) -> i64 {
+        let months =
+            (end.year as i64 - self.year as i64) * 12 + end.month as i64 - self.month as i64 + 1;
+        months.max(0)
+    }
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl FromStr for PartialDate {
+    type Err = String;
+
+    fn from_str(date: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid date {}, expected YYYY-MM or YYYY-MM-DD", date);
+        let number = |part: &str, len: usize| match part.len() == len
+            && part.bytes().all(|byte| byte.is_ascii_digit())
+        {
+            true => part.parse::<u16>().map_err(|_| invalid()),
+            false => Err(invalid()),
+        };
+        let parts: Vec<&str> = date.split('-').collect();
+        let (year, month, day) = match parts.as_slice() {
+            [year, month] => (number(year, 4)?, number(month, 2)? as u8, None),
+            [year, month, day] => (
+                number(year, 4)?,
+                number(month, 2)? as u8,
+                Some(number(day, 2)? as u8),
+            ),
+            _ => return Err(invalid()),
+        };
+        if !(1..=12).contains(&month)
+            || day.is_some_and(|day| day < 1 || day > days_in_month(year, month))
+        {
+            return Err(invalid());
+        }
+        Ok(PartialDate { year, month, day })
+    }
+}
+
+impl fmt::Display for PartialDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}", self.year, self.month)?;
+        match self.day {
+            Some(day) => write!(f, "-{:02}", day),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Serialize for PartialDate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PartialDate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Experience {
@@ -13,9 +118,15 @@ pub struct Experience {
     pub role: String,
     pub company: String,
     pub description: String,
-    pub start: String,
-    pub end: String,
+    /// Required, but `None` for a record whose legacy date couldn't be read.
+    pub start: Option<PartialDate>,
+    /// `None` while the position is held.
+    #[serde(default)]
+    pub end: Option<PartialDate>,
+    /// IDs of `TechStack` records.
     pub tech_stack: Vec<String>,
+    #[serde(flatten)]
+    pub metadata: Metadata,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -27,9 +138,167 @@ pub struct ExperienceUpdate {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub start: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub end: Option<String>,
+    pub start: Option<PartialDate>,
+    /// `Some(None)`, sent as `null`, for a position that is held again.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "present"
+    )]
+    pub end: Option<Option<PartialDate>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tech_stack: Option<Vec<String>>,
 }
+
+/// Tells a field sent as `null` apart from one left out.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+impl IndexedModel for Experience {}
+
+impl QueryableModel for Experience {
+    const FIELDS: &'static [&'static str] = &[
+        "role",
+        "company",
+        "description",
+        "start",
+        "end",
+        "tech_stack",
+    ];
+    /// Most recent first.
+    const DEFAULT_SORT: &'static [(&'static str, i32)] = &[("start", -1)];
+
+    /// Adds the `duration` of the position up to its end, or today while it
+    /// is held, as `years` and `months`.
+    fn compute(record: &mut Document) {
+        let start = record
+            .get_str("start")
+            .ok()
+            .and_then(|start| start.parse::<PartialDate>().ok());
+        let end = match record.get("end") {
+            Some(Bson::String(end)) => end.parse().ok(),
+            Some(Bson::Null) => PartialDate::today(),
+            _ => None,
+        };
+        if let (Some(start), Some(end)) = (start, end) {
+            let months = start.months_until(&end);
+            record.insert(
+                "duration",
+                doc! {"years": months / 12, "months": months % 12},
+            );
+        }
+    }
+}
+
+impl StampedModel for Experience {
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(date: &str) -> PartialDate {
+        date.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_and_writes_dates() {
+        for text in ["2021-03", "2021-03-09", "0999-12-31", "2024-02-29"] {
+            assert_eq!(date(text).to_string(), text);
+        }
+        assert_eq!(
+            date("2021-03"),
+            PartialDate {
+                year: 2021,
+                month: 3,
+                day: None
+            }
+        );
+        assert_eq!(date("2021-03-09").day, Some(9));
+
+        let bson = mongodb::bson::to_bson(&date("2021-03")).unwrap();
+        assert_eq!(bson, Bson::String("2021-03".to_owned()));
+        assert_eq!(
+            mongodb::bson::from_bson::<PartialDate>(bson).unwrap(),
+            date("2021-03")
+        );
+        assert!(mongodb::bson::from_bson::<PartialDate>(Bson::String("2021".to_owned())).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_and_impossible_dates() {
+        for text in [
+            "",
+            "2021",
+            "21-03",
+            "2021-3",
+            "2021-03-9",
+            "2021-03-09-01",
+            "2021/03",
+            "+021-03",
+            "2021-00",
+            "2021-13",
+            "2021-04-00",
+            "2021-04-31",
+            "2023-02-29",
+            "1900-02-29",
+        ] {
+            assert!(text.parse::<PartialDate>().is_err(), "{}", text);
+        }
+        assert!("2000-02-29".parse::<PartialDate>().is_ok());
+    }
+
+    #[test]
+    fn orders_dates_by_what_both_know() {
+        assert!(date("2020-12").is_before(&date("2021-01")));
+        assert!(date("2021-01-31").is_before(&date("2021-02-01")));
+        assert!(date("2021-03-08").is_before(&date("2021-03-09")));
+        assert!(!date("2021-03-09").is_before(&date("2021-03-09")));
+        assert!(!date("2021-03-09").is_before(&date("2021-03-08")));
+        // Without a day on both sides, dates within a month are even.
+        assert!(!date("2021-03").is_before(&date("2021-03-09")));
+        assert!(!date("2021-03-09").is_before(&date("2021-03")));
+        assert!(!date("2021-04").is_before(&date("2021-03-31")));
+    }
+
+    #[test]
+    fn counts_months_including_both_ends() {
+        assert_eq!(date("2021-03").months_until(&date("2021-03")), 1);
+        assert_eq!(date("2021-03-31").months_until(&date("2021-04-01")), 2);
+        assert_eq!(date("2020-11").months_until(&date("2022-02")), 16);
+        assert_eq!(date("2021-03").months_until(&date("2020-03")), 0);
+    }
+
+    #[test]
+    fn computes_durations() {
+        let mut record = doc! {"start": "2020-11", "end": "2022-02-15"};
+        Experience::compute(&mut record);
+        assert_eq!(
+            record.get_document("duration").unwrap(),
+            &doc! {"years": 1_i64, "months": 4_i64}
+        );
+
+        let mut record = doc! {"start": "2000-01", "end": Bson::Null};
+        Experience::compute(&mut record);
+        assert!(
+            record
+                .get_document("duration")
+                .unwrap()
+                .get_i64("years")
+                .unwrap()
+                > 20
+        );
+
+        let mut record = doc! {"start": "2000-01"};
+        Experience::compute(&mut record);
+        assert!(!record.contains_key("duration"));
+    }
+}
@@ -0,0 +1,79 @@
+use super::serialize_date;
+use mongodb::bson::{Bson, DateTime};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// When and by whom a record was created, last updated and deleted and how
+/// often it changed, stamped by the repository on every write. Whatever
+/// clients send for these is ignored.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Metadata {
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_date",
+        deserialize_with = "stored_date"
+    )]
+    pub created_at: Option<DateTime>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_date",
+        deserialize_with = "stored_date"
+    )]
+    pub updated_at: Option<DateTime>,
+    /// The ID of the user, `None` for records the system created itself.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "stored_string"
+    )]
+    pub created_by: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "stored_string"
+    )]
+    pub updated_by: Option<String>,
+    /// Set while the record is in the trash.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_date",
+        deserialize_with = "stored_date"
+    )]
+    pub deleted_at: Option<DateTime>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "stored_string"
+    )]
+    pub deleted_by: Option<String>,
+    /// Incremented on every update, and sent as the `ETag` of the record.
+    #[serde(default, deserialize_with = "stored_version")]
+    pub version: i64,
+}
+
+// Anything else came from a client rather than the repository, so rather than
+// failing the request it is dropped.
+
+fn stored_date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime>, D::Error> {
+    Ok(match Bson::deserialize(deserializer)? {
+        Bson::DateTime(date) => Some(date),
+        _ => None,
+    })
+}
+
+fn stored_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(match Bson::deserialize(deserializer)? {
+        Bson::String(string) => Some(string),
+        _ => None,
+    })
+}
+
+fn stored_version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    Ok(match Bson::deserialize(deserializer)? {
+        Bson::Int32(version) => version as i64,
+        Bson::Int64(version) => version,
+        _ => 0,
+    })
+}
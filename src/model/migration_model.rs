@@ -0,0 +1,27 @@
+use super::{metadata_model::Metadata, serialize_object_id, unique_index, IndexedModel};
+use mongodb::{
+    bson::{doc, oid::ObjectId},
+    IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+/// A data migration run at startup, recorded once it completed so it isn't
+/// run again. Its `created_at` tells when.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Migration {
+    #[serde(
+        rename(deserialize = "_id", serialize = "id"),
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub _id: Option<ObjectId>,
+    pub name: String,
+    #[serde(flatten)]
+    pub metadata: Metadata,
+}
+
+impl IndexedModel for Migration {
+    fn indexes() -> Vec<IndexModel> {
+        vec![unique_index(doc! {"name": 1})]
+    }
+}
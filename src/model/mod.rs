@@ -1,11 +1,29 @@
-use mongodb::bson::oid::ObjectId;
-use serde::Serializer;
+use metadata_model::Metadata;
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime, Document},
+    options::IndexOptions,
+    IndexModel,
+};
+use serde::{ser::Error, Serializer};
+use std::time::Duration;
 
+pub mod api_key_model;
+pub mod audit_model;
+pub mod bootstrap_model;
 pub mod detail_model;
 pub mod experience_model;
+pub mod metadata_model;
+pub mod migration_model;
+pub mod oidc_model;
+pub mod page_model;
+pub mod password_reset_model;
 pub mod project_model;
+pub mod revision_model;
 pub mod tech_stack_model;
+pub mod token_model;
+pub mod totp_model;
 pub mod user_model;
+pub mod validation_model;
 
 fn serialize_object_id<S>(object_id: &Option<ObjectId>, serializer: S) -> Result<S::Ok, S::Error>
 where
@@ -16,3 +34,59 @@ where
         None => serializer.serialize_none(),
     }
 }
+
+/// Serializes a date as an RFC 3339 string rather than extended JSON.
+fn serialize_date<S>(date: &Option<DateTime>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match date.map(|date| date.try_to_rfc3339_string()) {
+        Some(Ok(date)) => serializer.serialize_some(&date),
+        Some(Err(err)) => Err(S::Error::custom(err)),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Declares the indexes a repository maintains for a model's records.
+pub trait IndexedModel {
+    fn indexes() -> Vec<IndexModel> {
+        Vec::new()
+    }
+}
+
+/// A model carrying the [`Metadata`] the repository stamps.
+pub trait StampedModel {
+    fn metadata(&self) -> &Metadata;
+}
+
+/// The fields list endpoints let clients filter, sort and project a model's
+/// records by, besides `id`.
+pub trait QueryableModel {
+    const FIELDS: &'static [&'static str];
+    /// The order records are listed in when a request doesn't `sort` them,
+    /// before the ID that breaks ties.
+    const DEFAULT_SORT: &'static [(&'static str, i32)] = &[];
+
+    /// Adds the fields derived from the stored ones to a `record` about to be
+    /// sent, which may be projected.
+    fn compute(_record: &mut Document) {}
+}
+
+fn index(keys: Document) -> IndexModel {
+    IndexModel::builder().keys(keys).build()
+}
+
+fn unique_index(keys: Document) -> IndexModel {
+    IndexModel::builder()
+        .keys(keys)
+        .options(IndexOptions::builder().unique(true).build())
+        .build()
+}
+
+/// A TTL index removing documents once the date in `field` has passed.
+fn expiry_index(field: &str) -> IndexModel {
+    IndexModel::builder()
+        .keys(doc! {field: 1})
+        .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+        .build()
+}
@@ -0,0 +1,50 @@
+use super::{expiry_index, serialize_object_id, unique_index, IndexedModel};
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime},
+    IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+/// A pending authorization request, kept until the identity provider redirects
+/// back with the matching `state`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OidcLogin {
+    #[serde(
+        rename(deserialize = "_id", serialize = "id"),
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub _id: Option<ObjectId>,
+    pub state_hash: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub expires_at: DateTime,
+}
+
+/// Query string the identity provider redirects back with.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OidcCallback {
+    pub state: String,
+    pub code: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// The `id_token` claims used to find the user; `iss`, `aud` and `exp` are
+/// checked while decoding.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub nonce: Option<String>,
+}
+
+impl IndexedModel for OidcLogin {
+    fn indexes() -> Vec<IndexModel> {
+        vec![
+            unique_index(doc! {"state_hash": 1}),
+            expiry_index("expires_at"),
+        ]
+    }
+}
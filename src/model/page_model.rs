@@ -0,0 +1,11 @@
+use serde::Serialize;
+
+/// One page of a list endpoint's records.
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// How many records match the filter across every page.
+    pub total: u64,
+    /// Where the next page starts, if there is one.
+    pub next_cursor: Option<String>,
+}
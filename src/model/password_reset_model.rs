@@ -0,0 +1,40 @@
+use super::{expiry_index, index, serialize_object_id, unique_index, IndexedModel};
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime},
+    IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordResetToken {
+    #[serde(
+        rename(deserialize = "_id", serialize = "id"),
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub _id: Option<ObjectId>,
+    pub user_id: String,
+    pub token_hash: String,
+    pub expires_at: DateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordResetConfirm {
+    pub token: String,
+    pub new_password: String,
+}
+
+impl IndexedModel for PasswordResetToken {
+    fn indexes() -> Vec<IndexModel> {
+        vec![
+            unique_index(doc! {"token_hash": 1}),
+            index(doc! {"user_id": 1}),
+            expiry_index("expires_at"),
+        ]
+    }
+}
@@ -1,4 +1,6 @@
-use super::serialize_object_id;
+use super::{
+    metadata_model::Metadata, serialize_object_id, IndexedModel, QueryableModel, StampedModel,
+};
 use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 
@@ -14,7 +16,10 @@ pub struct Project {
     pub description: String,
     pub repo: String,
     pub url: String,
+    /// IDs of `TechStack` records.
     pub tech_stack: Vec<String>,
+    #[serde(flatten)]
+    pub metadata: Metadata,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -30,3 +35,15 @@ pub struct ProjectUpdate {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tech_stack: Option<Vec<String>>,
 }
+
+impl IndexedModel for Project {}
+
+impl QueryableModel for Project {
+    const FIELDS: &'static [&'static str] = &["name", "description", "repo", "url", "tech_stack"];
+}
+
+impl StampedModel for Project {
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
@@ -0,0 +1,49 @@
+use super::{metadata_model::Metadata, serialize_object_id, unique_index, IndexedModel};
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson, Document},
+    IndexModel,
+};
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+
+/// A version of a record of model `T` as it was before an update replaced
+/// it. Its `created_at` and `created_by` tell when and by whom.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct Revision<T> {
+    #[serde(
+        rename(deserialize = "_id", serialize = "id"),
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub _id: Option<ObjectId>,
+    pub record_id: String,
+    /// The `version` the record was at.
+    pub revision: i64,
+    pub record: Document,
+    #[serde(flatten)]
+    pub metadata: Metadata,
+    #[serde(skip)]
+    pub model: PhantomData<fn() -> T>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevisionDiffQuery {
+    /// The version to compare with, the current one if `None`.
+    pub against: Option<i64>,
+}
+
+/// A field whose value differs between two versions of a record, `None`
+/// where the version doesn't have it.
+#[derive(Debug, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub from: Option<Bson>,
+    pub to: Option<Bson>,
+}
+
+impl<T> IndexedModel for Revision<T> {
+    fn indexes() -> Vec<IndexModel> {
+        vec![unique_index(doc! {"record_id": 1, "revision": 1})]
+    }
+}
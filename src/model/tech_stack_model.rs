@@ -1,5 +1,11 @@
-use super::serialize_object_id;
-use mongodb::bson::oid::ObjectId;
+use super::{
+    index, metadata_model::Metadata, serialize_object_id, unique_index, IndexedModel,
+    QueryableModel, StampedModel,
+};
+use mongodb::{
+    bson::{doc, oid::ObjectId},
+    IndexModel,
+};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -12,6 +18,8 @@ pub struct TechStack {
     pub _id: Option<ObjectId>,
     pub name: String,
     pub category: String,
+    #[serde(flatten)]
+    pub metadata: Metadata,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -21,3 +29,28 @@ pub struct TechStackUpdate {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub category: Option<String>,
 }
+
+impl IndexedModel for TechStack {
+    fn indexes() -> Vec<IndexModel> {
+        vec![unique_index(doc! {"name": 1}), index(doc! {"category": 1})]
+    }
+}
+
+impl QueryableModel for TechStack {
+    const FIELDS: &'static [&'static str] = &["name", "category"];
+}
+
+impl StampedModel for TechStack {
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
+
+/// Query parameters of deleting a tech stack entry.
+#[derive(Debug, Deserialize)]
+pub struct TechStackDelete {
+    /// Remove the entry from the projects and experiences using it, instead
+    /// of refusing to delete it.
+    #[serde(default)]
+    pub cascade: bool,
+}
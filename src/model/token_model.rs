@@ -0,0 +1,130 @@
+use super::{
+    expiry_index, index, serialize_object_id, unique_index, user_model::Role, IndexedModel,
+};
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime},
+    IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    /// Always `token_service::ACCESS_TOKEN`.
+    pub aud: String,
+    pub id: String,
+    pub email: String,
+    pub role: Role,
+    pub iat: u64,
+    /// `iat` in milliseconds, so a revocation in the same second as the token
+    /// was issued can tell which came first. Missing from older tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat_ms: Option<u64>,
+    pub exp: u64,
+    pub jti: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshToken {
+    #[serde(
+        rename(deserialize = "_id", serialize = "id"),
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub _id: Option<ObjectId>,
+    pub user_id: String,
+    pub token_hash: String,
+    pub expires_at: DateTime,
+}
+
+/// Claims of single purpose tokens: the challenge `/users/auth` hands out
+/// instead of an access token while a second factor is pending, invitations
+/// and email verification links.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ActionClaims {
+    /// The purpose of the token, e.g. `token_service::INVITATION`.
+    pub aud: String,
+    /// ID of the user the token was issued for, or the invited email address.
+    pub sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    pub exp: u64,
+    /// Tells tokens apart, so one that only works once can be redeemed.
+    pub jti: String,
+}
+
+/// A revoked access token. When `jti` is `None` every token of `user_id`
+/// issued up to `revoked_before` is revoked instead.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevokedToken {
+    #[serde(
+        rename(deserialize = "_id", serialize = "id"),
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub _id: Option<ObjectId>,
+    pub jti: Option<String>,
+    pub user_id: String,
+    pub revoked_before: DateTime,
+    pub expires_at: DateTime,
+}
+
+/// An action token that has been used up, kept until it would have expired
+/// on its own.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RedeemedToken {
+    #[serde(
+        rename(deserialize = "_id", serialize = "id"),
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub _id: Option<ObjectId>,
+    pub jti: String,
+    pub expires_at: DateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogoutRequest {
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: String,
+    pub expires_in: u64,
+}
+
+impl IndexedModel for RefreshToken {
+    fn indexes() -> Vec<IndexModel> {
+        vec![
+            unique_index(doc! {"token_hash": 1}),
+            index(doc! {"user_id": 1}),
+            expiry_index("expires_at"),
+        ]
+    }
+}
+
+impl IndexedModel for RevokedToken {
+    fn indexes() -> Vec<IndexModel> {
+        vec![
+            index(doc! {"jti": 1}),
+            index(doc! {"user_id": 1, "revoked_before": 1}),
+            expiry_index("expires_at"),
+        ]
+    }
+}
+
+impl IndexedModel for RedeemedToken {
+    fn indexes() -> Vec<IndexModel> {
+        vec![unique_index(doc! {"jti": 1}), expiry_index("expires_at")]
+    }
+}
@@ -1,10 +1,26 @@
-use super::serialize_object_id;
+use super::{
+    metadata_model::Metadata, serialize_object_id, unique_index, IndexedModel, StampedModel,
+};
+use crate::service::config_service::env_or;
 use argon2::{
     password_hash::{rand_core::OsRng, Error, PasswordHasher, SaltString},
-    Argon2, PasswordHash, PasswordVerifier,
+    Algorithm, Argon2, Params, ParamsBuilder, PasswordHash, PasswordVerifier, Version,
+};
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime, Document},
+    IndexModel,
 };
-use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::OnceLock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    Editor,
+    #[default]
+    Viewer,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct User {
@@ -17,12 +33,83 @@ pub struct User {
     pub email: String,
     #[serde(skip_serializing_if = "String::is_empty")]
     pub password: String,
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default)]
+    pub email_verified: bool,
+    #[serde(default)]
+    pub failed_logins: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked_until: Option<DateTime>,
+    #[serde(default)]
+    pub totp_enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp_secret: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp_pending_secret: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp_last_step: Option<i64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recovery_codes: Vec<String>,
+    /// The address of a trashed user, whose `email` is replaced by
+    /// [`User::tombstone`] until they're restored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_email: Option<String>,
+    #[serde(flatten)]
+    pub metadata: Metadata,
+}
+
+impl User {
+    /// The update freeing the address of the trashed user `id` for new
+    /// accounts, keeping it in `deleted_email` for a restore.
+    pub fn tombstone(id: &ObjectId, email: &str) -> Document {
+        doc! {"email": format!("deleted:{}", id.to_hex()), "deleted_email": email}
+    }
+
+    /// Clears every credential before the user is sent in a response.
+    pub fn redact(&mut self) {
+        self.password.clear();
+        self.totp_secret = None;
+        self.totp_pending_secret = None;
+        self.totp_last_step = None;
+        self.recovery_codes.clear();
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserUpdate {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserRegistration {
+    /// Only read when bootstrapping the first user, otherwise the invited
+    /// email address is used.
+    #[serde(default)]
+    pub email: String,
+    pub password: String,
+    #[serde(default)]
+    pub invitation: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Invitation {
+    pub email: String,
+    #[serde(default)]
+    pub role: Role,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmailVerificationRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmailVerification {
+    pub token: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -31,19 +118,154 @@ pub struct PasswordUpdate {
     pub new_password: String,
 }
 
+/// Argon2 settings read once from `ARGON2_M_COST`, `ARGON2_T_COST`,
+/// `ARGON2_P_COST` and the optional peppers.
+///
+/// Peppers are listed in `PASSWORD_PEPPERS` as `<id>=<secret>` pairs, and
+/// `PASSWORD_PEPPER_ID` names the one new hashes use. A single
+/// `PASSWORD_PEPPER` is still accepted under `PASSWORD_PEPPER_ID`, which
+/// defaults to `1` then. Peppered hashes carry the pepper's ID as their
+/// `keyid` parameter, so rotating means adding a new pepper and pointing
+/// `PASSWORD_PEPPER_ID` at it while the old ones stay listed.
+struct HashConfig {
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    pepper_id: Option<String>,
+    peppers: HashMap<String, String>,
+}
+
+impl HashConfig {
+    fn from_env() -> Result<Self, String> {
+        let mut peppers = HashMap::new();
+        let listed = dotenv::var("PASSWORD_PEPPERS").unwrap_or_default();
+        for entry in listed
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            match entry.split_once('=') {
+                Some((id, pepper)) if !id.is_empty() && !pepper.is_empty() => {
+                    peppers.insert(id.to_owned(), pepper.to_owned());
+                }
+                _ => return Err("PASSWORD_PEPPERS must list <id>=<secret> pairs".to_owned()),
+            }
+        }
+        let mut pepper_id = dotenv::var("PASSWORD_PEPPER_ID").ok();
+        if let Ok(pepper) = dotenv::var("PASSWORD_PEPPER") {
+            let id = pepper_id.get_or_insert_with(|| "1".to_owned());
+            peppers.insert(id.clone(), pepper);
+        }
+        match &pepper_id {
+            Some(id) if !peppers.contains_key(id) => {
+                return Err(format!("PASSWORD_PEPPER_ID {} names no pepper", id))
+            }
+            None if !peppers.is_empty() => {
+                return Err("PASSWORD_PEPPER_ID must name the pepper to hash with".to_owned())
+            }
+            _ => (),
+        }
+
+        Ok(HashConfig {
+            m_cost: env_or("ARGON2_M_COST", Params::DEFAULT_M_COST),
+            t_cost: env_or("ARGON2_T_COST", Params::DEFAULT_T_COST),
+            p_cost: env_or("ARGON2_P_COST", Params::DEFAULT_P_COST),
+            pepper_id,
+            peppers,
+        })
+    }
+}
+
+fn hash_config() -> Result<&'static HashConfig, Error> {
+    static CONFIG: OnceLock<Result<HashConfig, String>> = OnceLock::new();
+    CONFIG
+        .get_or_init(HashConfig::from_env)
+        .as_ref()
+        .map_err(|_| Error::Crypto)
+}
+
+/// Checks the password hashing settings, so a misconfigured pepper stops the
+/// server at startup rather than failing every login.
+pub fn check_hash_config() -> Result<(), String> {
+    HashConfig::from_env().map(|_| ())
+}
+
+/// The hasher for a `keyid`, failing with [`Error::Crypto`] when it names a
+/// pepper that isn't configured.
+fn hasher<'a>(keyid: &[u8], params: Params) -> Result<Argon2<'a>, Error> {
+    if keyid.is_empty() {
+        return Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params));
+    }
+    let pepper = std::str::from_utf8(keyid)
+        .ok()
+        .and_then(|id| hash_config().ok()?.peppers.get(id))
+        .ok_or(Error::Crypto)?;
+
+    Ok(Argon2::new_with_secret(
+        pepper.as_bytes(),
+        Algorithm::Argon2id,
+        Version::V0x13,
+        params,
+    )?)
+}
+
 pub fn encrypt_password(password: &str) -> Result<String, Error> {
+    let config = hash_config()?;
+    let mut builder = ParamsBuilder::new();
+    builder
+        .m_cost(config.m_cost)?
+        .t_cost(config.t_cost)?
+        .p_cost(config.p_cost)?;
+    if let Some(id) = &config.pepper_id {
+        builder.keyid(id.as_bytes())?;
+    }
+    let params = builder.params()?;
     let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
+    let argon2 = hasher(params.keyid(), params.clone())?;
 
     Ok(argon2
         .hash_password(password.as_bytes(), &salt)?
         .to_string())
 }
 
+/// Checks a password against a hash using the pepper the hash names.
 pub fn verify_password(password: &str, hash_password: &str) -> Result<bool, Error> {
     let parsed_hash = PasswordHash::new(hash_password)?;
+    let params = Params::try_from(&parsed_hash)?;
 
-    Ok(Argon2::default()
+    Ok(hasher(params.keyid(), Params::default())?
         .verify_password(password.as_bytes(), &parsed_hash)
         .is_ok())
 }
+
+/// Whether a hash was created with weaker parameters or a different pepper
+/// than currently configured, and should be replaced after the next login.
+pub fn needs_rehash(hash_password: &str) -> Result<bool, Error> {
+    let config = hash_config()?;
+    let parsed_hash = PasswordHash::new(hash_password)?;
+    let params = Params::try_from(&parsed_hash)?;
+    let keyid = config
+        .pepper_id
+        .as_ref()
+        .map(|id| id.as_bytes())
+        .unwrap_or_default();
+
+    Ok(parsed_hash.algorithm != Algorithm::Argon2id.ident()
+        || parsed_hash.version != Some(Version::V0x13.into())
+        || params.m_cost() < config.m_cost
+        || params.t_cost() < config.t_cost
+        || params.p_cost() < config.p_cost
+        || params.keyid() != keyid)
+}
+
+impl IndexedModel for User {
+    fn indexes() -> Vec<IndexModel> {
+        vec![unique_index(doc! {"email": 1})]
+    }
+}
+
+impl StampedModel for User {
+    fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValidationError {
+    pub field: String,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub fn new(field: &str, message: &str) -> Self {
+        ValidationError {
+            field: field.to_owned(),
+            message: message.to_owned(),
+        }
+    }
+}
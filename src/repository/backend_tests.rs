@@ -0,0 +1,189 @@
+//! Checks every storage backend has to pass, run by each of them through
+//! [`backend_tests!`].
+
+use super::{RecordQuery, Repository};
+use crate::model::{
+    oidc_model::OidcLogin,
+    user_model::{Role, User},
+};
+use actix_web::http::StatusCode;
+use mongodb::bson::{doc, from_document, DateTime};
+
+pub fn user(email: &str) -> User {
+    from_document(doc! {"email": email, "password": "unused"}).unwrap()
+}
+
+pub async fn stores_and_queries_records(db: &dyn Repository<User>) {
+    let id = db
+        .create_record(user("a@x.io"), None)
+        .await
+        .unwrap()
+        .inserted_id;
+    db.create_record(user("b@x.io"), Some(&id)).await.unwrap();
+
+    let created = db.get_record(&id).await.unwrap();
+    assert_eq!(created.email, "a@x.io");
+    assert_eq!(created.metadata.version, 1);
+    assert!(created.metadata.created_at.is_some());
+    let found = db.find_one_record(doc! {"email": "b@x.io"}).await.unwrap();
+    assert_eq!(found.metadata.created_by, Some(id.clone()));
+    assert_eq!(db.count_records(doc! {}).await.unwrap(), 2);
+    assert_eq!(db.get_all_record().await.unwrap().len(), 2);
+
+    let page = db
+        .query_records(RecordQuery {
+            filter: doc! {"email": {"$in": ["a@x.io", "b@x.io"]}},
+            sort: doc! {"email": -1},
+            projection: Some(vec!["email".to_owned()]),
+            skip: 1,
+            limit: Some(5),
+        })
+        .await
+        .unwrap();
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].get_str("email"), Ok("a@x.io"));
+    assert!(page[0].contains_key("_id"));
+    assert!(!page[0].contains_key("password"));
+
+    let err = db.get_record("not an id").await.unwrap_err();
+    assert_eq!(err.0, StatusCode::BAD_REQUEST);
+    let err = db
+        .find_one_record(doc! {"email": "c@x.io"})
+        .await
+        .unwrap_err();
+    assert_eq!(err.0, StatusCode::NOT_FOUND);
+}
+
+pub async fn updates_records(db: &dyn Repository<User>) {
+    let id = db
+        .create_record(user("a@x.io"), None)
+        .await
+        .unwrap()
+        .inserted_id;
+    let other = db
+        .create_record(user("b@x.io"), None)
+        .await
+        .unwrap()
+        .inserted_id;
+
+    let updated = db
+        .update_record(&id, doc! {"role": "viewer"}, Some(&other))
+        .await
+        .unwrap();
+    assert_eq!(updated.matched_count, 1);
+    let record = db.get_record(&id).await.unwrap();
+    assert_eq!(record.role, Role::Viewer);
+    assert_eq!(record.metadata.version, 2);
+    assert_eq!(record.metadata.updated_by, Some(other.clone()));
+
+    let updated = db
+        .update_many_records(doc! {}, doc! {"email_verified": true}, None)
+        .await
+        .unwrap();
+    assert_eq!(updated.matched_count, 2);
+    let record = db.get_record(&id).await.unwrap();
+    assert!(record.email_verified);
+    assert_eq!(record.metadata.version, 3);
+
+    // Bookkeeping isn't an edit, so the version stays.
+    db.update_unstamped(doc! {"email": "a@x.io"}, doc! {"failed_logins": 3})
+        .await
+        .unwrap();
+    let record = db.get_record(&id).await.unwrap();
+    assert_eq!(record.failed_logins, 3);
+    assert_eq!(record.metadata.version, 3);
+}
+
+pub async fn honours_unique_indexes(db: &dyn Repository<User>) {
+    let id = db
+        .create_record(user("a@x.io"), None)
+        .await
+        .unwrap()
+        .inserted_id;
+    db.create_record(user("b@x.io"), None).await.unwrap();
+
+    let err = db.create_record(user("a@x.io"), None).await.unwrap_err();
+    assert_eq!(err.0, StatusCode::CONFLICT);
+    let err = db
+        .update_record(&id, doc! {"email": "b@x.io"}, None)
+        .await
+        .unwrap_err();
+    assert_eq!(err.0, StatusCode::CONFLICT);
+    assert_eq!(db.get_record(&id).await.unwrap().email, "a@x.io");
+    assert_eq!(db.count_records(doc! {}).await.unwrap(), 2);
+}
+
+pub async fn deletes_records(db: &dyn Repository<User>) {
+    let id = db
+        .create_record(user("a@x.io"), None)
+        .await
+        .unwrap()
+        .inserted_id;
+    db.create_record(user("b@x.io"), None).await.unwrap();
+    db.create_record(user("c@x.io"), None).await.unwrap();
+
+    assert_eq!(db.delete_record(&id).await.unwrap().deleted_count, 1);
+    assert_eq!(
+        db.get_record(&id).await.unwrap_err().0,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(db.delete_record(&id).await.unwrap().deleted_count, 0);
+    let deleted = db
+        .delete_many_records(doc! {"email": "b@x.io"})
+        .await
+        .unwrap();
+    assert_eq!(deleted.deleted_count, 1);
+    let deleted = db.delete_many_records(doc! {}).await.unwrap();
+    assert_eq!(deleted.deleted_count, 1);
+    assert_eq!(db.count_records(doc! {}).await.unwrap(), 0);
+}
+
+pub async fn drops_expired_records(db: &dyn Repository<OidcLogin>) {
+    let now = DateTime::now().timestamp_millis();
+    for (state_hash, expires_at) in [("past", now - 1), ("future", now + 60_000)] {
+        let login = OidcLogin {
+            _id: None,
+            state_hash: state_hash.to_owned(),
+            nonce: String::new(),
+            code_verifier: String::new(),
+            expires_at: DateTime::from_millis(expires_at),
+        };
+        db.create_record(login, None).await.unwrap();
+    }
+
+    let logins = db.get_all_record().await.unwrap();
+    assert_eq!(logins.len(), 1);
+    assert_eq!(logins[0].state_hash, "future");
+}
+
+/// Runs the checks above against a backend, given async expressions for
+/// empty `User` and `OidcLogin` repositories.
+macro_rules! backend_tests {
+    ($users:expr, $logins:expr) => {
+        #[actix_web::test]
+        async fn stores_and_queries_records() {
+            $crate::repository::backend_tests::stores_and_queries_records(&$users.await).await;
+        }
+
+        #[actix_web::test]
+        async fn updates_records() {
+            $crate::repository::backend_tests::updates_records(&$users.await).await;
+        }
+
+        #[actix_web::test]
+        async fn honours_unique_indexes() {
+            $crate::repository::backend_tests::honours_unique_indexes(&$users.await).await;
+        }
+
+        #[actix_web::test]
+        async fn deletes_records() {
+            $crate::repository::backend_tests::deletes_records(&$users.await).await;
+        }
+
+        #[actix_web::test]
+        async fn drops_expired_records() {
+            $crate::repository::backend_tests::drops_expired_records(&$logins.await).await;
+        }
+    };
+}
+pub(crate) use backend_tests;
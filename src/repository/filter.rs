@@ -0,0 +1,333 @@
+//! Evaluates MongoDB query, sort and projection documents against records
+//! for the backends that can't hand them to the database.
+
+use mongodb::bson::{Bson, Document};
+use std::cmp::Ordering;
+
+fn number(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Int32(value) => Some(*value as f64),
+        Bson::Int64(value) => Some(*value as f64),
+        Bson::Double(value) => Some(*value),
+        _ => None,
+    }
+}
+
+fn truthy(value: &Bson) -> bool {
+    match value {
+        Bson::Boolean(value) => *value,
+        Bson::Null => false,
+        value => number(value).is_none_or(|number| number != 0.0),
+    }
+}
+
+/// Orders two values of the same kind, numbers of any width comparing by
+/// value. Values of different kinds are unordered.
+pub fn compare(a: &Bson, b: &Bson) -> Option<Ordering> {
+    if let (Some(a), Some(b)) = (number(a), number(b)) {
+        return a.partial_cmp(&b);
+    }
+    match (a, b) {
+        (Bson::String(a), Bson::String(b)) => Some(a.cmp(b)),
+        (Bson::DateTime(a), Bson::DateTime(b)) => Some(a.cmp(b)),
+        (Bson::Boolean(a), Bson::Boolean(b)) => Some(a.cmp(b)),
+        (Bson::ObjectId(a), Bson::ObjectId(b)) => Some(a.cmp(b)),
+        (a, b) if a == b => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+/// Looks up a possibly dotted `path` in `record`.
+pub fn lookup<'a>(record: &'a Document, path: &str) -> Option<&'a Bson> {
+    let (head, rest) = match path.split_once('.') {
+        Some((head, rest)) => (head, Some(rest)),
+        None => (path, None),
+    };
+    match (record.get(head), rest) {
+        (Some(Bson::Document(nested)), Some(rest)) => lookup(nested, rest),
+        (value, None) => value,
+        _ => None,
+    }
+}
+
+/// Whether `value` equals `operand`, where an array matches if any of its
+/// elements does and a missing field equals `null`.
+pub fn equals(value: Option<&Bson>, operand: &Bson) -> bool {
+    match value {
+        None => *operand == Bson::Null,
+        Some(Bson::Array(items)) if !matches!(operand, Bson::Array(_)) => items
+            .iter()
+            .any(|item| compare(item, operand) == Some(Ordering::Equal)),
+        Some(value) => compare(value, operand) == Some(Ordering::Equal),
+    }
+}
+
+fn ordered(value: Option<&Bson>, operand: &Bson, accept: fn(Ordering) -> bool) -> bool {
+    match value {
+        Some(Bson::Array(items)) => items
+            .iter()
+            .any(|item| compare(item, operand).is_some_and(accept)),
+        Some(value) => compare(value, operand).is_some_and(accept),
+        None => false,
+    }
+}
+
+fn operand_array<'a>(operator: &str, operand: &'a Bson) -> Result<&'a Vec<Bson>, String> {
+    match operand {
+        Bson::Array(items) => Ok(items),
+        _ => Err(format!("{} needs an array", operator)),
+    }
+}
+
+fn matches_operator(value: Option<&Bson>, operator: &str, operand: &Bson) -> Result<bool, String> {
+    Ok(match operator {
+        "$eq" => equals(value, operand),
+        "$ne" => !equals(value, operand),
+        "$gt" => ordered(value, operand, Ordering::is_gt),
+        "$gte" => ordered(value, operand, Ordering::is_ge),
+        "$lt" => ordered(value, operand, Ordering::is_lt),
+        "$lte" => ordered(value, operand, Ordering::is_le),
+        "$in" => operand_array(operator, operand)?
+            .iter()
+            .any(|item| equals(value, item)),
+        "$nin" => !operand_array(operator, operand)?
+            .iter()
+            .any(|item| equals(value, item)),
+        "$exists" => value.is_some() == truthy(operand),
+        _ => return Err(format!("Unsupported operator {}", operator)),
+    })
+}
+
+fn matches_field(value: Option<&Bson>, condition: &Bson) -> Result<bool, String> {
+    match condition {
+        Bson::Document(operators) if operators.keys().any(|key| key.starts_with('$')) => {
+            for (operator, operand) in operators {
+                if !matches_operator(value, operator, operand)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        condition => Ok(equals(value, condition)),
+    }
+}
+
+fn sub_filters(operator: &str, operand: &Bson) -> Result<Vec<Document>, String> {
+    operand_array(operator, operand)?
+        .iter()
+        .map(|filter| match filter {
+            Bson::Document(filter) => Ok(filter.clone()),
+            _ => Err(format!("{} needs an array of documents", operator)),
+        })
+        .collect()
+}
+
+/// Evaluates the subset of the MongoDB query language repositories support
+/// against `record`: equality, comparisons, `$in`, `$nin`, `$exists` and the
+/// `$and`, `$or` and `$nor` combinators.
+pub fn matches(record: &Document, filter: &Document) -> Result<bool, String> {
+    for (key, condition) in filter {
+        let matched = match key.as_str() {
+            "$and" => {
+                let filters = sub_filters(key, condition)?;
+                filters.iter().try_fold(true, |all, filter| {
+                    Ok::<_, String>(all && matches(record, filter)?)
+                })?
+            }
+            "$or" | "$nor" => {
+                let filters = sub_filters(key, condition)?;
+                let any = filters.iter().try_fold(false, |any, filter| {
+                    Ok::<_, String>(any || matches(record, filter)?)
+                })?;
+                any == (key == "$or")
+            }
+            operator if operator.starts_with('$') => {
+                return Err(format!("Unsupported operator {}", operator))
+            }
+            field => matches_field(lookup(record, field), condition)?,
+        };
+        if !matched {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Where MongoDB sorts values of each kind relative to the others.
+fn kind_rank(value: Option<&Bson>) -> u8 {
+    match value {
+        None | Some(Bson::Null) => 0,
+        Some(value) if number(value).is_some() => 1,
+        Some(Bson::String(_)) => 2,
+        Some(Bson::Document(_)) => 3,
+        Some(Bson::Array(_)) => 4,
+        Some(Bson::ObjectId(_)) => 5,
+        Some(Bson::Boolean(_)) => 6,
+        Some(Bson::DateTime(_)) => 7,
+        Some(_) => 8,
+    }
+}
+
+fn sort_order(a: Option<&Bson>, b: Option<&Bson>) -> Ordering {
+    kind_rank(a).cmp(&kind_rank(b)).then_with(|| match (a, b) {
+        (Some(a), Some(b)) => compare(a, b).unwrap_or(Ordering::Equal),
+        _ => Ordering::Equal,
+    })
+}
+
+/// Sorts `records` by a MongoDB sort document, with `-1` for descending.
+/// The sort is stable, so records equal on every field keep their order.
+pub fn sort(records: &mut [Document], sort: &Document) {
+    records.sort_by(|a, b| {
+        sort.iter()
+            .map(|(field, direction)| {
+                let order = sort_order(lookup(a, field), lookup(b, field));
+                match number(direction).is_some_and(|direction| direction < 0.0) {
+                    true => order.reverse(),
+                    false => order,
+                }
+            })
+            .find(|order| order.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+}
+
+/// Keeps only `_id` and the top-level `fields` of `record`.
+pub fn project(record: Document, fields: &[String]) -> Document {
+    record
+        .into_iter()
+        .filter(|(key, _)| key == "_id" || fields.contains(key))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::{doc, oid::ObjectId, DateTime};
+
+    fn record() -> Document {
+        doc! {
+            "_id": ObjectId::parse_str("6ad4abb3557298608d132500").unwrap(),
+            "name": "ava",
+            "rank": 2,
+            "score": 2.5,
+            "tags": ["rust", "web"],
+            "empty": null,
+            "meta": {"owner": "a@x.io"},
+            "at": DateTime::from_millis(1_000),
+        }
+    }
+
+    /// Each filter with whether MongoDB matches [`record`] with it.
+    #[test]
+    fn matches_like_mongodb() {
+        let cases = [
+            (doc! {}, true),
+            (doc! {"name": "ava"}, true),
+            (doc! {"name": "Ava"}, false),
+            (doc! {"rank": 2.0}, true),
+            (doc! {"rank": {"$eq": 2_i64}}, true),
+            (doc! {"rank": "2"}, false),
+            (doc! {"rank": {"$ne": 3}}, true),
+            (doc! {"rank": {"$gt": 1, "$lt": 3}}, true),
+            (doc! {"rank": {"$gte": 2, "$lte": 2}}, true),
+            (doc! {"rank": {"$gt": 2}}, false),
+            // Comparisons only match values of the same kind.
+            (doc! {"rank": {"$gt": "1"}}, false),
+            (doc! {"name": {"$lt": 3}}, false),
+            (doc! {"at": {"$gt": DateTime::from_millis(0)}}, true),
+            (
+                doc! {"_id": ObjectId::parse_str("6ad4abb3557298608d132500").unwrap()},
+                true,
+            ),
+            // Missing fields and nulls equal null, and nothing else.
+            (doc! {"missing": null}, true),
+            (doc! {"empty": null}, true),
+            (doc! {"missing": {"$ne": null}}, false),
+            (doc! {"empty": {"$ne": null}}, false),
+            (doc! {"name": {"$ne": null}}, true),
+            (doc! {"missing": {"$gt": 0}}, false),
+            (doc! {"missing": {"$lt": 0}}, false),
+            (doc! {"missing": {"$in": [null, 1]}}, true),
+            (doc! {"missing": {"$nin": [1]}}, true),
+            (doc! {"missing": {"$exists": false}}, true),
+            (doc! {"empty": {"$exists": true}}, true),
+            (doc! {"empty": {"$exists": 0}}, false),
+            // Arrays match on any element, or as a whole.
+            (doc! {"tags": "rust"}, true),
+            (doc! {"tags": ["rust", "web"]}, true),
+            (doc! {"tags": ["web", "rust"]}, false),
+            (doc! {"tags": {"$in": ["go", "web"]}}, true),
+            (doc! {"tags": {"$nin": ["web"]}}, false),
+            (doc! {"tags": {"$gt": "s"}}, true),
+            (doc! {"meta.owner": "a@x.io"}, true),
+            (doc! {"meta.missing": null}, true),
+            (doc! {"name.missing": null}, true),
+            (doc! {"$and": [{"name": "ava"}, {"rank": 2}]}, true),
+            (doc! {"$and": [{"name": "ava"}, {"rank": 3}]}, false),
+            (doc! {"$or": [{"name": "eve"}, {"rank": 2}]}, true),
+            (doc! {"$or": [{"name": "eve"}, {"rank": 3}]}, false),
+            (doc! {"$nor": [{"name": "eve"}, {"rank": 3}]}, true),
+            (doc! {"$nor": [{"name": "ava"}]}, false),
+            (doc! {"name": "ava", "rank": 3}, false),
+        ];
+
+        for (filter, expected) in cases {
+            assert_eq!(matches(&record(), &filter), Ok(expected), "{}", filter);
+        }
+    }
+
+    #[test]
+    fn rejects_what_it_cant_evaluate() {
+        for filter in [
+            doc! {"name": {"$regex": "^a"}},
+            doc! {"$where": "true"},
+            doc! {"rank": {"$in": 2}},
+            doc! {"$or": {"rank": 2}},
+            doc! {"$and": [1]},
+        ] {
+            assert!(matches(&record(), &filter).is_err(), "{}", filter);
+        }
+    }
+
+    #[test]
+    fn sorts_like_mongodb() {
+        let mut records: Vec<Document> = [
+            doc! {"n": 0, "v": "b"},
+            doc! {"n": 1, "v": 2},
+            doc! {"n": 2},
+            doc! {"n": 3, "v": true},
+            doc! {"n": 4, "v": null},
+            doc! {"n": 5, "v": 1.5},
+            doc! {"n": 6, "v": "a"},
+            doc! {"n": 7, "v": DateTime::from_millis(0)},
+            doc! {"n": 8, "v": "b"},
+        ]
+        .into();
+        let order = |records: &[Document]| -> Vec<i32> {
+            records
+                .iter()
+                .map(|record| record.get_i32("n").unwrap())
+                .collect()
+        };
+
+        // Null and missing first, then numbers, strings, booleans and dates;
+        // ties keep their order.
+        sort(&mut records, &doc! {"v": 1});
+        assert_eq!(order(&records), [2, 4, 5, 1, 6, 0, 8, 3, 7]);
+
+        sort(&mut records, &doc! {"v": -1, "n": -1});
+        assert_eq!(order(&records), [7, 3, 8, 0, 6, 1, 5, 4, 2]);
+    }
+
+    #[test]
+    fn projects_top_level_fields_and_id() {
+        let projected = project(record(), &["name".to_owned(), "missing".to_owned()]);
+
+        assert_eq!(
+            projected,
+            doc! {"_id": record().get_object_id("_id").unwrap(), "name": "ava"}
+        );
+    }
+}
@@ -0,0 +1,325 @@
+use super::{
+    id_filter, version_of, DeleteResult, InsertResult, RecordQuery, Repository, UpdateResult,
+};
+use crate::model::{metadata_model::Metadata, revision_model::Revision};
+use actix_web::http::StatusCode;
+use async_trait::async_trait;
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use std::{marker::PhantomData, sync::Arc};
+
+/// How often an update is retried when the record changed since it was read.
+const UPDATE_ATTEMPTS: usize = 5;
+
+/// Keeps the version every record of another repository had before each
+/// update as a [`Revision`], and forgets them once the record is deleted.
+pub struct History<T> {
+    inner: Arc<dyn Repository<T>>,
+    revisions: Arc<dyn Repository<Revision<T>>>,
+}
+
+impl<T> History<T> {
+    pub fn init(
+        inner: Arc<dyn Repository<T>>,
+        revisions: Arc<dyn Repository<Revision<T>>>,
+    ) -> Self {
+        History { inner, revisions }
+    }
+
+    async fn current(&self, filter: Document) -> Result<Vec<Document>, (StatusCode, String)> {
+        self.inner
+            .query_records(RecordQuery {
+                filter,
+                ..Default::default()
+            })
+            .await
+    }
+
+    /// Stores the `previous` version of the record with `id` replaced on
+    /// behalf of `actor`, returning the ID of its revision. Fails with
+    /// `409 Conflict` if another update already archived that version.
+    async fn archive(
+        &self,
+        id: &ObjectId,
+        mut previous: Document,
+        actor: Option<&str>,
+    ) -> Result<String, (StatusCode, String)> {
+        previous.remove("_id");
+        let revision = Revision {
+            _id: None,
+            record_id: id.to_hex(),
+            revision: version_of(&previous),
+            record: previous,
+            metadata: Metadata::default(),
+            model: PhantomData,
+        };
+        Ok(self
+            .revisions
+            .create_record(revision, actor)
+            .await?
+            .inserted_id)
+    }
+
+    /// Applies `new_record` to each record matching `filter` only while it
+    /// still has the version read. That version is archived first, so the
+    /// update never goes through without it, and the revision is dropped
+    /// again if the record changed in the meantime. Racing updates are
+    /// kept apart by the unique index on `record_id` and `revision`.
+    async fn update_each(
+        &self,
+        filter: Document,
+        new_record: Document,
+        actor: Option<&str>,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        let mut result = UpdateResult {
+            matched_count: 0,
+            modified_count: 0,
+        };
+        for mut previous in self.current(filter.clone()).await? {
+            let id = match previous.get_object_id("_id") {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+            let mut attempts = 0;
+            loop {
+                let version = match version_of(&previous) {
+                    0 => Bson::Null,
+                    version => Bson::Int64(version),
+                };
+                let archived = match self.archive(&id, previous, actor).await {
+                    Ok(revision_id) => Some(revision_id),
+                    Err((StatusCode::CONFLICT, _)) => None,
+                    Err(err) => return Err(err),
+                };
+                if let Some(revision_id) = archived {
+                    let unchanged = doc! {"_id": id, "version": version, "$and": [filter.clone()]};
+                    let updated = self
+                        .inner
+                        .update_many_records(unchanged, new_record.clone(), actor)
+                        .await;
+                    match updated {
+                        Ok(updated) if updated.matched_count > 0 => {
+                            result.matched_count += updated.matched_count;
+                            result.modified_count += updated.modified_count;
+                            break;
+                        }
+                        updated => {
+                            self.revisions.delete_record(&revision_id).await?;
+                            updated?;
+                        }
+                    }
+                }
+
+                attempts += 1;
+                if attempts == UPDATE_ATTEMPTS {
+                    return Err((
+                        StatusCode::CONFLICT,
+                        "Record is being modified concurrently".to_owned(),
+                    ));
+                }
+                // Changed in the meantime, so check the filter against the latest version.
+                previous = match self
+                    .current(doc! {"_id": id, "$and": [filter.clone()]})
+                    .await?
+                    .pop()
+                {
+                    Some(previous) => previous,
+                    None => break,
+                };
+            }
+        }
+        Ok(result)
+    }
+
+    async fn forget(&self, ids: Vec<String>) -> Result<(), (StatusCode, String)> {
+        if !ids.is_empty() {
+            self.revisions
+                .delete_many_records(doc! {"record_id": {"$in": ids}})
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T: Send + Sync + 'static> Repository<T> for History<T> {
+    async fn create_record(
+        &self,
+        new_record: T,
+        actor: Option<&str>,
+    ) -> Result<InsertResult, (StatusCode, String)> {
+        self.inner.create_record(new_record, actor).await
+    }
+
+    async fn get_all_record(&self) -> Result<Vec<T>, (StatusCode, String)> {
+        self.inner.get_all_record().await
+    }
+
+    async fn find_record(&self, filter: Document) -> Result<Vec<T>, (StatusCode, String)> {
+        self.inner.find_record(filter).await
+    }
+
+    async fn find_one_record(&self, filter: Document) -> Result<T, (StatusCode, String)> {
+        self.inner.find_one_record(filter).await
+    }
+
+    async fn query_records(
+        &self,
+        query: RecordQuery,
+    ) -> Result<Vec<Document>, (StatusCode, String)> {
+        self.inner.query_records(query).await
+    }
+
+    async fn count_records(&self, filter: Document) -> Result<u64, (StatusCode, String)> {
+        self.inner.count_records(filter).await
+    }
+
+    async fn get_record(&self, id: &str) -> Result<T, (StatusCode, String)> {
+        self.inner.get_record(id).await
+    }
+
+    async fn update_record(
+        &self,
+        id: &str,
+        new_record: Document,
+        actor: Option<&str>,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        let filter = id_filter(id)?;
+        if new_record.is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "No schema data fields to update".to_owned(),
+            ));
+        }
+        self.update_each(filter, new_record, actor).await
+    }
+
+    async fn update_many_records(
+        &self,
+        filter: Document,
+        new_record: Document,
+        actor: Option<&str>,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        self.update_each(filter, new_record, actor).await
+    }
+
+    async fn update_unstamped(
+        &self,
+        filter: Document,
+        fields: Document,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        self.inner.update_unstamped(filter, fields).await
+    }
+
+    async fn delete_record(&self, id: &str) -> Result<DeleteResult, (StatusCode, String)> {
+        let result = self.inner.delete_record(id).await?;
+        if result.deleted_count > 0 {
+            self.forget(vec![id.to_owned()]).await?;
+        }
+        Ok(result)
+    }
+
+    async fn delete_many_records(
+        &self,
+        filter: Document,
+    ) -> Result<DeleteResult, (StatusCode, String)> {
+        let ids = self
+            .current(filter.clone())
+            .await?
+            .iter()
+            .filter_map(|record| record.get_object_id("_id").ok())
+            .map(|id| id.to_hex())
+            .collect();
+        let result = self.inner.delete_many_records(filter).await?;
+        if result.deleted_count > 0 {
+            self.forget(ids).await?;
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        model::detail_model::Detail,
+        repository::{memory_repo::Memory, trash_repo::Trash, TrashRepository},
+    };
+    use mongodb::bson::from_document;
+
+    fn repositories() -> (Trash<Detail>, Arc<dyn Repository<Revision<Detail>>>) {
+        let revisions: Arc<dyn Repository<Revision<Detail>>> =
+            Arc::new(Memory::init("DetailRevision"));
+        let history = History::init(Arc::new(Memory::init("Detail")), revisions.clone());
+        (Trash::init(Arc::new(history)), revisions)
+    }
+
+    async fn create(db: &dyn Repository<Detail>) -> String {
+        let detail = doc! {"name": "a", "description": "", "image": ""};
+        db.create_record(from_document(detail).unwrap(), None)
+            .await
+            .unwrap()
+            .inserted_id
+    }
+
+    #[actix_web::test]
+    async fn archives_each_replaced_version() {
+        let (db, revisions) = repositories();
+        let id = create(&db).await;
+        db.update_record(&id, doc! {"name": "b"}, None)
+            .await
+            .unwrap();
+        db.update_record(&id, doc! {"name": "c"}, None)
+            .await
+            .unwrap();
+
+        let stored = revisions
+            .find_record(doc! {"record_id": &id})
+            .await
+            .unwrap();
+        let names: Vec<(i64, &str)> = stored
+            .iter()
+            .map(|revision| (revision.revision, revision.record.get_str("name").unwrap()))
+            .collect();
+        assert_eq!(names, [(1, "a"), (2, "b")]);
+        assert_eq!(db.get_record(&id).await.unwrap().metadata.version, 3);
+    }
+
+    #[actix_web::test]
+    async fn leaves_records_alone_when_archiving_fails() {
+        let (db, revisions) = repositories();
+        let id = create(&db).await;
+        // Takes the slot of version 1, as a racing update would.
+        let taken = Revision::<Detail> {
+            _id: None,
+            record_id: id.clone(),
+            revision: 1,
+            record: doc! {},
+            metadata: Metadata::default(),
+            model: PhantomData,
+        };
+        revisions.create_record(taken, None).await.unwrap();
+
+        let err = db
+            .update_record(&id, doc! {"name": "b"}, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.0, StatusCode::CONFLICT);
+        let current = db.get_record(&id).await.unwrap();
+        assert_eq!(current.name, "a");
+        assert_eq!(current.metadata.version, 1);
+        assert_eq!(revisions.count_records(doc! {}).await.unwrap(), 1);
+    }
+
+    #[actix_web::test]
+    async fn trashing_and_restoring_keeps_no_revisions() {
+        let (db, revisions) = repositories();
+        let id = create(&db).await;
+        db.trash_records(doc! {}, Some("admin")).await.unwrap();
+        let trashed = db.trash().get_record(&id).await.unwrap();
+        assert_eq!(trashed.metadata.version, 1);
+        db.restore_record(&id, Some("admin")).await.unwrap();
+
+        assert_eq!(db.get_record(&id).await.unwrap().metadata.version, 1);
+        assert_eq!(revisions.count_records(doc! {}).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,303 @@
+use super::{
+    bump_version,
+    filter::{equals, lookup, matches, project, sort},
+    stamp_created, stamp_updated, DeleteResult, Indexes, InsertResult, RecordQuery, Repository,
+    UpdateResult,
+};
+use crate::model::IndexedModel;
+use actix_web::http::StatusCode;
+use async_trait::async_trait;
+use log::info;
+use mongodb::bson::{doc, from_document, oid::ObjectId, to_document, Bson, Document};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{marker::PhantomData, sync::Mutex};
+
+/// Keeps records as BSON documents in process memory, honouring the unique
+/// and expiry indexes the model declares like MongoDB would.
+pub struct Memory<T> {
+    records: Mutex<Vec<Document>>,
+    indexes: Indexes,
+    name: String,
+    model: PhantomData<fn() -> T>,
+}
+
+impl<T> Memory<T> {
+    pub fn init(collection: &str) -> Self
+    where
+        T: IndexedModel,
+    {
+        info!("Initializing in-memory Collection: {}", collection);
+        Memory {
+            records: Mutex::new(Vec::new()),
+            indexes: Indexes::of::<T>(),
+            name: collection.to_owned(),
+            model: PhantomData,
+        }
+    }
+
+    fn error(&self, status_code: StatusCode, err: impl std::fmt::Display) -> (StatusCode, String) {
+        (
+            status_code,
+            format!("{} Memory Repo Error: {}", self.name, err),
+        )
+    }
+
+    fn parse_id(&self, id: &str) -> Result<ObjectId, (StatusCode, String)> {
+        ObjectId::parse_str(id).map_err(|_| self.error(StatusCode::BAD_REQUEST, "Invalid ID"))
+    }
+
+    fn records(&self) -> std::sync::MutexGuard<'_, Vec<Document>> {
+        let mut records = self.records.lock().unwrap_or_else(|err| err.into_inner());
+        // Stands in for MongoDB's TTL monitor.
+        records.retain(|record| !self.indexes.is_expired(record));
+        records
+    }
+
+    fn filter(&self, record: &Document, filter: &Document) -> Result<bool, (StatusCode, String)> {
+        matches(record, filter).map_err(|err| self.error(StatusCode::BAD_REQUEST, err))
+    }
+
+    /// Fails with `409 Conflict` if `record` would break a unique index,
+    /// ignoring the record at `position` which it replaces.
+    fn check_unique(
+        &self,
+        records: &[Document],
+        record: &Document,
+        position: Option<usize>,
+    ) -> Result<(), (StatusCode, String)> {
+        for fields in &self.indexes.unique {
+            let key: Vec<Option<&Bson>> =
+                fields.iter().map(|field| lookup(record, field)).collect();
+            let duplicate = records.iter().enumerate().any(|(i, other)| {
+                Some(i) != position
+                    && fields.iter().zip(&key).all(|(field, value)| {
+                        equals(lookup(other, field), value.unwrap_or(&Bson::Null))
+                    })
+            });
+            if duplicate {
+                return Err(self.error(
+                    StatusCode::CONFLICT,
+                    format!("duplicate key for {}", fields.join(", ")),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn deserialize(&self, record: &Document) -> Result<T, (StatusCode, String)>
+    where
+        T: DeserializeOwned,
+    {
+        from_document(record.clone())
+            .map_err(|err| self.error(StatusCode::INTERNAL_SERVER_ERROR, err))
+    }
+
+    /// Applies `new_record` like `$set` to every record matching `filter`,
+    /// stopping after the first if `many` is false, and bumps the version of
+    /// each if `versioned`.
+    fn update(
+        &self,
+        filter: &Document,
+        new_record: &Document,
+        many: bool,
+        versioned: bool,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        let mut records = self.records();
+        let mut result = UpdateResult {
+            matched_count: 0,
+            modified_count: 0,
+        };
+        for position in 0..records.len() {
+            if !self.filter(&records[position], filter)? {
+                continue;
+            }
+            result.matched_count += 1;
+            let mut updated = records[position].clone();
+            for (key, value) in new_record {
+                updated.insert(key.to_owned(), value.clone());
+            }
+            if versioned {
+                bump_version(&mut updated);
+            }
+            if updated != records[position] {
+                self.check_unique(&records, &updated, Some(position))?;
+                records[position] = updated;
+                result.modified_count += 1;
+            }
+            if !many {
+                break;
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn delete(&self, filter: &Document, many: bool) -> Result<DeleteResult, (StatusCode, String)> {
+        let mut records = self.records();
+        let mut deleted_count = 0;
+        let mut position = 0;
+        while position < records.len() {
+            if (many || deleted_count == 0) && self.filter(&records[position], filter)? {
+                records.remove(position);
+                deleted_count += 1;
+            } else {
+                position += 1;
+            }
+        }
+
+        Ok(DeleteResult { deleted_count })
+    }
+}
+
+#[async_trait]
+impl<T> Repository<T> for Memory<T>
+where
+    T: Serialize + DeserializeOwned + Unpin + Send + Sync,
+{
+    async fn create_record(
+        &self,
+        new_record: T,
+        actor: Option<&str>,
+    ) -> Result<InsertResult, (StatusCode, String)> {
+        let id = ObjectId::new();
+        let mut record = doc! {"_id": id};
+        let fields =
+            to_document(&new_record).map_err(|err| self.error(StatusCode::BAD_REQUEST, err))?;
+        record.extend(fields);
+        stamp_created(&mut record, actor);
+
+        let mut records = self.records();
+        self.check_unique(&records, &record, None)?;
+        records.push(record);
+
+        Ok(InsertResult {
+            inserted_id: id.to_string(),
+        })
+    }
+
+    async fn get_all_record(&self) -> Result<Vec<T>, (StatusCode, String)> {
+        self.find_record(Document::new()).await
+    }
+
+    async fn find_record(&self, filter: Document) -> Result<Vec<T>, (StatusCode, String)> {
+        let records = self.records();
+        let mut found = Vec::new();
+        for record in records.iter() {
+            if self.filter(record, &filter)? {
+                found.push(self.deserialize(record)?);
+            }
+        }
+
+        Ok(found)
+    }
+
+    async fn find_one_record(&self, filter: Document) -> Result<T, (StatusCode, String)> {
+        let records = self.records();
+        for record in records.iter() {
+            if self.filter(record, &filter)? {
+                return self.deserialize(record);
+            }
+        }
+
+        Err(self.error(StatusCode::NOT_FOUND, "ID not found"))
+    }
+
+    async fn query_records(
+        &self,
+        query: RecordQuery,
+    ) -> Result<Vec<Document>, (StatusCode, String)> {
+        let mut found = Vec::new();
+        for record in self.records().iter() {
+            if self.filter(record, &query.filter)? {
+                found.push(record.clone());
+            }
+        }
+        sort(&mut found, &query.sort);
+
+        let page = found
+            .into_iter()
+            .skip(query.skip as usize)
+            .take(query.limit.map_or(usize::MAX, |limit| limit as usize));
+        Ok(match &query.projection {
+            Some(fields) => page.map(|record| project(record, fields)).collect(),
+            None => page.collect(),
+        })
+    }
+
+    async fn count_records(&self, filter: Document) -> Result<u64, (StatusCode, String)> {
+        let mut count = 0;
+        for record in self.records().iter() {
+            if self.filter(record, &filter)? {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    async fn get_record(&self, id: &str) -> Result<T, (StatusCode, String)> {
+        let obj_id = self.parse_id(id)?;
+        self.find_one_record(doc! {"_id": obj_id}).await
+    }
+
+    async fn update_record(
+        &self,
+        id: &str,
+        mut new_record: Document,
+        actor: Option<&str>,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        let obj_id = self.parse_id(id)?;
+        if new_record.is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "No schema data fields to update".to_owned(),
+            ));
+        }
+        stamp_updated(&mut new_record, actor);
+        self.update(&doc! {"_id": obj_id}, &new_record, false, true)
+    }
+
+    async fn update_many_records(
+        &self,
+        filter: Document,
+        mut new_record: Document,
+        actor: Option<&str>,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        stamp_updated(&mut new_record, actor);
+        self.update(&filter, &new_record, true, true)
+    }
+
+    async fn update_unstamped(
+        &self,
+        filter: Document,
+        fields: Document,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        self.update(&filter, &fields, true, false)
+    }
+
+    async fn delete_record(&self, id: &str) -> Result<DeleteResult, (StatusCode, String)> {
+        let obj_id = self.parse_id(id)?;
+        self.delete(&doc! {"_id": obj_id}, false)
+    }
+
+    async fn delete_many_records(
+        &self,
+        filter: Document,
+    ) -> Result<DeleteResult, (StatusCode, String)> {
+        self.delete(&filter, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        model::{oidc_model::OidcLogin, user_model::User},
+        repository::backend_tests::backend_tests,
+    };
+
+    backend_tests!(async { Memory::<User>::init("User") }, async {
+        Memory::<OidcLogin>::init("OidcLogin")
+    });
+}
@@ -1 +1,296 @@
+use crate::model::{revision_model::Revision, IndexedModel};
+use actix_web::http::StatusCode;
+use async_trait::async_trait;
+use log::info;
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson, DateTime, Document},
+    Database,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{sync::Arc, time::Duration};
+
+#[cfg(test)]
+mod backend_tests;
+pub mod filter;
+pub mod history_repo;
+pub mod memory_repo;
 pub mod mongodb_repo;
+#[cfg(feature = "sql")]
+pub mod sql_repo;
+pub mod trash_repo;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InsertResult {
+    pub inserted_id: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateResult {
+    pub matched_count: u64,
+    pub modified_count: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeleteResult {
+    pub deleted_count: u64,
+}
+
+/// Stamps a new record with `created_at`, `updated_at`, `created_by` and
+/// `updated_by`, replacing any the client sent, and starts its `version` at 1.
+/// `actor` is the ID of the user making the change, `None` for the system.
+pub fn stamp_created(record: &mut Document, actor: Option<&str>) {
+    let now = DateTime::now();
+    record.insert("created_at", now);
+    record.insert("updated_at", now);
+    record.insert("created_by", actor);
+    record.insert("updated_by", actor);
+    record.insert("version", 1_i64);
+}
+
+/// Stamps an update with `updated_at` and `updated_by`, dropping any
+/// creation metadata or version it carries. The backend increments the
+/// version itself, see [`bump_version`].
+pub fn stamp_updated(update: &mut Document, actor: Option<&str>) {
+    update.remove("created_at");
+    update.remove("created_by");
+    update.remove("version");
+    update.insert("updated_at", DateTime::now());
+    update.insert("updated_by", actor);
+}
+
+/// Increments the `version` of an updated record, like MongoDB's `$inc`
+/// would, starting from 0 for records written before versions existed.
+pub fn bump_version(record: &mut Document) {
+    record.insert("version", version_of(record) + 1);
+}
+
+/// The `version` of a stored record, 0 if it was written before versions
+/// existed.
+pub fn version_of(record: &Document) -> i64 {
+    match record.get("version") {
+        Some(Bson::Int32(version)) => *version as i64,
+        Some(Bson::Int64(version)) => *version,
+        _ => 0,
+    }
+}
+
+/// A filter matching the record with the hex ObjectId `id`.
+pub fn id_filter(id: &str) -> Result<Document, (StatusCode, String)> {
+    ObjectId::parse_str(id)
+        .map(|id| doc! {"_id": id})
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid ID".to_owned()))
+}
+
+/// What a list endpoint asks a repository for.
+#[derive(Debug, Clone, Default)]
+pub struct RecordQuery {
+    pub filter: Document,
+    /// A MongoDB sort document, in order of precedence.
+    pub sort: Document,
+    /// The top-level fields to keep besides `_id`, or all of them if `None`.
+    pub projection: Option<Vec<String>>,
+    /// How many matching records to skip before the first one returned.
+    pub skip: u64,
+    /// How many records to return at most, or all of them if `None`.
+    pub limit: Option<u64>,
+}
+
+/// The unique and expiry indexes a model declares, for backends that have to
+/// enforce them themselves.
+#[derive(Debug, Clone, Default)]
+pub struct Indexes {
+    /// Fields of each unique index.
+    pub unique: Vec<Vec<String>>,
+    /// Date fields records expire at, after the given delay.
+    pub expiry: Vec<(String, Duration)>,
+}
+
+impl Indexes {
+    pub fn of<T: IndexedModel>() -> Self {
+        let mut indexes = Indexes::default();
+        for index in T::indexes() {
+            let options = index.options.unwrap_or_default();
+            let fields: Vec<String> = index.keys.keys().cloned().collect();
+            if let (Some(expire_after), [field]) = (options.expire_after, fields.as_slice()) {
+                indexes.expiry.push((field.to_owned(), expire_after));
+            }
+            if options.unique == Some(true) {
+                indexes.unique.push(fields);
+            }
+        }
+        indexes
+    }
+
+    /// Whether MongoDB's TTL monitor would have removed `record` by now.
+    pub fn is_expired(&self, record: &Document) -> bool {
+        let now = DateTime::now().timestamp_millis();
+        self.expiry
+            .iter()
+            .any(|(field, expire_after)| match record.get(field) {
+                Some(Bson::DateTime(at)) => {
+                    at.timestamp_millis() + expire_after.as_millis() as i64 <= now
+                }
+                _ => false,
+            })
+    }
+}
+
+/// Storage for the records of one model.
+///
+/// Filters are MongoDB query documents and updates are applied like `$set`,
+/// so every backend has to understand that subset of the query language.
+#[async_trait]
+pub trait Repository<T>: Send + Sync {
+    /// Writes on behalf of `actor` are stamped as described in
+    /// [`stamp_created`] and [`stamp_updated`].
+    async fn create_record(
+        &self,
+        new_record: T,
+        actor: Option<&str>,
+    ) -> Result<InsertResult, (StatusCode, String)>;
+
+    async fn get_all_record(&self) -> Result<Vec<T>, (StatusCode, String)>;
+
+    async fn find_record(&self, filter: Document) -> Result<Vec<T>, (StatusCode, String)>;
+
+    async fn find_one_record(&self, filter: Document) -> Result<T, (StatusCode, String)>;
+
+    /// Finds records as raw documents, since a projection may leave out
+    /// fields `T` requires.
+    async fn query_records(
+        &self,
+        query: RecordQuery,
+    ) -> Result<Vec<Document>, (StatusCode, String)>;
+
+    async fn count_records(&self, filter: Document) -> Result<u64, (StatusCode, String)>;
+
+    async fn get_record(&self, id: &str) -> Result<T, (StatusCode, String)>;
+
+    async fn update_record(
+        &self,
+        id: &str,
+        new_record: Document,
+        actor: Option<&str>,
+    ) -> Result<UpdateResult, (StatusCode, String)>;
+
+    async fn update_many_records(
+        &self,
+        filter: Document,
+        new_record: Document,
+        actor: Option<&str>,
+    ) -> Result<UpdateResult, (StatusCode, String)>;
+
+    /// Sets bookkeeping `fields`, like login counters, on the records
+    /// matching `filter` without stamping them, bumping their `version` or
+    /// keeping a revision, since they aren't edits of the record.
+    async fn update_unstamped(
+        &self,
+        filter: Document,
+        fields: Document,
+    ) -> Result<UpdateResult, (StatusCode, String)>;
+
+    async fn delete_record(&self, id: &str) -> Result<DeleteResult, (StatusCode, String)>;
+
+    async fn delete_many_records(
+        &self,
+        filter: Document,
+    ) -> Result<DeleteResult, (StatusCode, String)>;
+}
+
+/// A [`Repository`] whose deletes move records to a trash they can be
+/// restored from, until purged through [`TrashRepository::trash`].
+#[async_trait]
+pub trait TrashRepository<T>: Repository<T> {
+    /// Moves the live records matching `filter` to the trash on behalf of
+    /// `actor`.
+    async fn trash_records(
+        &self,
+        filter: Document,
+        actor: Option<&str>,
+    ) -> Result<DeleteResult, (StatusCode, String)>;
+
+    /// Moves a trashed record back to the live ones.
+    async fn restore_record(
+        &self,
+        id: &str,
+        actor: Option<&str>,
+    ) -> Result<UpdateResult, (StatusCode, String)>;
+
+    /// The trashed records, which deleting from purges for good.
+    fn trash(&self) -> Arc<dyn Repository<T>>;
+}
+
+/// The backend repositories are created in, selected with `DATABASE`:
+/// `memory` keeps every record in the process and loses them on restart,
+/// `sql` connects to `DATABASE_URL` when built with the `sqlite` or
+/// `postgres` feature, anything else connects to MongoDB.
+pub enum Storage {
+    MongoDB(Database),
+    Memory,
+    #[cfg(feature = "sql")]
+    Sql(sql_repo::SqlDatabase),
+}
+
+impl Storage {
+    pub async fn from_env(database: &str) -> Self {
+        match dotenv::var("DATABASE").as_deref() {
+            Ok("memory") => {
+                info!("Using in-memory storage, records are lost on restart");
+                Storage::Memory
+            }
+            #[cfg(feature = "sql")]
+            Ok("sql") => Storage::Sql(sql_repo::new().await),
+            #[cfg(not(feature = "sql"))]
+            Ok("sql") => panic!("DATABASE=sql requires the sqlite or postgres feature"),
+            _ => Storage::MongoDB(mongodb_repo::new(database).await),
+        }
+    }
+
+    pub async fn repository<T>(&mut self, collection: &str) -> Arc<dyn Repository<T>>
+    where
+        T: IndexedModel + Serialize + DeserializeOwned + Unpin + Send + Sync + 'static,
+    {
+        match self {
+            Storage::MongoDB(db) => {
+                Arc::new(mongodb_repo::MongoDB::<T>::init(db, collection).await)
+            }
+            Storage::Memory => Arc::new(memory_repo::Memory::<T>::init(collection)),
+            #[cfg(feature = "sql")]
+            Storage::Sql(db) => Arc::new(sql_repo::Sql::<T>::init(db, collection)),
+        }
+    }
+
+    /// A repository whose deletes go to a trash, see [`trash_repo::Trash`].
+    pub async fn trash_repository<T>(&mut self, collection: &str) -> Arc<dyn TrashRepository<T>>
+    where
+        T: IndexedModel + Serialize + DeserializeOwned + Unpin + Send + Sync + 'static,
+    {
+        Arc::new(trash_repo::Trash::init(
+            self.repository::<T>(collection).await,
+        ))
+    }
+
+    /// Like [`Storage::trash_repository`], also keeping the versions records
+    /// had before every update in `{collection}Revision`, see
+    /// [`history_repo::History`].
+    pub async fn history_repository<T>(
+        &mut self,
+        collection: &str,
+    ) -> (
+        Arc<dyn TrashRepository<T>>,
+        Arc<dyn Repository<Revision<T>>>,
+    )
+    where
+        T: IndexedModel + Serialize + DeserializeOwned + Unpin + Send + Sync + 'static,
+    {
+        let revisions = self
+            .repository::<Revision<T>>(&format!("{}Revision", collection))
+            .await;
+        let history =
+            history_repo::History::init(self.repository::<T>(collection).await, revisions.clone());
+        (
+            Arc::new(trash_repo::Trash::init(Arc::new(history))),
+            revisions,
+        )
+    }
+}
@@ -1,16 +1,23 @@
 use std::env;
 extern crate dotenv;
+use super::{
+    stamp_created, stamp_updated, DeleteResult, InsertResult, RecordQuery, Repository, UpdateResult,
+};
+use crate::model::IndexedModel;
 use actix_web::http::StatusCode;
+use async_trait::async_trait;
 use futures::stream::TryStreamExt;
-use log::info;
+use log::{error, info};
 use mongodb::{
-    bson::{doc, oid::ObjectId, Document},
-    error::ErrorKind,
-    results::{DeleteResult, InsertOneResult, UpdateResult},
-    Client, Collection, Database,
+    bson::{doc, oid::ObjectId, to_document, Document},
+    error::{Error, ErrorKind, WriteError, WriteFailure},
+    options::FindOptions,
+    results, Client, Collection, Database, IndexModel,
 };
 use serde::{de::DeserializeOwned, Serialize};
 
+const DUPLICATE_KEY: i32 = 11000;
+
 pub struct MongoDB<T> {
     col: Collection<T>,
     name: String,
@@ -26,37 +33,157 @@ pub async fn new(database: &str) -> Database {
     client.database(database)
 }
 
-impl<T> MongoDB<T>
-where
-    T: Serialize + DeserializeOwned + Unpin + Send + Sync,
-{
-    pub async fn init(db: &mut Database, collection: &str) -> Self {
+fn write_error_status(err: &Error) -> StatusCode {
+    match *err.kind {
+        ErrorKind::InvalidArgument { .. } => StatusCode::BAD_REQUEST,
+        ErrorKind::Write(WriteFailure::WriteError(WriteError {
+            code: DUPLICATE_KEY,
+            ..
+        })) => StatusCode::CONFLICT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn update_result(result: results::UpdateResult) -> UpdateResult {
+    UpdateResult {
+        matched_count: result.matched_count,
+        modified_count: result.modified_count,
+    }
+}
+
+fn delete_result(result: results::DeleteResult) -> DeleteResult {
+    DeleteResult {
+        deleted_count: result.deleted_count,
+    }
+}
+
+/// Lists up to 10 groups of records that share the key of a unique `index`,
+/// which would keep MongoDB from building it.
+async fn duplicates(col: &Collection<Document>, index: &IndexModel) -> Result<Vec<String>, Error> {
+    let mut pipeline = Vec::new();
+    if let Some(filter) = index
+        .options
+        .as_ref()
+        .and_then(|options| options.partial_filter_expression.clone())
+    {
+        pipeline.push(doc! {"$match": filter});
+    }
+    let mut key = Document::new();
+    for field in index.keys.keys() {
+        key.insert(field.replace('.', "_"), format!("${}", field));
+    }
+    pipeline.push(doc! {"$group": {"_id": key, "ids": {"$push": "$_id"}, "count": {"$sum": 1}}});
+    pipeline.push(doc! {"$match": {"count": {"$gt": 1}}});
+    pipeline.push(doc! {"$limit": 10});
+
+    let groups: Vec<Document> = col.aggregate(pipeline, None).await?.try_collect().await?;
+    Ok(groups
+        .iter()
+        .map(|group| {
+            let ids: Vec<String> = group
+                .get_array("ids")
+                .map(|ids| ids.iter().map(|id| id.to_string()).collect())
+                .unwrap_or_default();
+            format!(
+                "{} in {}",
+                group.get("_id").cloned().unwrap_or_default(),
+                ids.join(", ")
+            )
+        })
+        .collect())
+}
+
+/// Creates `index`, unless it is unique and records already break it, which
+/// is reported for them to be cleaned up instead.
+async fn create_index(col: &Collection<Document>, index: IndexModel) -> Result<(), String> {
+    let fields = index.keys.keys().cloned().collect::<Vec<_>>().join(", ");
+    let unique = index
+        .options
+        .as_ref()
+        .and_then(|options| options.unique)
+        .unwrap_or(false);
+    if unique {
+        let duplicates = duplicates(col, &index)
+            .await
+            .map_err(|err| format!("error checking {} for duplicates: {}", fields, err))?;
+        if !duplicates.is_empty() {
+            return Err(format!(
+                "cannot create unique index on {}, records share its key: {}",
+                fields,
+                duplicates.join("; ")
+            ));
+        }
+    }
+    col.create_index(index, None)
+        .await
+        .map(|_| ())
+        .map_err(|err| format!("error creating index on {}: {}", fields, err))
+}
+
+impl<T> MongoDB<T> {
+    /// Opens `collection`, creating the indexes of `T`. An index that can't
+    /// be created is logged rather than stopping the server, so duplicates
+    /// left from before a unique index existed can be resolved by hand.
+    pub async fn init(db: &mut Database, collection: &str) -> Self
+    where
+        T: IndexedModel,
+    {
         info!("Initializing MongoDB Collection: {}", collection);
         let col: Collection<T> = db.collection(collection);
+        let indexes = T::indexes();
+        if !indexes.is_empty() {
+            info!("Creating indexes for MongoDB Collection: {}", collection);
+            let records = col.clone_with_type::<Document>();
+            for index in indexes {
+                if let Err(err) = create_index(&records, index).await {
+                    error!("{}: {}", collection, err);
+                }
+            }
+        }
         MongoDB {
             col,
             name: collection.to_owned(),
         }
     }
+}
 
-    pub async fn create_record(
+#[async_trait]
+impl<T> Repository<T> for MongoDB<T>
+where
+    T: Serialize + DeserializeOwned + Unpin + Send + Sync,
+{
+    async fn create_record(
         &self,
         new_record: T,
-    ) -> Result<InsertOneResult, (StatusCode, String)> {
-        let record = self.col.insert_one(new_record, None).await.map_err(|err| {
+        actor: Option<&str>,
+    ) -> Result<InsertResult, (StatusCode, String)> {
+        let mut new_record = to_document(&new_record).map_err(|err| {
             (
-                match *err.kind {
-                    ErrorKind::InvalidArgument { .. } => StatusCode::BAD_REQUEST,
-                    _ => StatusCode::INTERNAL_SERVER_ERROR,
-                },
+                StatusCode::BAD_REQUEST,
                 format!("{} MongoDB Repo Error: {}", self.name, err),
             )
         })?;
+        stamp_created(&mut new_record, actor);
+        let record = self
+            .col
+            .clone_with_type::<Document>()
+            .insert_one(new_record, None)
+            .await
+            .map_err(|err| {
+                (
+                    write_error_status(&err),
+                    format!("{} MongoDB Repo Error: {}", self.name, err),
+                )
+            })?;
 
-        Ok(record)
+        let inserted_id = match record.inserted_id.as_object_id() {
+            Some(object_id) => object_id.to_string(),
+            None => record.inserted_id.to_string(),
+        };
+        Ok(InsertResult { inserted_id })
     }
 
-    pub async fn get_all_record(&self) -> Result<Vec<T>, (StatusCode, String)> {
+    async fn get_all_record(&self) -> Result<Vec<T>, (StatusCode, String)> {
         let mut cursors = self.col.find(None, None).await.map_err(|err| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -75,27 +202,26 @@ where
         Ok(records)
     }
 
-    // WILL BE USED IN THE FUTURE
-    // pub async fn find_record(&self, filter: Document) -> Result<Vec<T>, (StatusCode, String)> {
-    //     let mut cursors = self.col.find(filter, None).await.map_err(|err| {
-    //         (
-    //             StatusCode::INTERNAL_SERVER_ERROR,
-    //             format!("{} MongoDB Repo Error: {}", self.name, err),
-    //         )
-    //     })?;
-    //     let mut records = Vec::new();
-    //     while let Some(record) = cursors.try_next().await.map_err(|err| {
-    //         (
-    //             StatusCode::INTERNAL_SERVER_ERROR,
-    //             format!("{} MongoDB Repo Error: {}", self.name, err),
-    //         )
-    //     })? {
-    //         records.push(record)
-    //     }
-    //     Ok(records)
-    // }
-
-    pub async fn find_one_record(&self, filter: Document) -> Result<T, (StatusCode, String)> {
+    async fn find_record(&self, filter: Document) -> Result<Vec<T>, (StatusCode, String)> {
+        let mut cursors = self.col.find(filter, None).await.map_err(|err| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("{} MongoDB Repo Error: {}", self.name, err),
+            )
+        })?;
+        let mut records = Vec::new();
+        while let Some(record) = cursors.try_next().await.map_err(|err| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("{} MongoDB Repo Error: {}", self.name, err),
+            )
+        })? {
+            records.push(record)
+        }
+        Ok(records)
+    }
+
+    async fn find_one_record(&self, filter: Document) -> Result<T, (StatusCode, String)> {
         let record = self.col.find_one(filter, None).await.map_err(|err| {
             (
                 match *err.kind {
@@ -112,7 +238,55 @@ where
         ))
     }
 
-    pub async fn get_record(&self, id: &str) -> Result<T, (StatusCode, String)> {
+    async fn query_records(
+        &self,
+        query: RecordQuery,
+    ) -> Result<Vec<Document>, (StatusCode, String)> {
+        let projection = query
+            .projection
+            .map(|fields| fields.into_iter().map(|field| (field, 1.into())).collect());
+        let options = FindOptions::builder()
+            .sort((!query.sort.is_empty()).then_some(query.sort))
+            .projection(projection)
+            .skip(query.skip)
+            .limit(query.limit.map(|limit| limit as i64))
+            .build();
+        let cursors = self
+            .col
+            .clone_with_type::<Document>()
+            .find(query.filter, options)
+            .await
+            .map_err(|err| {
+                (
+                    match *err.kind {
+                        ErrorKind::InvalidArgument { .. } => StatusCode::BAD_REQUEST,
+                        _ => StatusCode::INTERNAL_SERVER_ERROR,
+                    },
+                    format!("{} MongoDB Repo Error: {}", self.name, err),
+                )
+            })?;
+
+        cursors.try_collect().await.map_err(|err| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("{} MongoDB Repo Error: {}", self.name, err),
+            )
+        })
+    }
+
+    async fn count_records(&self, filter: Document) -> Result<u64, (StatusCode, String)> {
+        self.col.count_documents(filter, None).await.map_err(|err| {
+            (
+                match *err.kind {
+                    ErrorKind::InvalidArgument { .. } => StatusCode::BAD_REQUEST,
+                    _ => StatusCode::INTERNAL_SERVER_ERROR,
+                },
+                format!("{} MongoDB Repo Error: {}", self.name, err),
+            )
+        })
+    }
+
+    async fn get_record(&self, id: &str) -> Result<T, (StatusCode, String)> {
         let obj_id = ObjectId::parse_str(id).map_err(|_| {
             (
                 StatusCode::BAD_REQUEST,
@@ -136,10 +310,11 @@ where
         ))
     }
 
-    pub async fn update_record(
+    async fn update_record(
         &self,
         id: &str,
-        new_record: Document,
+        mut new_record: Document,
+        actor: Option<&str>,
     ) -> Result<UpdateResult, (StatusCode, String)> {
         let obj_id = match ObjectId::parse_str(id) {
             Ok(id) => id,
@@ -156,9 +331,11 @@ where
                 "No schema data fields to update".to_owned(),
             ));
         }
+        stamp_updated(&mut new_record, actor);
         let filter = doc! {"_id": obj_id};
         let new_doc = doc! {
             "$set": new_record,
+            "$inc": {"version": 1},
         };
         let updated_doc = self
             .col
@@ -166,17 +343,56 @@ where
             .await
             .map_err(|err| {
                 (
-                    match *err.kind {
-                        ErrorKind::InvalidArgument { .. } => StatusCode::BAD_REQUEST,
-                        _ => StatusCode::INTERNAL_SERVER_ERROR,
-                    },
+                    write_error_status(&err),
                     format!("{} MongoDB Repo Error: {}", self.name, err),
                 )
             })?;
-        Ok(updated_doc)
+        Ok(update_result(updated_doc))
     }
 
-    pub async fn delete_record(&self, id: &str) -> Result<DeleteResult, (StatusCode, String)> {
+    async fn update_many_records(
+        &self,
+        filter: Document,
+        mut new_record: Document,
+        actor: Option<&str>,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        stamp_updated(&mut new_record, actor);
+        let new_doc = doc! {
+            "$set": new_record,
+            "$inc": {"version": 1},
+        };
+        let updated_doc = self
+            .col
+            .update_many(filter, new_doc, None)
+            .await
+            .map_err(|err| {
+                (
+                    write_error_status(&err),
+                    format!("{} MongoDB Repo Error: {}", self.name, err),
+                )
+            })?;
+        Ok(update_result(updated_doc))
+    }
+
+    async fn update_unstamped(
+        &self,
+        filter: Document,
+        fields: Document,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        let updated_doc = self
+            .col
+            .update_many(filter, doc! {"$set": fields}, None)
+            .await
+            .map_err(|err| {
+                (
+                    write_error_status(&err),
+                    format!("{} MongoDB Repo Error: {}", self.name, err),
+                )
+            })?;
+        Ok(update_result(updated_doc))
+    }
+
+    async fn delete_record(&self, id: &str) -> Result<DeleteResult, (StatusCode, String)> {
         let obj_id = ObjectId::parse_str(id).map_err(|_| {
             (
                 StatusCode::BAD_REQUEST,
@@ -191,6 +407,20 @@ where
             )
         })?;
 
-        Ok(record)
+        Ok(delete_result(record))
+    }
+
+    async fn delete_many_records(
+        &self,
+        filter: Document,
+    ) -> Result<DeleteResult, (StatusCode, String)> {
+        let record = self.col.delete_many(filter, None).await.map_err(|err| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("{} MongoDB Repo Error: {}", self.name, err),
+            )
+        })?;
+
+        Ok(delete_result(record))
     }
 }
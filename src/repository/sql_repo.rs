@@ -0,0 +1,472 @@
+use super::{
+    bump_version,
+    filter::{matches, project, sort},
+    stamp_created, stamp_updated, DeleteResult, Indexes, InsertResult, RecordQuery, Repository,
+    UpdateResult,
+};
+use crate::model::IndexedModel;
+use actix_web::http::StatusCode;
+use async_trait::async_trait;
+use log::info;
+use mongodb::bson::{doc, from_document, oid::ObjectId, to_document, Bson, Document};
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::{
+    any::{install_default_drivers, AnyPoolOptions},
+    AnyPool, Row,
+};
+use std::{fmt::Display, marker::PhantomData};
+
+/// How often an update is retried when the row changed since it was read.
+const UPDATE_ATTEMPTS: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dialect {
+    Sqlite,
+    Postgres,
+}
+
+/// Connection pool shared by the tables of every collection.
+#[derive(Clone)]
+pub struct SqlDatabase {
+    pool: AnyPool,
+    dialect: Dialect,
+}
+
+/// Connects to `DATABASE_URL`, either `sqlite:` or `postgres:`, and runs the
+/// embedded migrations for it.
+pub async fn new() -> SqlDatabase {
+    info!("Initializing SQL Database...");
+    let url = dotenv::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    connect(&url).await
+}
+
+/// Connects to `url` and runs the embedded migrations for it. An in-memory
+/// SQLite database only lives as long as its one connection.
+pub async fn connect(url: &str) -> SqlDatabase {
+    let dialect = if url.starts_with("postgres") {
+        Dialect::Postgres
+    } else {
+        Dialect::Sqlite
+    };
+    install_default_drivers();
+    let mut options = AnyPoolOptions::new();
+    if dialect == Dialect::Sqlite && url.contains(":memory:") {
+        options = options
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None);
+    }
+    let pool = options
+        .connect(url)
+        .await
+        .expect("error connecting to database");
+
+    info!("Running {:?} migrations", dialect);
+    let migrator = match dialect {
+        Dialect::Sqlite => sqlx::migrate!("migrations/sqlite"),
+        Dialect::Postgres => sqlx::migrate!("migrations/postgres"),
+    };
+    migrator.run(&pool).await.expect("error running migrations");
+
+    SqlDatabase { pool, dialect }
+}
+
+/// Stores every record of a collection as extended JSON in a table named
+/// after it, keyed by the hex string of its ObjectId.
+///
+/// Only lookups by ID and by uniquely indexed fields are narrowed down in
+/// SQL; the rest of a filter is evaluated on the rows returned.
+pub struct Sql<T> {
+    db: SqlDatabase,
+    table: String,
+    indexes: Indexes,
+    model: PhantomData<fn() -> T>,
+}
+
+/// A row as read, keeping the stored text to detect concurrent changes.
+struct Stored {
+    id: String,
+    data: String,
+    record: Document,
+}
+
+impl<T> Sql<T> {
+    pub fn init(db: &SqlDatabase, table: &str) -> Self
+    where
+        T: IndexedModel,
+    {
+        info!("Initializing SQL Table: {}", table);
+        Sql {
+            db: db.clone(),
+            table: table.to_owned(),
+            indexes: Indexes::of::<T>(),
+            model: PhantomData,
+        }
+    }
+
+    fn error(&self, status_code: StatusCode, err: impl Display) -> (StatusCode, String) {
+        (
+            status_code,
+            format!("{} SQL Repo Error: {}", self.table, err),
+        )
+    }
+
+    fn db_error(&self, err: sqlx::Error) -> (StatusCode, String) {
+        let status_code = match &err {
+            sqlx::Error::Database(err) if err.is_unique_violation() => StatusCode::CONFLICT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        self.error(status_code, err)
+    }
+
+    fn parse_id(&self, id: &str) -> Result<ObjectId, (StatusCode, String)> {
+        ObjectId::parse_str(id).map_err(|_| self.error(StatusCode::BAD_REQUEST, "Invalid ID"))
+    }
+
+    fn placeholder(&self, position: usize) -> String {
+        match self.db.dialect {
+            Dialect::Sqlite => "?".to_owned(),
+            Dialect::Postgres => format!("${}", position),
+        }
+    }
+
+    /// Matches the expressions of the unique indexes in the migrations.
+    fn field(&self, field: &str) -> String {
+        match self.db.dialect {
+            Dialect::Sqlite => format!("json_extract(data, '$.{}')", field),
+            Dialect::Postgres => format!("(data::jsonb ->> '{}')", field),
+        }
+    }
+
+    /// Turns the ID and unique string field equalities of `filter` into a
+    /// `WHERE` clause.
+    fn narrow(&self, filter: &Document) -> (String, Vec<String>) {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        for (key, value) in filter {
+            let column = match (key.as_str(), value) {
+                ("_id", Bson::ObjectId(_)) => "id".to_owned(),
+                (field, Bson::String(_))
+                    if self.indexes.unique.iter().any(|fields| fields == &[field]) =>
+                {
+                    self.field(field)
+                }
+                _ => continue,
+            };
+            params.push(match value {
+                Bson::ObjectId(id) => id.to_hex(),
+                value => value.as_str().unwrap_or_default().to_owned(),
+            });
+            conditions.push(format!("{} = {}", column, self.placeholder(params.len())));
+        }
+
+        match conditions.is_empty() {
+            true => (String::new(), params),
+            false => (format!(" WHERE {}", conditions.join(" AND ")), params),
+        }
+    }
+
+    fn encode(&self, mut record: Document) -> String {
+        record.remove("_id");
+        Bson::Document(record).into_relaxed_extjson().to_string()
+    }
+
+    fn decode(&self, id: &str, data: &str) -> Result<Document, (StatusCode, String)> {
+        let value: serde_json::Value = serde_json::from_str(data)
+            .map_err(|err| self.error(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+        let mut record = match Bson::try_from(value) {
+            Ok(Bson::Document(record)) => record,
+            Ok(_) => return Err(self.error(StatusCode::INTERNAL_SERVER_ERROR, "Invalid record")),
+            Err(err) => return Err(self.error(StatusCode::INTERNAL_SERVER_ERROR, err)),
+        };
+        record.insert("_id", self.parse_id(id)?);
+        Ok(record)
+    }
+
+    fn deserialize(&self, record: Document) -> Result<T, (StatusCode, String)>
+    where
+        T: DeserializeOwned,
+    {
+        from_document(record).map_err(|err| self.error(StatusCode::INTERNAL_SERVER_ERROR, err))
+    }
+
+    /// Reads the rows matching `filter` in insertion order, deleting the ones
+    /// that expired on the way.
+    async fn scan(&self, filter: &Document) -> Result<Vec<Stored>, (StatusCode, String)> {
+        let (clause, params) = self.narrow(filter);
+        let sql = format!(
+            r#"SELECT id, data FROM "{}"{} ORDER BY seq"#,
+            self.table, clause
+        );
+        let mut query = sqlx::query(&sql);
+        for param in params {
+            query = query.bind(param);
+        }
+        let rows = query
+            .fetch_all(&self.db.pool)
+            .await
+            .map_err(|err| self.db_error(err))?;
+
+        let mut found = Vec::new();
+        for row in rows {
+            let id: String = row.try_get("id").map_err(|err| self.db_error(err))?;
+            let data: String = row.try_get("data").map_err(|err| self.db_error(err))?;
+            let record = self.decode(&id, &data)?;
+            if self.indexes.is_expired(&record) {
+                self.delete_id(&id).await?;
+                continue;
+            }
+            if matches(&record, filter).map_err(|err| self.error(StatusCode::BAD_REQUEST, err))? {
+                found.push(Stored { id, data, record });
+            }
+        }
+
+        Ok(found)
+    }
+
+    async fn delete_id(&self, id: &str) -> Result<u64, (StatusCode, String)> {
+        let sql = format!(
+            r#"DELETE FROM "{}" WHERE id = {}"#,
+            self.table,
+            self.placeholder(1)
+        );
+        let result = sqlx::query(&sql)
+            .bind(id.to_owned())
+            .execute(&self.db.pool)
+            .await
+            .map_err(|err| self.db_error(err))?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Applies `new_record` like `$set` to the row read as `row`, bumping its
+    /// version if `versioned`, and only writing if it is unchanged since so
+    /// concurrent updates can't be lost. Returns whether the row was matched
+    /// and whether it was modified.
+    async fn update_row(
+        &self,
+        mut row: Stored,
+        filter: &Document,
+        new_record: &Document,
+        versioned: bool,
+    ) -> Result<(bool, bool), (StatusCode, String)> {
+        for _ in 0..UPDATE_ATTEMPTS {
+            let mut updated = row.record.clone();
+            for (key, value) in new_record {
+                updated.insert(key.to_owned(), value.clone());
+            }
+            if versioned {
+                bump_version(&mut updated);
+            }
+            if updated == row.record {
+                return Ok((true, false));
+            }
+
+            let sql = format!(
+                r#"UPDATE "{}" SET data = {} WHERE id = {} AND data = {}"#,
+                self.table,
+                self.placeholder(1),
+                self.placeholder(2),
+                self.placeholder(3)
+            );
+            let result = sqlx::query(&sql)
+                .bind(self.encode(updated))
+                .bind(row.id.clone())
+                .bind(row.data.clone())
+                .execute(&self.db.pool)
+                .await
+                .map_err(|err| self.db_error(err))?;
+            if result.rows_affected() == 1 {
+                return Ok((true, true));
+            }
+
+            // Changed in the meantime, so check the filter against the latest data.
+            let mut filter = filter.clone();
+            filter.insert("_id", self.parse_id(&row.id)?);
+            row = match self.scan(&filter).await?.pop() {
+                Some(row) => row,
+                None => return Ok((false, false)),
+            };
+        }
+
+        Err(self.error(
+            StatusCode::CONFLICT,
+            "Record is being modified concurrently",
+        ))
+    }
+
+    async fn update(
+        &self,
+        filter: Document,
+        new_record: Document,
+        many: bool,
+        versioned: bool,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        let mut result = UpdateResult {
+            matched_count: 0,
+            modified_count: 0,
+        };
+        for row in self.scan(&filter).await? {
+            let (matched, modified) = self
+                .update_row(row, &filter, &new_record, versioned)
+                .await?;
+            result.matched_count += matched as u64;
+            result.modified_count += modified as u64;
+            if matched && !many {
+                break;
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl<T> Repository<T> for Sql<T>
+where
+    T: Serialize + DeserializeOwned + Unpin + Send + Sync,
+{
+    async fn create_record(
+        &self,
+        new_record: T,
+        actor: Option<&str>,
+    ) -> Result<InsertResult, (StatusCode, String)> {
+        let id = ObjectId::new().to_hex();
+        let mut record =
+            to_document(&new_record).map_err(|err| self.error(StatusCode::BAD_REQUEST, err))?;
+        stamp_created(&mut record, actor);
+        let sql = format!(
+            r#"INSERT INTO "{}" (id, data) VALUES ({}, {})"#,
+            self.table,
+            self.placeholder(1),
+            self.placeholder(2)
+        );
+        sqlx::query(&sql)
+            .bind(id.clone())
+            .bind(self.encode(record))
+            .execute(&self.db.pool)
+            .await
+            .map_err(|err| self.db_error(err))?;
+
+        Ok(InsertResult { inserted_id: id })
+    }
+
+    async fn get_all_record(&self) -> Result<Vec<T>, (StatusCode, String)> {
+        self.find_record(Document::new()).await
+    }
+
+    async fn find_record(&self, filter: Document) -> Result<Vec<T>, (StatusCode, String)> {
+        self.scan(&filter)
+            .await?
+            .into_iter()
+            .map(|row| self.deserialize(row.record))
+            .collect()
+    }
+
+    async fn find_one_record(&self, filter: Document) -> Result<T, (StatusCode, String)> {
+        match self.scan(&filter).await?.into_iter().next() {
+            Some(row) => self.deserialize(row.record),
+            None => Err(self.error(StatusCode::NOT_FOUND, "ID not found")),
+        }
+    }
+
+    async fn query_records(
+        &self,
+        query: RecordQuery,
+    ) -> Result<Vec<Document>, (StatusCode, String)> {
+        let mut found: Vec<Document> = self
+            .scan(&query.filter)
+            .await?
+            .into_iter()
+            .map(|row| row.record)
+            .collect();
+        sort(&mut found, &query.sort);
+
+        let page = found
+            .into_iter()
+            .skip(query.skip as usize)
+            .take(query.limit.map_or(usize::MAX, |limit| limit as usize));
+        Ok(match &query.projection {
+            Some(fields) => page.map(|record| project(record, fields)).collect(),
+            None => page.collect(),
+        })
+    }
+
+    async fn count_records(&self, filter: Document) -> Result<u64, (StatusCode, String)> {
+        Ok(self.scan(&filter).await?.len() as u64)
+    }
+
+    async fn get_record(&self, id: &str) -> Result<T, (StatusCode, String)> {
+        let obj_id = self.parse_id(id)?;
+        self.find_one_record(doc! {"_id": obj_id}).await
+    }
+
+    async fn update_record(
+        &self,
+        id: &str,
+        mut new_record: Document,
+        actor: Option<&str>,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        let obj_id = self.parse_id(id)?;
+        if new_record.is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "No schema data fields to update".to_owned(),
+            ));
+        }
+        stamp_updated(&mut new_record, actor);
+        self.update(doc! {"_id": obj_id}, new_record, false, true)
+            .await
+    }
+
+    async fn update_many_records(
+        &self,
+        filter: Document,
+        mut new_record: Document,
+        actor: Option<&str>,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        stamp_updated(&mut new_record, actor);
+        self.update(filter, new_record, true, true).await
+    }
+
+    async fn update_unstamped(
+        &self,
+        filter: Document,
+        fields: Document,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        self.update(filter, fields, true, false).await
+    }
+
+    async fn delete_record(&self, id: &str) -> Result<DeleteResult, (StatusCode, String)> {
+        let obj_id = self.parse_id(id)?;
+        let deleted_count = self.delete_id(&obj_id.to_hex()).await?;
+
+        Ok(DeleteResult { deleted_count })
+    }
+
+    async fn delete_many_records(
+        &self,
+        filter: Document,
+    ) -> Result<DeleteResult, (StatusCode, String)> {
+        let mut deleted_count = 0;
+        for row in self.scan(&filter).await? {
+            deleted_count += self.delete_id(&row.id).await?;
+        }
+
+        Ok(DeleteResult { deleted_count })
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::{
+        model::{oidc_model::OidcLogin, user_model::User},
+        repository::backend_tests::backend_tests,
+    };
+
+    backend_tests!(
+        async { Sql::<User>::init(&connect("sqlite::memory:").await, "User") },
+        async { Sql::<OidcLogin>::init(&connect("sqlite::memory:").await, "OidcLogin") }
+    );
+}
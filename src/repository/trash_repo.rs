@@ -0,0 +1,199 @@
+use super::{
+    id_filter, DeleteResult, InsertResult, RecordQuery, Repository, TrashRepository, UpdateResult,
+};
+use actix_web::http::StatusCode;
+use async_trait::async_trait;
+use mongodb::bson::{doc, Bson, DateTime, Document};
+use std::sync::Arc;
+
+/// Soft deletes the records of another repository by stamping them with
+/// `deleted_at` and `deleted_by`, and hides them from everything else.
+/// Moving records in and out of the trash leaves their content, and so
+/// their version and revisions, as they are.
+///
+/// Trashed records still count towards unique indexes until purged.
+pub struct Trash<T> {
+    inner: Arc<dyn Repository<T>>,
+    /// Whether this is the view of trashed records rather than live ones.
+    trashed: bool,
+}
+
+impl<T> Trash<T> {
+    pub fn init(inner: Arc<dyn Repository<T>>) -> Self {
+        Trash {
+            inner,
+            trashed: false,
+        }
+    }
+
+    fn view(&self, trashed: bool) -> Self {
+        Trash {
+            inner: self.inner.clone(),
+            trashed,
+        }
+    }
+
+    /// Narrows `filter` to the records this view shows.
+    fn scope(&self, filter: Document) -> Document {
+        let deleted_at = match self.trashed {
+            true => Bson::Document(doc! {"$ne": null}),
+            false => Bson::Null,
+        };
+        if filter.contains_key("deleted_at") {
+            return doc! {"$and": [filter, {"deleted_at": deleted_at}]};
+        }
+        let mut filter = filter;
+        filter.insert("deleted_at", deleted_at);
+        filter
+    }
+
+    fn by_id(&self, id: &str) -> Result<Document, (StatusCode, String)> {
+        Ok(self.scope(id_filter(id)?))
+    }
+}
+
+/// Keeps updates from moving records in or out of the trash.
+fn untrashed(mut update: Document) -> Document {
+    update.remove("deleted_at");
+    update.remove("deleted_by");
+    update
+}
+
+#[async_trait]
+impl<T: Send + Sync + 'static> Repository<T> for Trash<T> {
+    async fn create_record(
+        &self,
+        new_record: T,
+        actor: Option<&str>,
+    ) -> Result<InsertResult, (StatusCode, String)> {
+        if self.trashed {
+            return Err((
+                StatusCode::METHOD_NOT_ALLOWED,
+                "Records can't be created in the trash".to_owned(),
+            ));
+        }
+        self.inner.create_record(new_record, actor).await
+    }
+
+    async fn get_all_record(&self) -> Result<Vec<T>, (StatusCode, String)> {
+        self.inner.find_record(self.scope(doc! {})).await
+    }
+
+    async fn find_record(&self, filter: Document) -> Result<Vec<T>, (StatusCode, String)> {
+        self.inner.find_record(self.scope(filter)).await
+    }
+
+    async fn find_one_record(&self, filter: Document) -> Result<T, (StatusCode, String)> {
+        self.inner.find_one_record(self.scope(filter)).await
+    }
+
+    async fn query_records(
+        &self,
+        mut query: RecordQuery,
+    ) -> Result<Vec<Document>, (StatusCode, String)> {
+        query.filter = self.scope(query.filter);
+        self.inner.query_records(query).await
+    }
+
+    async fn count_records(&self, filter: Document) -> Result<u64, (StatusCode, String)> {
+        self.inner.count_records(self.scope(filter)).await
+    }
+
+    async fn get_record(&self, id: &str) -> Result<T, (StatusCode, String)> {
+        self.inner.find_one_record(self.by_id(id)?).await
+    }
+
+    async fn update_record(
+        &self,
+        id: &str,
+        new_record: Document,
+        actor: Option<&str>,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        let filter = self.by_id(id)?;
+        let new_record = untrashed(new_record);
+        if new_record.is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "No schema data fields to update".to_owned(),
+            ));
+        }
+        self.inner
+            .update_many_records(filter, new_record, actor)
+            .await
+    }
+
+    async fn update_many_records(
+        &self,
+        filter: Document,
+        new_record: Document,
+        actor: Option<&str>,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        self.inner
+            .update_many_records(self.scope(filter), untrashed(new_record), actor)
+            .await
+    }
+
+    async fn update_unstamped(
+        &self,
+        filter: Document,
+        fields: Document,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        self.inner
+            .update_unstamped(self.scope(filter), untrashed(fields))
+            .await
+    }
+
+    /// Moves a live record to the trash, or purges a trashed one.
+    async fn delete_record(&self, id: &str) -> Result<DeleteResult, (StatusCode, String)> {
+        let filter = self.by_id(id)?;
+        match self.trashed {
+            true => self.inner.delete_many_records(filter).await,
+            false => self.trash_records(filter, None).await,
+        }
+    }
+
+    async fn delete_many_records(
+        &self,
+        filter: Document,
+    ) -> Result<DeleteResult, (StatusCode, String)> {
+        match self.trashed {
+            true => self.inner.delete_many_records(self.scope(filter)).await,
+            false => self.trash_records(filter, None).await,
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Send + Sync + 'static> TrashRepository<T> for Trash<T> {
+    async fn trash_records(
+        &self,
+        filter: Document,
+        actor: Option<&str>,
+    ) -> Result<DeleteResult, (StatusCode, String)> {
+        let result = self
+            .inner
+            .update_unstamped(
+                self.scope(filter),
+                doc! {"deleted_at": DateTime::now(), "deleted_by": actor},
+            )
+            .await?;
+        Ok(DeleteResult {
+            deleted_count: result.matched_count,
+        })
+    }
+
+    async fn restore_record(
+        &self,
+        id: &str,
+        _actor: Option<&str>,
+    ) -> Result<UpdateResult, (StatusCode, String)> {
+        let filter = self.view(true).by_id(id)?;
+        self.inner
+            .update_unstamped(filter, doc! {"deleted_at": null, "deleted_by": null})
+            .await
+    }
+
+    fn trash(&self) -> Arc<dyn Repository<T>> {
+        Arc::new(self.view(true))
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{
+    model::{api_key_model::ApiKey, user_model::User},
+    repository::Repository,
+    service::token_service,
+};
+use actix_web::{
+    http::{Method, StatusCode},
+    HttpRequest,
+};
+use log::warn;
+use mongodb::bson::{doc, DateTime};
+
+const KEY_PREFIX: &str = "ava_";
+const DISPLAYED_PREFIX_LENGTH: usize = 12;
+
+/// Resources API keys can be scoped to, named after their route under `/api`.
+pub const RESOURCES: &[&str] = &["details", "tech-stack", "projects", "experiences", "users"];
+
+/// Returns a new plain text API key. Only its hash is ever stored.
+pub fn generate_key() -> String {
+    format!("{}{}", KEY_PREFIX, token_service::random_token())
+}
+
+pub fn displayed_prefix(key: &str) -> String {
+    key.chars().take(DISPLAYED_PREFIX_LENGTH).collect()
+}
+
+/// Whether `scope` is either `<resource>:read`, `<resource>:write`,
+/// `read:all` or `write:all`.
+pub fn is_valid_scope(scope: &str) -> bool {
+    match scope.split_once(':') {
+        Some(("read", "all")) | Some(("write", "all")) => true,
+        Some((resource, "read")) | Some((resource, "write")) => RESOURCES.contains(&resource),
+        _ => false,
+    }
+}
+
+/// The scope a request needs: the first path segment after `/api` as the
+/// resource, and `read` for safe methods or `write` for everything else.
+fn required_scope(req: &HttpRequest) -> (String, &'static str) {
+    let resource = req
+        .path()
+        .trim_start_matches("/api/")
+        .split('/')
+        .next()
+        .unwrap_or_default()
+        .to_owned();
+    let access = match *req.method() {
+        Method::GET | Method::HEAD | Method::OPTIONS => "read",
+        _ => "write",
+    };
+    (resource, access)
+}
+
+pub fn allows(scopes: &[String], req: &HttpRequest) -> bool {
+    let (resource, access) = required_scope(req);
+    scopes.iter().any(|scope| {
+        *scope == format!("{}:{}", resource, access) || *scope == format!("{}:all", access)
+    })
+}
+
+/// Looks up the key and its owner, recording when it was last used.
+pub async fn authenticate(
+    api_key_db: &dyn Repository<ApiKey>,
+    user_db: &dyn Repository<User>,
+    key: &str,
+) -> Result<(ApiKey, User), (StatusCode, String)> {
+    let api_key = match api_key_db
+        .find_one_record(doc! {"key_hash": token_service::hash_token(key)})
+        .await
+    {
+        Ok(api_key) => api_key,
+        Err((StatusCode::NOT_FOUND, _)) => {
+            return Err((StatusCode::UNAUTHORIZED, "Invalid API key".to_owned()))
+        }
+        Err(err) => return Err(err),
+    };
+    let user = match user_db.get_record(&api_key.user_id).await {
+        Ok(user) => user,
+        Err((StatusCode::NOT_FOUND, _)) => {
+            return Err((StatusCode::UNAUTHORIZED, "Invalid API key".to_owned()))
+        }
+        Err(err) => return Err(err),
+    };
+
+    if let Some(id) = api_key._id {
+        let doc = doc! {"last_used_at": DateTime::now()};
+        if let Err((_, err)) = api_key_db.update_unstamped(doc! {"_id": id}, doc).await {
+            warn!("Failed to record API key usage: {}", err);
+        }
+    }
+
+    Ok((api_key, user))
+}
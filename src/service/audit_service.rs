@@ -0,0 +1,79 @@
+use crate::{
+    model::{
+        audit_model::{AuditAction, AuditEntry},
+        metadata_model::Metadata,
+    },
+    repository::{RecordQuery, Repository},
+    service::query_service::render,
+};
+use actix_web::{web::Data, HttpRequest};
+use log::error;
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use std::any::type_name;
+
+/// Fields never written to the audit log, whatever the model.
+const SECRET_FIELDS: &[&str] = &[
+    "password",
+    "totp_secret",
+    "totp_pending_secret",
+    "totp_last_step",
+    "recovery_codes",
+    "key_hash",
+];
+
+/// The record with `id` as stored, if `db` has it.
+pub async fn snapshot<T>(db: &dyn Repository<T>, id: &str) -> Option<Document> {
+    let id = ObjectId::parse_str(id).ok()?;
+    db.query_records(RecordQuery {
+        filter: doc! {"_id": id},
+        ..Default::default()
+    })
+    .await
+    .ok()?
+    .pop()
+}
+
+/// Appends a write `req` made on behalf of `actor` to the record with `id`
+/// to the audit log, with the record as it was `before` and as `db` has it
+/// now. The write has already happened, so failing to log it only logs an
+/// error.
+pub async fn record<T>(
+    req: &HttpRequest,
+    db: &dyn Repository<T>,
+    action: AuditAction,
+    id: &str,
+    actor: Option<&str>,
+    before: Option<Document>,
+) {
+    let audit_db = match req.app_data::<Data<dyn Repository<AuditEntry>>>() {
+        Some(audit_db) => audit_db,
+        None => {
+            error!("Audit log is not configured");
+            return;
+        }
+    };
+    let secrets: Vec<String> = SECRET_FIELDS
+        .iter()
+        .map(|field| field.to_string())
+        .collect();
+    let entry = AuditEntry {
+        _id: None,
+        actor: actor.map(str::to_owned),
+        action,
+        resource: type_name::<T>()
+            .rsplit("::")
+            .next()
+            .unwrap_or_default()
+            .to_owned(),
+        record_id: id.to_owned(),
+        before: before.map(|record| render(record, &secrets)),
+        after: snapshot(db, id)
+            .await
+            .map(|record| render(record, &secrets)),
+        ip: req.peer_addr().map(|addr| addr.ip().to_string()),
+        metadata: Metadata::default(),
+    };
+    if let Err((_, err)) = audit_db.create_record(entry, actor).await {
+        error!("Failed to write audit log: {}", err);
+    }
+}
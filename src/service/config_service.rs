@@ -0,0 +1,10 @@
+use std::str::FromStr;
+
+/// Reads and parses the environment variable `var`, falling back to `default`
+/// when it is unset or invalid.
+pub fn env_or<T: FromStr>(var: &str, default: T) -> T {
+    dotenv::var(var)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
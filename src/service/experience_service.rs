@@ -0,0 +1,149 @@
+use crate::{
+    model::{
+        experience_model::{Experience, PartialDate},
+        revision_model::Revision,
+    },
+    repository::{RecordQuery, Repository, TrashRepository},
+};
+use actix_web::http::StatusCode;
+use log::warn;
+use mongodb::bson::{doc, Bson, Document};
+
+const MONTHS: [&str; 12] = [
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+
+/// What records written before dates were structured put in `end` for a
+/// position still held.
+const ONGOING: &[&str] = &["", "present", "current", "now", "ongoing", "today"];
+
+fn month(token: &str) -> Option<u8> {
+    match token.parse::<u8>() {
+        Ok(month) => Some(month).filter(|month| (1..=12).contains(month)),
+        Err(_) if token.len() >= 3 => MONTHS
+            .iter()
+            .position(|name| name.starts_with(token))
+            .map(|index| index as u8 + 1),
+        Err(_) => None,
+    }
+}
+
+/// Reads a date stored as free text, like `2020-05-14T00:00:00Z`, `05/2020`,
+/// `May 2020` or just `2020`, which is taken as its first month for a start
+/// and its last for an `end`.
+fn legacy_date(date: &str, end: bool) -> Option<PartialDate> {
+    let date = date.trim();
+    if let Ok(date) = date
+        .parse()
+        .or_else(|_| date.get(..10).unwrap_or_default().parse())
+    {
+        return Some(date);
+    }
+    let lowercase = date.to_lowercase();
+    let (years, others): (Vec<&str>, Vec<&str>) = lowercase
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .partition(|token| token.len() == 4 && token.bytes().all(|byte| byte.is_ascii_digit()));
+    let year = match years.as_slice() {
+        [year] => year.parse().ok()?,
+        _ => return None,
+    };
+    let month = match others.as_slice() {
+        [] if end => 12,
+        [] => 1,
+        [token] => month(token)?,
+        _ => return None,
+    };
+    Some(PartialDate {
+        year,
+        month,
+        day: None,
+    })
+}
+
+/// The `start` and `end` of a legacy `record` rewritten as dates, empty if
+/// they already are. Values that can't be read are logged and cleared, so an
+/// editor has to set them again; a cleared `end` reads as still held.
+fn migrated(record: &Document, id: &str) -> Document {
+    let mut update = Document::new();
+    for field in ["start", "end"] {
+        let value = match record.get(field) {
+            Some(Bson::String(value)) if value.parse::<PartialDate>().is_err() => value.to_owned(),
+            Some(Bson::DateTime(date)) => date.try_to_rfc3339_string().unwrap_or_default(),
+            None | Some(Bson::Null) | Some(Bson::String(_)) => continue,
+            Some(other) => other.to_string(),
+        };
+        if field == "end" && ONGOING.contains(&value.trim().to_lowercase().as_str()) {
+            update.insert(field, Bson::Null);
+            continue;
+        }
+        match legacy_date(&value, field == "end") {
+            Some(date) => {
+                update.insert(field, date.to_string());
+            }
+            None => {
+                warn!(
+                    "Cannot read {} {:?} of Experience {}, clearing it",
+                    field, value, id
+                );
+                update.insert(field, Bson::Null);
+            }
+        }
+    }
+    update
+}
+
+async fn records<T>(db: &dyn Repository<T>) -> Result<Vec<Document>, (StatusCode, String)> {
+    db.query_records(RecordQuery::default()).await
+}
+
+/// Rewrites the free text `start` and `end` of the experiences, trashed ones
+/// and revisions included, as [`PartialDate`]s, an `end` like `Present`
+/// becoming `null`.
+pub async fn migrate_dates(
+    db: &dyn TrashRepository<Experience>,
+    revisions: &dyn Repository<Revision<Experience>>,
+) -> Result<(), (StatusCode, String)> {
+    let trash = db.trash();
+    for view in [db as &dyn Repository<Experience>, trash.as_ref()] {
+        for record in records(view).await? {
+            let record_id = match record.get_object_id("_id") {
+                Ok(record_id) => record_id,
+                Err(_) => continue,
+            };
+            let update = migrated(&record, &record_id.to_hex());
+            if !update.is_empty() {
+                view.update_unstamped(doc! {"_id": record_id}, update)
+                    .await?;
+            }
+        }
+    }
+    for revision in records(revisions).await? {
+        let (revision_id, mut record) = match (
+            revision.get_object_id("_id"),
+            revision.get_document("record"),
+        ) {
+            (Ok(id), Ok(record)) => (id, record.clone()),
+            _ => continue,
+        };
+        let update = migrated(&record, revision.get_str("record_id").unwrap_or_default());
+        if !update.is_empty() {
+            record.extend(update);
+            revisions
+                .update_unstamped(doc! {"_id": revision_id}, doc! {"record": record})
+                .await?;
+        }
+    }
+    Ok(())
+}
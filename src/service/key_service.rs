@@ -0,0 +1,276 @@
+use actix_web::http::StatusCode;
+use data_encoding::BASE64URL_NOPAD;
+use ed25519_dalek::{
+    pkcs8::{DecodePrivateKey, DecodePublicKey},
+    SigningKey, VerifyingKey,
+};
+use jsonwebtoken::{
+    decode, decode_header, encode,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use log::info;
+use rsa::{
+    pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey},
+    traits::PublicKeyParts,
+    RsaPrivateKey, RsaPublicKey,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use std::{fs, sync::OnceLock};
+
+/// A key tokens are accepted from, published in the JWKS.
+struct VerificationKey {
+    algorithm: Algorithm,
+    key: DecodingKey,
+    jwk: Jwk,
+}
+
+/// The key tokens are signed with and every key they are verified with.
+///
+/// Rotating keys works by signing with the new key while the previous public
+/// keys are still listed in `JWT_VERIFICATION_KEY_FILES`, until tokens signed
+/// with them have expired.
+pub struct KeyStore {
+    kid: String,
+    algorithm: Algorithm,
+    signing_key: EncodingKey,
+    verification_keys: Vec<VerificationKey>,
+}
+
+fn invalid_token() -> (StatusCode, String) {
+    (StatusCode::UNAUTHORIZED, "Invalid token".to_owned())
+}
+
+/// The `typ` header of tokens of `kind`.
+fn typ(kind: &str) -> String {
+    format!("{}+jwt", kind)
+}
+
+fn read_pem(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|err| format!("error reading key {}: {}", path, err))
+}
+
+fn rsa_parameters(key: &RsaPublicKey) -> AlgorithmParameters {
+    AlgorithmParameters::RSA(RSAKeyParameters {
+        key_type: RSAKeyType::RSA,
+        n: BASE64URL_NOPAD.encode(&key.n().to_bytes_be()),
+        e: BASE64URL_NOPAD.encode(&key.e().to_bytes_be()),
+    })
+}
+
+fn ed25519_parameters(key: &VerifyingKey) -> AlgorithmParameters {
+    AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+        key_type: OctetKeyPairType::OctetKeyPair,
+        curve: EllipticCurve::Ed25519,
+        x: BASE64URL_NOPAD.encode(key.as_bytes()),
+    })
+}
+
+/// Parses a public key in SPKI or, for RSA, PKCS#1 PEM.
+fn public_key_parameters(pem: &str) -> Option<(Algorithm, AlgorithmParameters)> {
+    if let Ok(key) = VerifyingKey::from_public_key_pem(pem) {
+        return Some((Algorithm::EdDSA, ed25519_parameters(&key)));
+    }
+    RsaPublicKey::from_public_key_pem(pem)
+        .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+        .ok()
+        .map(|key| (Algorithm::RS256, rsa_parameters(&key)))
+}
+
+/// Parses a private key in PKCS#8 or, for RSA, PKCS#1 PEM, returning the
+/// parameters of its public half.
+fn private_key_parameters(pem: &str) -> Option<(Algorithm, AlgorithmParameters)> {
+    if let Ok(key) = SigningKey::from_pkcs8_pem(pem) {
+        return Some((Algorithm::EdDSA, ed25519_parameters(&key.verifying_key())));
+    }
+    RsaPrivateKey::from_pkcs8_pem(pem)
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+        .ok()
+        .map(|key| (Algorithm::RS256, rsa_parameters(&key.to_public_key())))
+}
+
+/// The RFC 7638 thumbprint of a public key, used as its default `kid`.
+fn thumbprint(parameters: &AlgorithmParameters) -> String {
+    // Only the required members, in lexicographic order and without spaces.
+    let canonical = match parameters {
+        AlgorithmParameters::RSA(rsa) => {
+            format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, rsa.e, rsa.n)
+        }
+        AlgorithmParameters::OctetKeyPair(okp) => {
+            format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, okp.x)
+        }
+        _ => String::new(),
+    };
+
+    BASE64URL_NOPAD.encode(&Sha256::digest(canonical.as_bytes()))
+}
+
+fn verification_key(
+    kid: String,
+    algorithm: Algorithm,
+    parameters: AlgorithmParameters,
+) -> Result<VerificationKey, String> {
+    let jwk = Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(match algorithm {
+                Algorithm::EdDSA => KeyAlgorithm::EdDSA,
+                _ => KeyAlgorithm::RS256,
+            }),
+            key_id: Some(kid),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    };
+    let key = DecodingKey::from_jwk(&jwk).map_err(|err| err.to_string())?;
+
+    Ok(VerificationKey {
+        algorithm,
+        key,
+        jwk,
+    })
+}
+
+impl KeyStore {
+    /// Reads the private key to sign with from the PEM file `JWT_PRIVATE_KEY_FILE`,
+    /// either RSA for RS256 or Ed25519 for EdDSA, and its `kid` from
+    /// `JWT_KEY_ID`, defaulting to the key's thumbprint.
+    ///
+    /// Previous keys still accepted are read from `JWT_VERIFICATION_KEY_FILES`,
+    /// a comma separated list of public key PEM files, each optionally
+    /// prefixed with `<kid>=`.
+    pub fn from_env() -> Result<Self, String> {
+        let path = dotenv::var("JWT_PRIVATE_KEY_FILE")
+            .map_err(|_| "JWT_PRIVATE_KEY_FILE must be set".to_owned())?;
+        let pem = read_pem(&path)?;
+        let (algorithm, parameters) = private_key_parameters(&pem)
+            .ok_or(format!("{} is not an RSA or Ed25519 private key", path))?;
+        let signing_key = match algorithm {
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(pem.as_bytes()),
+            _ => EncodingKey::from_rsa_pem(pem.as_bytes()),
+        }
+        .map_err(|err| format!("error loading key {}: {}", path, err))?;
+        let kid = dotenv::var("JWT_KEY_ID").unwrap_or_else(|_| thumbprint(&parameters));
+        info!("Signing tokens with {:?} key {}", algorithm, kid);
+
+        let mut verification_keys = vec![verification_key(kid.clone(), algorithm, parameters)?];
+        let previous = dotenv::var("JWT_VERIFICATION_KEY_FILES").unwrap_or_default();
+        for entry in previous
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (kid, path) = match entry.split_once('=') {
+                Some((kid, path)) => (Some(kid.to_owned()), path),
+                None => (None, entry),
+            };
+            let (algorithm, parameters) = public_key_parameters(&read_pem(path)?)
+                .ok_or(format!("{} is not an RSA or Ed25519 public key", path))?;
+            let kid = kid.unwrap_or_else(|| thumbprint(&parameters));
+            info!("Accepting tokens signed with {:?} key {}", algorithm, kid);
+            verification_keys.push(verification_key(kid, algorithm, parameters)?);
+        }
+
+        Ok(KeyStore {
+            kid,
+            algorithm,
+            signing_key,
+            verification_keys,
+        })
+    }
+
+    /// Signs `claims` as a token of `kind`, named in its `typ` header; the
+    /// claims carry it as their `aud`.
+    pub fn sign<C: Serialize>(
+        &self,
+        claims: &C,
+        kind: &str,
+    ) -> Result<String, (StatusCode, String)> {
+        let mut header = Header::new(self.algorithm);
+        header.kid = Some(self.kid.clone());
+        header.typ = Some(typ(kind));
+
+        encode(&header, claims, &self.signing_key)
+            .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+    }
+
+    /// Checks the signature against the key named by the token's `kid`, and
+    /// that `typ` and `aud` both name `kind` so one kind of token never passes
+    /// for another. Expiry is left to the caller.
+    pub fn verify<C: DeserializeOwned>(
+        &self,
+        token: &str,
+        kind: &str,
+    ) -> Result<C, (StatusCode, String)> {
+        let header = decode_header(token).map_err(|_| invalid_token())?;
+        if header.typ != Some(typ(kind)) {
+            return Err(invalid_token());
+        }
+        let kid = header.kid.ok_or_else(invalid_token)?;
+        let key = self
+            .verification_keys
+            .iter()
+            .find(|key| key.jwk.common.key_id.as_ref() == Some(&kid))
+            .filter(|key| key.algorithm == header.alg)
+            .ok_or_else(invalid_token)?;
+
+        let mut validation = Validation::new(key.algorithm);
+        validation.validate_exp = false;
+        validation.set_required_spec_claims(&["aud"]);
+        validation.set_audience(&[kind]);
+
+        decode::<C>(token, &key.key, &validation)
+            .map(|data| data.claims)
+            .map_err(|_| invalid_token())
+    }
+
+    /// The public keys tokens are verified with, as served at
+    /// `/.well-known/jwks.json`.
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .verification_keys
+                .iter()
+                .map(|key| key.jwk.clone())
+                .collect(),
+        }
+    }
+}
+
+/// The keys loaded from the environment on first use.
+pub fn keys() -> Result<&'static KeyStore, (StatusCode, String)> {
+    static KEYS: OnceLock<Result<KeyStore, String>> = OnceLock::new();
+    KEYS.get_or_init(KeyStore::from_env)
+        .as_ref()
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_owned()))
+}
+
+/// A fresh Ed25519 private key in PKCS#8 PEM.
+#[cfg(test)]
+pub fn generate_test_key() -> String {
+    use ed25519_dalek::pkcs8::{spki::der::pem::LineEnding, EncodePrivateKey};
+    use rand_core::{OsRng, RngCore};
+
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    SigningKey::from_bytes(&secret)
+        .to_pkcs8_pem(LineEnding::LF)
+        .expect("error encoding test key")
+        .to_string()
+}
+
+/// Points `JWT_PRIVATE_KEY_FILE` at a key generated for this test run,
+/// before [`keys`] reads it.
+#[cfg(test)]
+pub fn init_test_keys() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        let path = std::env::temp_dir().join(format!("ava-test-{}.pem", std::process::id()));
+        fs::write(&path, generate_test_key()).expect("error writing test key");
+        std::env::set_var("JWT_PRIVATE_KEY_FILE", path);
+    });
+}
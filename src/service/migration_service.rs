@@ -0,0 +1,107 @@
+use crate::{
+    model::{metadata_model::Metadata, migration_model::Migration, user_model::User},
+    repository::{Repository, TrashRepository},
+    service::validation_service::normalize_email,
+};
+use actix_web::http::StatusCode;
+use log::{error, info};
+use mongodb::bson::doc;
+use std::{collections::BTreeMap, future::Future};
+
+/// Runs the data `migration` named `name` unless it already completed, and
+/// records it once it does so the next start skips it.
+pub async fn run_once<F>(
+    db: &dyn Repository<Migration>,
+    name: &str,
+    migration: F,
+) -> Result<(), (StatusCode, String)>
+where
+    F: Future<Output = Result<(), (StatusCode, String)>>,
+{
+    match db.count_records(doc! {"name": name}).await? {
+        0 => {}
+        _ => return Ok(()),
+    }
+    info!("Running data migration {}", name);
+    migration.await?;
+    let migration = Migration {
+        _id: None,
+        name: name.to_owned(),
+        metadata: Metadata::default(),
+    };
+    db.create_record(migration, None).await?;
+
+    Ok(())
+}
+
+/// Stores the emails of users as [`normalize_email`] would. Users whose
+/// addresses only differ in case are left as they are and logged, since
+/// which account to keep is for an admin to decide; the ones not stored
+/// normalized can't sign in until their address is changed.
+/// Trashed users are included, as they still hold their address.
+pub async fn normalize_user_emails(
+    db: &dyn TrashRepository<User>,
+) -> Result<(), (StatusCode, String)> {
+    let mut by_email: BTreeMap<String, Vec<User>> = BTreeMap::new();
+    let mut users = db.get_all_record().await?;
+    users.extend(db.trash().get_all_record().await?);
+    for user in users {
+        by_email
+            .entry(normalize_email(&user.email))
+            .or_default()
+            .push(user);
+    }
+
+    for (email, users) in by_email {
+        match users.as_slice() {
+            [user] if user.email != email => {
+                if let Some(id) = user._id {
+                    let filter = doc! {"_id": id};
+                    // Only one of the views holds the user.
+                    db.update_unstamped(filter.clone(), doc! {"email": &email})
+                        .await?;
+                    db.trash()
+                        .update_unstamped(filter, doc! {"email": &email})
+                        .await?;
+                }
+            }
+            [_] => {}
+            users => {
+                let accounts: Vec<String> = users
+                    .iter()
+                    .map(|user| {
+                        let id = user._id.map(|id| id.to_hex()).unwrap_or_default();
+                        format!("{} ({})", user.email, id)
+                    })
+                    .collect();
+                error!(
+                    "Users share the address {}, change all but one of them: {}",
+                    email,
+                    accounts.join(", ")
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the addresses of users trashed before addresses were freed on
+/// delete, see [`User::tombstone`].
+pub async fn tombstone_trashed_users(
+    db: &dyn TrashRepository<User>,
+) -> Result<(), (StatusCode, String)> {
+    for user in db
+        .trash()
+        .find_record(doc! {"deleted_email": {"$exists": false}})
+        .await?
+    {
+        if let Some(id) = user._id {
+            db.trash()
+                .update_unstamped(doc! {"_id": id}, User::tombstone(&id, &user.email))
+                .await?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,14 @@
+pub mod api_key_service;
+pub mod audit_service;
+pub mod config_service;
+pub mod experience_service;
+pub mod key_service;
+pub mod migration_service;
+pub mod oidc_service;
+pub mod query_service;
+pub mod reference_service;
+pub mod throttle_service;
+pub mod token_service;
+pub mod totp_service;
+pub mod trash_service;
+pub mod validation_service;
@@ -0,0 +1,272 @@
+use crate::{model::oidc_model::IdTokenClaims, service::config_service::env_or};
+use actix_web::http::StatusCode;
+use data_encoding::BASE64URL_NOPAD;
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use log::info;
+use reqwest::{Client, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use sha2::{Digest, Sha256};
+use std::{fmt::Display, sync::RwLock, time::Duration};
+
+const DEFAULT_SCOPES: &str = "openid email";
+const DEFAULT_LOGIN_LIFETIME: u64 = 10 * 60;
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The parts of the provider's discovery document the login flow needs.
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Client for an OpenID Connect identity provider, using the authorization
+/// code flow with PKCE.
+///
+/// The discovery document and signing keys are fetched on first use and
+/// cached; the keys are fetched again when a token names an unknown `kid`.
+pub struct OidcProvider {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    scopes: String,
+    /// Seconds the user has to complete the login at the provider.
+    pub login_lifetime: u64,
+    client: Client,
+    metadata: RwLock<Option<ProviderMetadata>>,
+    jwks: RwLock<JwkSet>,
+}
+
+fn provider_error(err: impl Display) -> (StatusCode, String) {
+    (
+        StatusCode::BAD_GATEWAY,
+        format!("OIDC Provider Error: {}", err),
+    )
+}
+
+fn invalid_id_token() -> (StatusCode, String) {
+    (StatusCode::UNAUTHORIZED, "Invalid ID token".to_owned())
+}
+
+/// The PKCE `S256` challenge for `code_verifier`.
+pub fn code_challenge(code_verifier: &str) -> String {
+    BASE64URL_NOPAD.encode(&Sha256::digest(code_verifier.as_bytes()))
+}
+
+impl OidcProvider {
+    /// Reads `OIDC_ISSUER`, `OIDC_CLIENT_ID`, `OIDC_CLIENT_SECRET`,
+    /// `OIDC_REDIRECT_URI`, `OIDC_SCOPES` and `OIDC_LOGIN_LIFETIME` (in
+    /// seconds). Returns `None` when no issuer is configured.
+    pub fn from_env() -> Option<Self> {
+        let issuer = dotenv::var("OIDC_ISSUER").ok()?;
+        info!("Configuring OIDC provider {}", issuer);
+
+        Some(OidcProvider {
+            scopes: env_or("OIDC_SCOPES", DEFAULT_SCOPES.to_owned()),
+            login_lifetime: env_or("OIDC_LOGIN_LIFETIME", DEFAULT_LOGIN_LIFETIME),
+            ..OidcProvider::new(
+                issuer,
+                dotenv::var("OIDC_CLIENT_ID").expect("OIDC_CLIENT_ID must be set"),
+                dotenv::var("OIDC_CLIENT_SECRET").ok(),
+                dotenv::var("OIDC_REDIRECT_URI").expect("OIDC_REDIRECT_URI must be set"),
+            )
+        })
+    }
+
+    /// A provider with the default scopes and login lifetime.
+    pub fn new(
+        issuer: String,
+        client_id: String,
+        client_secret: Option<String>,
+        redirect_uri: String,
+    ) -> Self {
+        let client = Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .build()
+            .expect("error building OIDC HTTP client");
+
+        OidcProvider {
+            issuer,
+            client_id,
+            client_secret,
+            redirect_uri,
+            scopes: DEFAULT_SCOPES.to_owned(),
+            login_lifetime: DEFAULT_LOGIN_LIFETIME,
+            client,
+            metadata: RwLock::new(None),
+            jwks: RwLock::new(JwkSet { keys: Vec::new() }),
+        }
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T, (StatusCode, String)> {
+        self.client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)
+    }
+
+    async fn metadata(&self) -> Result<ProviderMetadata, (StatusCode, String)> {
+        if let Some(metadata) = self
+            .metadata
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .as_ref()
+        {
+            return Ok(metadata.clone());
+        }
+
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.issuer.trim_end_matches('/')
+        );
+        let metadata: ProviderMetadata = self.get_json(&url).await?;
+        if metadata.issuer != self.issuer {
+            return Err(provider_error(format!(
+                "discovery document is for issuer {}",
+                metadata.issuer
+            )));
+        }
+        *self.metadata.write().unwrap_or_else(|err| err.into_inner()) = Some(metadata.clone());
+
+        Ok(metadata)
+    }
+
+    fn cached_key(&self, kid: Option<&str>) -> Option<Jwk> {
+        let jwks = self.jwks.read().unwrap_or_else(|err| err.into_inner());
+        match kid {
+            Some(kid) => jwks.find(kid).cloned(),
+            // Without a `kid` the key is only unambiguous if there is just one.
+            None if jwks.keys.len() == 1 => jwks.keys.first().cloned(),
+            None => None,
+        }
+    }
+
+    async fn signing_key(&self, kid: Option<&str>) -> Result<Jwk, (StatusCode, String)> {
+        if let Some(jwk) = self.cached_key(kid) {
+            return Ok(jwk);
+        }
+
+        // The provider may have rotated its keys since they were cached.
+        let metadata = self.metadata().await?;
+        let jwks: JwkSet = self.get_json(&metadata.jwks_uri).await?;
+        *self.jwks.write().unwrap_or_else(|err| err.into_inner()) = jwks;
+
+        self.cached_key(kid).ok_or((
+            StatusCode::UNAUTHORIZED,
+            "Unknown ID token signing key".to_owned(),
+        ))
+    }
+
+    /// The URL to send the user to for logging in at the provider.
+    pub async fn authorization_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_verifier: &str,
+    ) -> Result<String, (StatusCode, String)> {
+        let metadata = self.metadata().await?;
+        let url = Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.client_id),
+                ("redirect_uri", &self.redirect_uri),
+                ("scope", &self.scopes),
+                ("state", state),
+                ("nonce", nonce),
+                ("code_challenge", &code_challenge(code_verifier)),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(provider_error)?;
+
+        Ok(url.into())
+    }
+
+    /// Redeems an authorization code at the token endpoint, returning the
+    /// unverified `id_token`.
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<String, (StatusCode, String)> {
+        let metadata = self.metadata().await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_uri),
+            ("client_id", &self.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(client_secret) = &self.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+
+        let response = self
+            .client
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(provider_error)?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(provider_error(format!(
+                "token endpoint responded with {}: {}",
+                status, body
+            )));
+        }
+        let tokens: TokenResponse = response.json().await.map_err(provider_error)?;
+
+        Ok(tokens.id_token)
+    }
+
+    /// Checks the signature, issuer, audience, expiry and `nonce` of an
+    /// `id_token`.
+    pub async fn verify_id_token(
+        &self,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, (StatusCode, String)> {
+        let header = decode_header(id_token).map_err(|_| invalid_id_token())?;
+        // Only accept keys published by the provider, never a shared secret.
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(invalid_id_token());
+        }
+        let jwk = self.signing_key(header.kid.as_deref()).await?;
+        let key = DecodingKey::from_jwk(&jwk).map_err(|_| invalid_id_token())?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.client_id]);
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|_| invalid_id_token())?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(invalid_id_token());
+        }
+
+        Ok(claims)
+    }
+}
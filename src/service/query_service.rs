@@ -0,0 +1,489 @@
+use crate::{
+    repository::{filter::lookup, RecordQuery},
+    service::config_service::env_or,
+};
+use actix_web::http::StatusCode;
+use data_encoding::BASE64URL_NOPAD;
+use mongodb::bson::{doc, oid::ObjectId, to_vec, Bson, DateTime, Document};
+
+const DEFAULT_MAX_PAGE_SIZE: u64 = 100;
+
+/// The metadata every record is stamped with, which can be queried like the
+/// model's own fields.
+const METADATA_FIELDS: &[&str] = &[
+    "created_at",
+    "updated_at",
+    "created_by",
+    "updated_by",
+    "deleted_at",
+    "deleted_by",
+];
+const DATE_FIELDS: &[&str] = &["created_at", "updated_at", "deleted_at"];
+
+/// A parsed list request.
+pub struct ListQuery {
+    /// The page of records, asking for one more than `limit` to tell whether
+    /// there is a next page.
+    pub records: RecordQuery,
+    /// The filter without the page position, to count every match.
+    pub filter: Document,
+    pub limit: u64,
+}
+
+fn bad_request(err: String) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, err)
+}
+
+/// Maps a field named in the query string to its stored name, rejecting any
+/// not in `fields`.
+fn field(name: &str, fields: &[&str]) -> Result<String, (StatusCode, String)> {
+    match name {
+        "id" => Ok("_id".to_owned()),
+        name if fields.contains(&name) || METADATA_FIELDS.contains(&name) => Ok(name.to_owned()),
+        name => Err(bad_request(format!("Unknown field {}", name))),
+    }
+}
+
+fn invalid_cursor() -> (StatusCode, String) {
+    bad_request("Invalid cursor".to_owned())
+}
+
+fn number(key: &str, param: &str) -> Result<u64, (StatusCode, String)> {
+    param
+        .parse()
+        .map_err(|_| bad_request(format!("Invalid {}", key)))
+}
+
+fn names(param: &str) -> impl Iterator<Item = &str> {
+    param
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+}
+
+/// Values are always compared as strings, except IDs and RFC 3339 dates, so
+/// a parameter can never smuggle in an operator of its own.
+fn value(field: &str, value: &str) -> Result<Bson, (StatusCode, String)> {
+    match field {
+        "_id" => ObjectId::parse_str(value)
+            .map(Bson::ObjectId)
+            .map_err(|_| bad_request("Invalid ID".to_owned())),
+        field if DATE_FIELDS.contains(&field) => DateTime::parse_rfc3339_str(value)
+            .map(Bson::DateTime)
+            .map_err(|_| bad_request(format!("Invalid date for {}", field))),
+        _ => Ok(Bson::String(value.to_owned())),
+    }
+}
+
+fn condition(field: &str, operator: &str, operand: &str) -> Result<Bson, (StatusCode, String)> {
+    Ok(match operator {
+        "eq" => value(field, operand)?,
+        "ne" | "gt" | "gte" | "lt" | "lte" => {
+            doc! { format!("${}", operator): value(field, operand)? }.into()
+        }
+        "in" | "nin" => {
+            let values = operand
+                .split(',')
+                .map(|operand| value(field, operand))
+                .collect::<Result<Vec<Bson>, _>>()?;
+            doc! { format!("${}", operator): values }.into()
+        }
+        operator => return Err(bad_request(format!("Unsupported operator {}", operator))),
+    })
+}
+
+/// An opaque cursor for the page after `record` in the `sort` order.
+pub fn cursor(record: &Document, sort: &Document) -> Result<String, (StatusCode, String)> {
+    let after: Vec<Bson> = sort
+        .keys()
+        .map(|field| lookup(record, field).cloned().unwrap_or(Bson::Null))
+        .collect();
+    let bytes = to_vec(&doc! {"sort": sort.clone(), "after": after})
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+    Ok(BASE64URL_NOPAD.encode(&bytes))
+}
+
+/// The condition for the records after `cursor`, which has to be for the
+/// same `sort`.
+fn after(cursor: &str, sort: &Document) -> Result<Document, (StatusCode, String)> {
+    let bytes = BASE64URL_NOPAD
+        .decode(cursor.as_bytes())
+        .map_err(|_| invalid_cursor())?;
+    let cursor = Document::from_reader(bytes.as_slice()).map_err(|_| invalid_cursor())?;
+    if !cursor
+        .get_document("sort")
+        .is_ok_and(|cursor_sort| cursor_sort.iter().eq(sort.iter()))
+    {
+        return Err(bad_request("Cursor is for a different sort".to_owned()));
+    }
+    let values = match cursor.get_array("after") {
+        Ok(values)
+            if values.len() == sort.len()
+                && !values
+                    .iter()
+                    .any(|value| matches!(value, Bson::Document(_))) =>
+        {
+            values
+        }
+        _ => return Err(invalid_cursor()),
+    };
+
+    // Records past the cursor on the first sort field, or equal on it and
+    // past it on the next, and so on.
+    let mut alternatives = Vec::new();
+    let mut equal = Vec::new();
+    for ((field, direction), value) in sort.iter().zip(values) {
+        let descending = direction.as_i32() == Some(-1);
+        if let Some(past) = past(field, value, descending) {
+            let mut alternative = equal.clone();
+            alternative.push(past);
+            alternatives.push(doc! {"$and": alternative});
+        }
+        // Matches missing fields too for a null value.
+        equal.push(doc! { field: value.clone() });
+    }
+    // Only possible for a made up cursor, as IDs are never null.
+    if alternatives.is_empty() {
+        return Err(invalid_cursor());
+    }
+
+    Ok(doc! {"$or": alternatives})
+}
+
+/// The condition for the values of `field` that sort past `value`, if any
+/// do. Null and missing values sort lowest, as MongoDB sorts them, but
+/// comparisons never match them so they need conditions of their own.
+fn past(field: &str, value: &Bson, descending: bool) -> Option<Document> {
+    match (value, descending) {
+        (Bson::Null, false) => Some(doc! { field: {"$ne": null} }),
+        (Bson::Null, true) => None,
+        (value, false) => Some(doc! { field: {"$gt": value.clone()} }),
+        (value, true) => Some(doc! {
+            "$or": [{ field: {"$lt": value.clone()} }, { field: null }]
+        }),
+    }
+}
+
+/// Translates the query parameters of a list endpoint into a query over the
+/// allow-listed `fields` and `id`:
+///
+/// - `field=value` or `field[op]=value` filters, `op` being one of `eq`, `ne`,
+///   `gt`, `gte`, `lt`, `lte`, `in` or `nin`, the last two taking comma
+///   separated values;
+/// - `sort=-field,field` orders by the fields, descending for a leading `-`,
+///   instead of the `default_sort`;
+/// - `fields=field,field` returns only those fields and `id`;
+/// - `limit=n` returns at most `n` records, no more than `MAX_PAGE_SIZE`;
+/// - `offset=n` skips the first `n` records, or `cursor` starts after the
+///   record a previous page's `next_cursor` was for.
+///
+/// `expand` is left to the endpoint, see [`expanded`].
+pub fn parse(
+    params: &[(String, String)],
+    fields: &[&str],
+    default_sort: &[(&str, i32)],
+) -> Result<ListQuery, (StatusCode, String)> {
+    let mut query = RecordQuery::default();
+    let mut conditions = Vec::new();
+    let (mut limit, mut offset, mut cursor) = (None, None, None);
+    for (key, param) in params {
+        match key.as_str() {
+            "limit" => limit = Some(number(key, param)?),
+            "offset" => offset = Some(number(key, param)?),
+            "cursor" => cursor = Some(param.as_str()),
+            "expand" => {}
+            "sort" => {
+                for name in names(param) {
+                    let (name, direction) = match name.strip_prefix('-') {
+                        Some(name) => (name, -1),
+                        None => (name.strip_prefix('+').unwrap_or(name), 1),
+                    };
+                    query.sort.insert(field(name, fields)?, direction);
+                }
+            }
+            "fields" => {
+                let mut projection = Vec::new();
+                for name in names(param) {
+                    let name = field(name, fields)?;
+                    if name != "_id" {
+                        projection.push(name);
+                    }
+                }
+                query.projection = Some(projection);
+            }
+            key => {
+                let (name, operator) =
+                    match key.strip_suffix(']').and_then(|key| key.split_once('[')) {
+                        Some((name, operator)) => (name, operator),
+                        None => (key, "eq"),
+                    };
+                let name = field(name, fields)?;
+                let condition = condition(&name, operator, param)?;
+                conditions.push(doc! { name: condition });
+            }
+        }
+    }
+
+    if !params.iter().any(|(key, _)| key == "sort") {
+        for (field, direction) in default_sort {
+            query.sort.insert(*field, *direction);
+        }
+    }
+    // Ties are broken by ID so the order is total, which cursors rely on.
+    if !query.sort.contains_key("_id") {
+        query.sort.insert("_id", 1);
+    }
+    let filter = match conditions.len() {
+        0 => Document::new(),
+        1 => conditions[0].clone(),
+        _ => doc! { "$and": conditions.clone() },
+    };
+    query.filter = match (cursor, offset) {
+        (Some(_), Some(_)) => {
+            return Err(bad_request("Use either a cursor or an offset".to_owned()))
+        }
+        (Some(cursor), None) => {
+            conditions.push(after(cursor, &query.sort)?);
+            doc! { "$and": conditions }
+        }
+        (None, _) => filter.clone(),
+    };
+
+    let max_page_size = env_or("MAX_PAGE_SIZE", DEFAULT_MAX_PAGE_SIZE);
+    let limit = limit.unwrap_or(max_page_size).clamp(1, max_page_size);
+    query.skip = offset.unwrap_or(0);
+    query.limit = Some(limit + 1);
+
+    Ok(ListQuery {
+        records: query,
+        filter,
+        limit,
+    })
+}
+
+/// The fields `expand=field,field` asks to embed the referenced records of.
+pub fn expanded(params: &[(String, String)]) -> Vec<String> {
+    params
+        .iter()
+        .filter(|(key, _)| key == "expand")
+        .flat_map(|(_, param)| names(param).map(str::to_owned))
+        .collect()
+}
+
+/// Renders a projected record the way the model would, with `_id` as the
+/// `id` string and dates in RFC 3339, leaving out the `hidden` fields.
+pub fn render(mut record: Document, hidden: &[String]) -> Document {
+    let mut rendered = Document::new();
+    if let Some(Bson::ObjectId(id)) = record.remove("_id") {
+        rendered.insert("id", id.to_hex());
+    }
+    for (field, value) in record {
+        if hidden.contains(&field) {
+            continue;
+        }
+        let value = match value {
+            Bson::DateTime(date) => date
+                .try_to_rfc3339_string()
+                .map_or(Bson::DateTime(date), Bson::String),
+            value => value,
+        };
+        rendered.insert(field, value);
+    }
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::filter::{matches, sort};
+
+    const FIELDS: &[&str] = &["rank", "name"];
+
+    /// Records with equal, null and missing ranks and names.
+    fn records() -> Vec<Document> {
+        let ranks = [
+            Bson::Int32(2),
+            Bson::Null,
+            Bson::Undefined,
+            Bson::Int32(1),
+            Bson::Int32(2),
+            Bson::Undefined,
+            Bson::Int32(3),
+            Bson::Null,
+        ];
+        let names = ["b", "a", "c", "a", "", "a", "c", "b"];
+        ranks
+            .into_iter()
+            .zip(names)
+            .map(|(rank, name)| {
+                let mut record = doc! {"_id": ObjectId::new()};
+                // Undefined and empty stand for a missing field here.
+                if rank != Bson::Undefined {
+                    record.insert("rank", rank);
+                }
+                if !name.is_empty() {
+                    record.insert("name", name);
+                }
+                record
+            })
+            .collect()
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// Lists every page of `records` with `sort` the way the repositories
+    /// without MongoDB run queries, following the cursors, and returns the
+    /// records in the order they came.
+    fn walk(records: &[Document], sort_param: &str, limit: u64) -> Vec<Document> {
+        let mut listed = Vec::new();
+        let mut next_cursor: Option<String> = None;
+        loop {
+            let limit = limit.to_string();
+            let mut pairs = vec![("sort", sort_param), ("limit", limit.as_str())];
+            if let Some(next_cursor) = &next_cursor {
+                pairs.push(("cursor", next_cursor));
+            }
+            let query = parse(&params(&pairs), FIELDS, &[]).unwrap();
+            let mut found: Vec<Document> = records
+                .iter()
+                .filter(|record| matches(record, &query.records.filter).unwrap())
+                .cloned()
+                .collect();
+            sort(&mut found, &query.records.sort);
+            found.truncate(query.limit as usize + 1);
+
+            let more = found.len() > query.limit as usize;
+            found.truncate(query.limit as usize);
+            next_cursor = match (more, found.last()) {
+                (true, Some(last)) => Some(cursor(last, &query.records.sort).unwrap()),
+                _ => None,
+            };
+            listed.extend(found);
+            if next_cursor.is_none() {
+                return listed;
+            }
+        }
+    }
+
+    fn sorted(records: &[Document], sort_param: &str) -> Vec<Document> {
+        let query = parse(&params(&[("sort", sort_param)]), FIELDS, &[]).unwrap();
+        let mut records = records.to_vec();
+        sort(&mut records, &query.records.sort);
+        records
+    }
+
+    #[test]
+    fn cursors_walk_past_null_and_missing_values() {
+        let records = records();
+        for sort_param in ["rank", "-rank", "-rank,name", "name,-rank", "-name,-id"] {
+            let expected = sorted(&records, sort_param);
+            for limit in 1..=4 {
+                assert_eq!(
+                    walk(&records, sort_param, limit),
+                    expected,
+                    "sort={} limit={}",
+                    sort_param,
+                    limit
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn nulls_and_missing_values_sort_lowest() {
+        let ranked: Vec<bool> = sorted(&records(), "rank")
+            .iter()
+            .map(|record| !matches!(record.get("rank"), None | Some(Bson::Null)))
+            .collect();
+
+        assert_eq!(ranked, [false, false, false, false, true, true, true, true]);
+    }
+
+    #[test]
+    fn cursors_only_fit_their_sort() {
+        let records = sorted(&records(), "rank");
+        let query = parse(&params(&[("sort", "rank")]), FIELDS, &[]).unwrap();
+        let next_cursor = cursor(&records[0], &query.records.sort).unwrap();
+
+        let other_sort = params(&[("sort", "-rank"), ("cursor", &next_cursor)]);
+        assert!(parse(&other_sort, FIELDS, &[]).is_err());
+        let made_up = params(&[("sort", "rank"), ("cursor", "bm90IGEgY3Vyc29y")]);
+        assert!(parse(&made_up, FIELDS, &[]).is_err());
+        let both = params(&[("cursor", &next_cursor), ("offset", "1")]);
+        assert!(parse(&both, FIELDS, &[]).is_err());
+    }
+
+    #[test]
+    fn parses_filters_as_strings_ids_and_dates() {
+        let query = parse(
+            &params(&[
+                ("rank", "2"),
+                ("name[in]", "a,b"),
+                ("id[ne]", "6ad4abb3557298608d132500"),
+                ("created_at[gte]", "2020-01-01T00:00:00Z"),
+            ]),
+            FIELDS,
+            &[],
+        )
+        .unwrap();
+
+        let id = ObjectId::parse_str("6ad4abb3557298608d132500").unwrap();
+        let created_at = DateTime::parse_rfc3339_str("2020-01-01T00:00:00Z").unwrap();
+        let filter = doc! {"$and": [
+            {"rank": "2"},
+            {"name": {"$in": ["a", "b"]}},
+            {"_id": {"$ne": id}},
+            {"created_at": {"$gte": created_at}},
+        ]};
+        assert_eq!(query.filter, filter);
+        assert_eq!(query.records.filter, filter);
+    }
+
+    #[test]
+    fn rejects_unknown_fields_operators_and_values() {
+        for pairs in [
+            [("password", "x")],
+            [("name[regex]", "^a")],
+            [("name[$where]", "1")],
+            [("id", "not an id")],
+            [("updated_at[lt]", "yesterday")],
+            [("sort", "password")],
+            [("fields", "name,password")],
+            [("limit", "-1")],
+        ] {
+            assert!(parse(&params(&pairs), FIELDS, &[]).is_err(), "{:?}", pairs);
+        }
+    }
+
+    #[test]
+    fn parses_sort_projection_and_page() {
+        let query = parse(
+            &params(&[
+                ("sort", "-rank,+name"),
+                ("fields", "name,id"),
+                ("limit", "10"),
+                ("offset", "20"),
+            ]),
+            FIELDS,
+            &[("name", -1)],
+        )
+        .unwrap();
+        assert_eq!(query.records.sort, doc! {"rank": -1, "name": 1, "_id": 1});
+        assert_eq!(query.records.projection, Some(vec!["name".to_owned()]));
+        assert_eq!(query.records.skip, 20);
+        assert_eq!(query.records.limit, Some(11));
+        assert_eq!(query.limit, 10);
+
+        let query = parse(&params(&[("limit", "0")]), FIELDS, &[("name", -1)]).unwrap();
+        assert_eq!(query.records.sort, doc! {"name": -1, "_id": 1});
+        assert_eq!(query.records.projection, None);
+        assert_eq!(query.limit, 1);
+        assert_eq!(query.filter, doc! {});
+    }
+}
@@ -0,0 +1,250 @@
+use crate::{
+    model::{
+        audit_model::AuditAction, metadata_model::Metadata, tech_stack_model::TechStack,
+        validation_model::ValidationError,
+    },
+    repository::{RecordQuery, Repository, TrashRepository},
+    service::{audit_service, query_service::render},
+};
+use actix_web::{http::StatusCode, HttpRequest};
+use futures::future::BoxFuture;
+use log::{info, warn};
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use std::collections::HashMap;
+
+/// The category tech stack entries created for names no entry had are put in.
+const UNCATEGORIZED: &str = "Uncategorized";
+
+type Find<'a> =
+    Box<dyn Fn(Document) -> BoxFuture<'a, Result<Vec<Document>, (StatusCode, String)>> + 'a>;
+
+/// A field holding the IDs of records in another collection, which have to
+/// exist when the field is written and which requests can `expand` the field
+/// into.
+pub struct Reference<'a> {
+    pub field: &'static str,
+    find: Find<'a>,
+}
+
+/// The IDs `field` of `record` holds.
+pub fn ids(record: &Document, field: &str) -> Vec<String> {
+    match record.get(field) {
+        Some(Bson::Array(items)) => items
+            .iter()
+            .filter_map(|item| item.as_str().map(str::to_owned))
+            .collect(),
+        Some(Bson::String(id)) => vec![id.to_owned()],
+        _ => Vec::new(),
+    }
+}
+
+impl<'a> Reference<'a> {
+    pub fn new<R>(field: &'static str, db: &'a dyn Repository<R>) -> Self {
+        Reference {
+            field,
+            find: Box::new(move |filter| {
+                db.query_records(RecordQuery {
+                    filter,
+                    ..Default::default()
+                })
+            }),
+        }
+    }
+
+    /// The referenced records with `ids`, by ID.
+    async fn find(
+        &self,
+        ids: &[String],
+    ) -> Result<HashMap<String, Document>, (StatusCode, String)> {
+        let ids: Vec<ObjectId> = ids
+            .iter()
+            .filter_map(|id| ObjectId::parse_str(id).ok())
+            .collect();
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let records = (self.find)(doc! {"_id": {"$in": ids}}).await?;
+
+        Ok(records
+            .into_iter()
+            .filter_map(|record| Some((record.get_object_id("_id").ok()?.to_hex(), record)))
+            .collect())
+    }
+}
+
+/// One error for every ID `record` references that doesn't exist.
+pub async fn validate(
+    references: &[Reference<'_>],
+    record: &Document,
+) -> Result<Vec<ValidationError>, (StatusCode, String)> {
+    let mut errors = Vec::new();
+    for reference in references {
+        let ids = ids(record, reference.field);
+        let found = reference.find(&ids).await?;
+        for id in ids.iter().filter(|id| !found.contains_key(*id)) {
+            errors.push(ValidationError::new(
+                reference.field,
+                &format!("Unknown ID {}", id),
+            ));
+        }
+    }
+    Ok(errors)
+}
+
+/// Replaces the IDs in each `requested` field of `records` with the rendered
+/// records they reference, leaving out any that no longer exist.
+pub async fn expand(
+    references: &[Reference<'_>],
+    requested: &[String],
+    records: &mut [Document],
+) -> Result<(), (StatusCode, String)> {
+    for field in requested {
+        let reference = references
+            .iter()
+            .find(|reference| reference.field == field)
+            .ok_or((StatusCode::BAD_REQUEST, format!("Cannot expand {}", field)))?;
+        let referenced: Vec<String> = records
+            .iter()
+            .flat_map(|record| ids(record, reference.field))
+            .collect();
+        let found = reference.find(&referenced).await?;
+        for record in records.iter_mut() {
+            if !record.contains_key(reference.field) {
+                continue;
+            }
+            let expanded: Vec<Document> = ids(record, reference.field)
+                .iter()
+                .filter_map(|id| found.get(id))
+                .map(|found| render(found.clone(), &[]))
+                .collect();
+            record.insert(reference.field, expanded);
+        }
+    }
+    Ok(())
+}
+
+/// How many records of `db` reference `id` in `field`, trashed ones included
+/// since restoring them would bring the reference back.
+pub async fn usage<T>(
+    db: &dyn TrashRepository<T>,
+    field: &str,
+    id: &str,
+) -> Result<u64, (StatusCode, String)> {
+    Ok(db.count_records(doc! { field: id }).await?
+        + db.trash().count_records(doc! { field: id }).await?)
+}
+
+/// Removes `id` from `field` of every record of `db` referencing it, trashed
+/// ones included, on behalf of `actor`.
+pub async fn detach<T>(
+    req: &HttpRequest,
+    db: &dyn TrashRepository<T>,
+    field: &str,
+    id: &str,
+    actor: &str,
+) -> Result<(), (StatusCode, String)> {
+    detach_from(req, db, field, id, actor).await?;
+    detach_from(req, db.trash().as_ref(), field, id, actor).await
+}
+
+async fn detach_from<T>(
+    req: &HttpRequest,
+    db: &dyn Repository<T>,
+    field: &str,
+    id: &str,
+    actor: &str,
+) -> Result<(), (StatusCode, String)> {
+    let records = db
+        .query_records(RecordQuery {
+            filter: doc! { field: id },
+            ..Default::default()
+        })
+        .await?;
+    for record in records {
+        let record_id = match record.get_object_id("_id") {
+            Ok(record_id) => record_id.to_hex(),
+            Err(_) => continue,
+        };
+        let remaining: Vec<String> = ids(&record, field)
+            .into_iter()
+            .filter(|other| other != id)
+            .collect();
+        db.update_record(&record_id, doc! { field: remaining }, Some(actor))
+            .await?;
+        audit_service::record(
+            req,
+            db,
+            AuditAction::Update,
+            &record_id,
+            Some(actor),
+            Some(record),
+        )
+        .await;
+    }
+    Ok(())
+}
+
+/// Replaces the tech stack names `tech_stack` of the records of `db` used to
+/// hold with the IDs of the entries of that name, creating entries for names
+/// there are none for yet. Trashed records and entries are included, and
+/// values shaped like IDs are left alone even if no entry has them.
+pub async fn link_tech_stack_names<T>(
+    tech_stack_db: &dyn TrashRepository<TechStack>,
+    db: &dyn TrashRepository<T>,
+) -> Result<(), (StatusCode, String)> {
+    let mut entries = tech_stack_db.query_records(RecordQuery::default()).await?;
+    entries.extend(
+        tech_stack_db
+            .trash()
+            .query_records(RecordQuery::default())
+            .await?,
+    );
+    let mut by_name = HashMap::new();
+    for entry in entries {
+        if let (Ok(id), Ok(name)) = (entry.get_object_id("_id"), entry.get_str("name")) {
+            by_name.insert(name.to_owned(), id.to_hex());
+        }
+    }
+
+    let trash = db.trash();
+    for view in [db as &dyn Repository<T>, trash.as_ref()] {
+        for record in view.query_records(RecordQuery::default()).await? {
+            let record_id = match record.get_object_id("_id") {
+                Ok(record_id) => record_id,
+                Err(_) => continue,
+            };
+            let current = ids(&record, "tech_stack");
+            let mut linked = Vec::new();
+            for value in &current {
+                if ObjectId::parse_str(value).is_ok() {
+                    if !by_name.values().any(|id| id == value) {
+                        warn!("Unknown tech stack {} in record {}", value, record_id);
+                    }
+                    linked.push(value.to_owned());
+                    continue;
+                }
+                let id = match by_name.get(value) {
+                    Some(id) => id.to_owned(),
+                    None => {
+                        let entry = TechStack {
+                            _id: None,
+                            name: value.to_owned(),
+                            category: UNCATEGORIZED.to_owned(),
+                            metadata: Metadata::default(),
+                        };
+                        let id = tech_stack_db.create_record(entry, None).await?.inserted_id;
+                        info!("Created tech stack entry {} for {}", id, value);
+                        by_name.insert(value.to_owned(), id.clone());
+                        id
+                    }
+                };
+                linked.push(id);
+            }
+            if linked != current {
+                view.update_unstamped(doc! {"_id": record_id}, doc! {"tech_stack": linked})
+                    .await?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use crate::service::{config_service::env_or, token_service::now};
+use std::{collections::HashMap, sync::Mutex};
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_LOCKOUT: u64 = 15 * 60;
+const DEFAULT_BACKOFF_BASE: u64 = 1;
+const DEFAULT_BACKOFF_MAX: u64 = 5 * 60;
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+#[derive(Debug, Default)]
+struct Attempts {
+    failures: u32,
+    blocked_until: u64,
+}
+
+/// In-memory failed login counters keyed by client IP or email, applying an
+/// exponential backoff between attempts.
+///
+/// Lockouts that have to survive restarts are recorded on the `User` document
+/// instead, see [`LoginThrottle::max_attempts`] and [`LoginThrottle::lockout`].
+#[derive(Debug)]
+pub struct LoginThrottle {
+    attempts: Mutex<HashMap<String, Attempts>>,
+    pub max_attempts: u32,
+    pub lockout: u64,
+    backoff_base: u64,
+    backoff_max: u64,
+}
+
+impl LoginThrottle {
+    /// Reads `LOGIN_MAX_ATTEMPTS`, `LOGIN_LOCKOUT`, `LOGIN_BACKOFF_BASE` and
+    /// `LOGIN_BACKOFF_MAX` (durations in seconds).
+    pub fn from_env() -> Self {
+        LoginThrottle {
+            attempts: Mutex::new(HashMap::new()),
+            max_attempts: env_or("LOGIN_MAX_ATTEMPTS", DEFAULT_MAX_ATTEMPTS).max(1),
+            lockout: env_or("LOGIN_LOCKOUT", DEFAULT_LOCKOUT),
+            backoff_base: env_or("LOGIN_BACKOFF_BASE", DEFAULT_BACKOFF_BASE),
+            backoff_max: env_or("LOGIN_BACKOFF_MAX", DEFAULT_BACKOFF_MAX),
+        }
+    }
+
+    /// Returns the number of seconds the caller has to wait if any of `keys`
+    /// is still backing off.
+    pub fn retry_after(&self, keys: &[String]) -> Option<u64> {
+        let attempts = self.attempts.lock().unwrap_or_else(|err| err.into_inner());
+        let now = now();
+        keys.iter()
+            .filter_map(|key| attempts.get(key))
+            .map(|attempt| attempt.blocked_until.saturating_sub(now))
+            .filter(|wait| *wait > 0)
+            .max()
+    }
+
+    pub fn record_failure(&self, keys: &[String]) {
+        let mut attempts = self.attempts.lock().unwrap_or_else(|err| err.into_inner());
+        let now = now();
+        if attempts.len() >= MAX_TRACKED_KEYS {
+            let backoff_max = self.backoff_max;
+            attempts.retain(|_, attempt| attempt.blocked_until + backoff_max > now);
+        }
+        for key in keys {
+            let attempt = attempts.entry(key.to_owned()).or_default();
+            attempt.failures = attempt.failures.saturating_add(1);
+            let backoff = self
+                .backoff_base
+                .saturating_mul(1 << (attempt.failures - 1).min(32))
+                .min(self.backoff_max);
+            attempt.blocked_until = now + backoff;
+        }
+    }
+
+    pub fn record_success(&self, keys: &[String]) {
+        let mut attempts = self.attempts.lock().unwrap_or_else(|err| err.into_inner());
+        for key in keys {
+            attempts.remove(key);
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use crate::{
+    model::{
+        token_model::{ActionClaims, Claims, RedeemedToken, RefreshToken, RevokedToken, TokenPair},
+        user_model::{Role, User},
+    },
+    repository::Repository,
+    service::{config_service::env_or, key_service},
+};
+use actix_web::http::StatusCode;
+use mongodb::bson::{doc, DateTime};
+use rand_core::{OsRng, RngCore};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_ACCESS_TOKEN_LIFETIME: u64 = 15 * 60;
+const DEFAULT_REFRESH_TOKEN_LIFETIME: u64 = 30 * 24 * 60 * 60;
+const CHALLENGE_LIFETIME: u64 = 5 * 60;
+/// The kind of access tokens. Like the purpose of action tokens it is named
+/// in their `typ` and `aud`, so neither passes for the other.
+pub const ACCESS_TOKEN: &str = "access";
+const TOTP_CHALLENGE: &str = "totp";
+pub const INVITATION: &str = "invitation";
+pub const EMAIL_VERIFICATION: &str = "email_verification";
+
+/// Lifetime of access tokens in seconds, configured through `JWT_LIFETIME`.
+pub fn access_token_lifetime() -> u64 {
+    env_or("JWT_LIFETIME", DEFAULT_ACCESS_TOKEN_LIFETIME)
+}
+
+/// Lifetime of refresh tokens in seconds, configured through `REFRESH_TOKEN_LIFETIME`.
+pub fn refresh_token_lifetime() -> u64 {
+    env_or("REFRESH_TOKEN_LIFETIME", DEFAULT_REFRESH_TOKEN_LIFETIME)
+}
+
+pub fn now() -> u64 {
+    now_millis() / 1000
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Returns a hex encoded string of 32 random bytes.
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Hashes an opaque token so only its digest has to be persisted.
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub fn sign<C: Serialize>(claims: &C, kind: &str) -> Result<String, (StatusCode, String)> {
+    key_service::keys()?.sign(claims, kind)
+}
+
+pub fn verify(token: &str) -> Result<Claims, (StatusCode, String)> {
+    let claims: Claims = key_service::keys()?.verify(token, ACCESS_TOKEN)?;
+
+    if claims.exp <= now() {
+        return Err((StatusCode::UNAUTHORIZED, "Token expired".to_owned()));
+    }
+
+    Ok(claims)
+}
+
+/// Signs a single purpose token valid for `lifetime` seconds.
+pub fn sign_action(
+    purpose: &str,
+    sub: &str,
+    email: Option<String>,
+    role: Option<Role>,
+    lifetime: u64,
+) -> Result<String, (StatusCode, String)> {
+    let claims = ActionClaims {
+        aud: purpose.to_owned(),
+        sub: sub.to_owned(),
+        email,
+        role,
+        exp: now() + lifetime,
+        jti: random_token(),
+    };
+
+    sign(&claims, purpose)
+}
+
+/// Verifies a token signed by [`sign_action`] for the same `purpose`.
+pub fn verify_action(token: &str, purpose: &str) -> Result<ActionClaims, (StatusCode, String)> {
+    let claims: ActionClaims = key_service::keys()?.verify(token, purpose)?;
+
+    if claims.exp <= now() {
+        return Err((StatusCode::UNAUTHORIZED, "Token expired".to_owned()));
+    }
+
+    Ok(claims)
+}
+
+/// Uses up an action token that only works once, failing with
+/// `401 Unauthorized` if it already was.
+pub async fn redeem(
+    redeemed_db: &dyn Repository<RedeemedToken>,
+    claims: &ActionClaims,
+) -> Result<(), (StatusCode, String)> {
+    let redeemed = RedeemedToken {
+        _id: None,
+        jti: claims.jti.clone(),
+        expires_at: DateTime::from_millis((claims.exp * 1000) as i64),
+    };
+    match redeemed_db.create_record(redeemed, None).await {
+        Ok(_) => Ok(()),
+        Err((StatusCode::CONFLICT, _)) => {
+            Err((StatusCode::UNAUTHORIZED, "Token already used".to_owned()))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Makes a token [`redeem`]ed for something that then failed usable again.
+pub async fn release(
+    redeemed_db: &dyn Repository<RedeemedToken>,
+    claims: &ActionClaims,
+) -> Result<(), (StatusCode, String)> {
+    redeemed_db
+        .delete_many_records(doc! {"jti": &claims.jti})
+        .await?;
+    Ok(())
+}
+
+/// Issues a challenge that has to be completed with a TOTP code before the
+/// user gets an access token. Returns the challenge and its lifetime.
+pub fn sign_totp_challenge(user_id: &str) -> Result<(String, u64), (StatusCode, String)> {
+    let challenge = sign_action(TOTP_CHALLENGE, user_id, None, None, CHALLENGE_LIFETIME)?;
+
+    Ok((challenge, CHALLENGE_LIFETIME))
+}
+
+/// Returns the ID of the user a TOTP challenge was issued to.
+pub fn verify_totp_challenge(challenge: &str) -> Result<String, (StatusCode, String)> {
+    verify_action(challenge, TOTP_CHALLENGE).map(|claims| claims.sub)
+}
+
+/// An access token for the user with `id`, signed with the test keys.
+#[cfg(test)]
+pub fn test_access_token(id: &str, role: Role) -> String {
+    key_service::init_test_keys();
+    let claims = Claims {
+        aud: ACCESS_TOKEN.to_owned(),
+        id: id.to_owned(),
+        email: "test@x.io".to_owned(),
+        role,
+        iat: now(),
+        iat_ms: Some(now_millis()),
+        exp: now() + 60,
+        jti: random_token(),
+    };
+    sign(&claims, ACCESS_TOKEN).expect("error signing test token")
+}
+
+/// Issues a new access token and a refresh token for `user`, persisting the
+/// hash of the refresh token.
+pub async fn issue_token_pair(
+    refresh_db: &dyn Repository<RefreshToken>,
+    user: &User,
+) -> Result<TokenPair, (StatusCode, String)> {
+    let id = match user._id {
+        Some(id) => id.to_string(),
+        None => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "User ID does not exist.".to_owned(),
+            ))
+        }
+    };
+    let issued_at_ms = now_millis();
+    let issued_at = issued_at_ms / 1000;
+    let expires_in = access_token_lifetime();
+    let claims = Claims {
+        aud: ACCESS_TOKEN.to_owned(),
+        id: id.clone(),
+        email: user.email.clone(),
+        role: user.role,
+        iat: issued_at,
+        iat_ms: Some(issued_at_ms),
+        exp: issued_at + expires_in,
+        jti: random_token(),
+    };
+    let access_token = sign(&claims, ACCESS_TOKEN)?;
+
+    let refresh_token = random_token();
+    let expires_at = (issued_at + refresh_token_lifetime()) * 1000;
+    refresh_db
+        .create_record(
+            RefreshToken {
+                _id: None,
+                user_id: id,
+                token_hash: hash_token(&refresh_token),
+                expires_at: DateTime::from_millis(expires_at as i64),
+            },
+            None,
+        )
+        .await?;
+
+    Ok(TokenPair {
+        access_token,
+        refresh_token,
+        token_type: "Bearer".to_owned(),
+        expires_in,
+    })
+}
+
+/// Checks the revocation store for either the token's `jti` or a revocation of
+/// every session of its user issued no earlier than the token was. Tokens
+/// without `iat_ms` count as issued at the start of their second.
+pub async fn is_revoked(
+    revoked_db: &dyn Repository<RevokedToken>,
+    claims: &Claims,
+) -> Result<bool, (StatusCode, String)> {
+    let issued_at = claims.iat_ms.unwrap_or(claims.iat * 1000);
+    for filter in [
+        doc! {"jti": &claims.jti},
+        doc! {
+            "jti": null,
+            "user_id": &claims.id,
+            "revoked_before": {"$gte": DateTime::from_millis(issued_at as i64)},
+        },
+    ] {
+        if revoked_db.count_records(filter).await? > 0 {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Revokes a single access token until it would have expired on its own.
+pub async fn revoke_token(
+    revoked_db: &dyn Repository<RevokedToken>,
+    user_id: &str,
+    jti: &str,
+    exp: u64,
+) -> Result<(), (StatusCode, String)> {
+    revoked_db
+        .create_record(
+            RevokedToken {
+                _id: None,
+                jti: Some(jti.to_owned()),
+                user_id: user_id.to_owned(),
+                revoked_before: DateTime::now(),
+                expires_at: DateTime::from_millis((exp * 1000) as i64),
+            },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Revokes every access token issued to the user so far and deletes all of
+/// their refresh tokens.
+pub async fn revoke_all_sessions(
+    revoked_db: &dyn Repository<RevokedToken>,
+    refresh_db: &dyn Repository<RefreshToken>,
+    user_id: &str,
+) -> Result<(), (StatusCode, String)> {
+    let revoked_at = DateTime::now();
+    refresh_db
+        .delete_many_records(doc! {"user_id": user_id})
+        .await?;
+    revoked_db
+        .create_record(
+            RevokedToken {
+                _id: None,
+                jti: None,
+                user_id: user_id.to_owned(),
+                revoked_before: revoked_at,
+                expires_at: DateTime::from_millis(
+                    revoked_at.timestamp_millis() + (access_token_lifetime() * 1000) as i64,
+                ),
+            },
+            None,
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::memory_repo::Memory;
+
+    fn access_claims() -> Claims {
+        Claims {
+            aud: ACCESS_TOKEN.to_owned(),
+            id: "6ad4abb3557298608d132500".to_owned(),
+            email: "a@x.io".to_owned(),
+            role: Role::Admin,
+            iat: now(),
+            iat_ms: None,
+            exp: now() + 60,
+            jti: random_token(),
+        }
+    }
+
+    #[test]
+    fn action_tokens_only_pass_for_their_purpose() {
+        key_service::init_test_keys();
+        let token = sign_action(INVITATION, "a@x.io", None, Some(Role::Viewer), 60).unwrap();
+
+        assert_eq!(verify_action(&token, INVITATION).unwrap().sub, "a@x.io");
+        assert!(verify_action(&token, EMAIL_VERIFICATION).is_err());
+        assert!(verify_totp_challenge(&token).is_err());
+        assert!(verify(&token).is_err());
+    }
+
+    #[test]
+    fn access_tokens_need_their_kind_in_header_and_claims() {
+        key_service::init_test_keys();
+        let token = sign(&access_claims(), ACCESS_TOKEN).unwrap();
+
+        assert_eq!(verify(&token).unwrap().email, "a@x.io");
+        assert!(verify_action(&token, INVITATION).is_err());
+
+        let mislabeled = sign(&access_claims(), INVITATION).unwrap();
+        assert!(verify(&mislabeled).is_err());
+        assert!(verify_action(&mislabeled, INVITATION).is_err());
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        key_service::init_test_keys();
+        let claims = Claims {
+            exp: now() - 1,
+            ..access_claims()
+        };
+        let token = sign(&claims, ACCESS_TOKEN).unwrap();
+
+        assert_eq!(verify(&token).unwrap_err().1, "Token expired");
+    }
+
+    #[actix_web::test]
+    async fn revocations_cover_tokens_issued_up_to_their_millisecond() {
+        let revoked_db = Memory::<RevokedToken>::init("RevokedToken");
+        let revoked_at = now_millis();
+        revoked_db
+            .create_record(
+                RevokedToken {
+                    _id: None,
+                    jti: None,
+                    user_id: access_claims().id,
+                    revoked_before: DateTime::from_millis(revoked_at as i64),
+                    expires_at: DateTime::from_millis(revoked_at as i64 + 60_000),
+                },
+                None,
+            )
+            .await
+            .unwrap();
+
+        let issued_at = |iat_ms: u64| Claims {
+            iat: iat_ms / 1000,
+            iat_ms: Some(iat_ms),
+            ..access_claims()
+        };
+        for (iat_ms, revoked) in [
+            (revoked_at - 1, true),
+            (revoked_at, true),
+            (revoked_at + 1, false),
+        ] {
+            let claims = issued_at(iat_ms);
+            assert_eq!(is_revoked(&revoked_db, &claims).await.unwrap(), revoked);
+        }
+        let other_user = Claims {
+            id: "6ad4abb3557298608d132501".to_owned(),
+            ..issued_at(revoked_at - 1)
+        };
+        assert!(!is_revoked(&revoked_db, &other_user).await.unwrap());
+    }
+}
@@ -0,0 +1,166 @@
+use crate::service::{config_service::env_or, token_service};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use sha1::Sha1;
+
+const STEP: u64 = 30;
+const DIGITS: u32 = 6;
+const SECRET_LENGTH: usize = 20;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Returns a new random base32 encoded secret.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; SECRET_LENGTH];
+    OsRng.fill_bytes(&mut bytes);
+    BASE32_NOPAD.encode(&bytes)
+}
+
+/// Builds the `otpauth://` URI authenticator apps read from a QR code. The
+/// issuer is configured through `TOTP_ISSUER`.
+pub fn otpauth_uri(secret: &str, account: &str) -> String {
+    let issuer = env_or("TOTP_ISSUER", "AVA".to_owned());
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        encode_uri_component(&issuer),
+        encode_uri_component(account),
+        secret,
+        encode_uri_component(&issuer),
+        DIGITS,
+        STEP
+    )
+}
+
+fn encode_uri_component(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'@' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn code_at(key: &[u8], step: u64) -> Option<u32> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).ok()?;
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    Some(binary % 10u32.pow(DIGITS))
+}
+
+/// Checks `code` against the current time step and one step either side of it
+/// to allow for clock drift. Returns the matching step, which has to be greater
+/// than `last_step` so a code can't be used twice.
+pub fn verify(secret: &str, code: &str, last_step: Option<u64>) -> Option<u64> {
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let code = code.trim();
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let current = token_service::now() / STEP;
+
+    (current.saturating_sub(1)..=current + 1)
+        .filter(|step| last_step.is_none_or(|last_step| *step > last_step))
+        .find(|step| code_at(&key, *step) == Some(code))
+}
+
+/// Generates one-time recovery codes in `xxxxx-xxxxx` form.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let token = token_service::random_token();
+            format!("{}-{}", &token[..5], &token[5..10])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RFC_6238_KEY: &[u8] = b"12345678901234567890";
+
+    fn current_code(secret: &str, offset: i64) -> (u64, String) {
+        let key = BASE32_NOPAD.decode(secret.as_bytes()).unwrap();
+        let step = (token_service::now() / STEP).saturating_add_signed(offset);
+        (step, format!("{:06}", code_at(&key, step).unwrap()))
+    }
+
+    /// The SHA-1 vectors of RFC 6238, truncated to six digits.
+    #[test]
+    fn codes_match_rfc_6238() {
+        for (time, code) in [
+            (59, 287082),
+            (1111111109, 81804),
+            (1111111111, 50471),
+            (1234567890, 5924),
+            (2000000000, 279037),
+            (20000000000, 353130),
+        ] {
+            assert_eq!(code_at(RFC_6238_KEY, time / STEP), Some(code), "T={}", time);
+        }
+    }
+
+    #[test]
+    fn accepts_one_step_of_drift() {
+        let secret = generate_secret();
+        for offset in [-1, 0, 1] {
+            // Try again if the step moved on between making and checking.
+            let (step, found) = loop {
+                let (step, code) = current_code(&secret, offset);
+                let found = verify(&secret, &code, None);
+                if current_code(&secret, offset).0 == step {
+                    break (step, found);
+                }
+            };
+            assert_eq!(found, Some(step), "offset {}", offset);
+        }
+        let (_, code) = current_code(&secret, -3);
+        assert_eq!(verify(&secret, &code, None), None);
+    }
+
+    #[test]
+    fn codes_only_work_once() {
+        let secret = generate_secret();
+        let (_, code) = current_code(&secret, 0);
+        let step = verify(&secret, &code, None).unwrap();
+
+        assert_eq!(verify(&secret, &code, Some(step)), None);
+        // Nor does an earlier one still in the window.
+        let (_, earlier) = current_code(&secret, -1);
+        assert_eq!(verify(&secret, &earlier, Some(step)), None);
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        let secret = generate_secret();
+        let (_, code) = current_code(&secret, 0);
+        assert_eq!(verify(&secret, &code[1..], None), None);
+        assert_eq!(verify(&secret, &format!("{}0", code), None), None);
+        assert_eq!(verify(&secret, "abcdef", None), None);
+        assert_eq!(verify("not base32!", &code, None), None);
+        assert!(verify(&secret, &format!(" {} ", code), None).is_some());
+    }
+
+    #[test]
+    fn generates_secrets_and_recovery_codes() {
+        let secret = BASE32_NOPAD.decode(generate_secret().as_bytes()).unwrap();
+        assert_eq!(secret.len(), SECRET_LENGTH);
+
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert!(codes
+            .iter()
+            .all(|code| code.len() == 11 && &code[5..6] == "-"));
+    }
+}
@@ -0,0 +1,35 @@
+use crate::{repository::TrashRepository, service::config_service::env_or};
+use actix_web::rt::time;
+use log::{error, info};
+use mongodb::bson::{doc, DateTime};
+use std::{sync::Arc, time::Duration};
+
+const DEFAULT_RETENTION_DAYS: i64 = 30;
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Purges the records trashed more than `TRASH_RETENTION_DAYS` ago, 30 by
+/// default, every hour. With 0 they stay in the trash until purged by hand.
+pub async fn purge_expired<T>(db: Arc<dyn TrashRepository<T>>, collection: &str) {
+    let days = env_or("TRASH_RETENTION_DAYS", DEFAULT_RETENTION_DAYS);
+    if days <= 0 {
+        return;
+    }
+    let retention = days * 24 * 60 * 60 * 1000;
+    let mut interval = time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let cutoff = DateTime::from_millis(DateTime::now().timestamp_millis() - retention);
+        match db
+            .trash()
+            .delete_many_records(doc! {"deleted_at": {"$lte": cutoff}})
+            .await
+        {
+            Ok(result) if result.deleted_count > 0 => info!(
+                "Purged {} {} records from the trash",
+                result.deleted_count, collection
+            ),
+            Ok(_) => {}
+            Err((_, err)) => error!("Error purging {} trash: {}", collection, err),
+        }
+    }
+}
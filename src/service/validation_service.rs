@@ -0,0 +1,346 @@
+use crate::{
+    model::{experience_model::PartialDate, validation_model::ValidationError},
+    service::config_service::env_or,
+};
+use email_address::EmailAddress;
+use log::info;
+use std::{collections::HashSet, fs};
+
+const DEFAULT_MIN_LENGTH: usize = 10;
+const DEFAULT_MIN_SCORE: u8 = 3;
+
+/// A small built-in list of the most common passwords, extended with the
+/// contents of `PASSWORD_BLOCKLIST_FILE` when set.
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456",
+    "123456789",
+    "12345678",
+    "1234567890",
+    "password",
+    "password1",
+    "password123",
+    "qwerty",
+    "qwerty123",
+    "qwertyuiop",
+    "abc123",
+    "111111",
+    "000000",
+    "123123",
+    "654321",
+    "iloveyou",
+    "admin",
+    "admin123",
+    "administrator",
+    "welcome",
+    "welcome1",
+    "letmein",
+    "monkey",
+    "dragon",
+    "football",
+    "baseball",
+    "sunshine",
+    "princess",
+    "master",
+    "shadow",
+    "superman",
+    "trustno1",
+    "passw0rd",
+    "p@ssw0rd",
+    "p@ssword",
+    "changeme",
+    "secret",
+    "login",
+    "starwars",
+    "whatever",
+    "1q2w3e4r",
+    "1qaz2wsx",
+    "zaq12wsx",
+    "asdfghjkl",
+    "qazwsx",
+    "michael",
+    "jennifer",
+    "hunter2",
+    "portfolio",
+];
+
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+pub fn validate_email(email: &str) -> Vec<ValidationError> {
+    if EmailAddress::is_valid(email) && email.contains('@') {
+        Vec::new()
+    } else {
+        vec![ValidationError::new("email", "Invalid email address")]
+    }
+}
+
+/// Rejects a period without a start or that ends before it starts. An open
+/// `end` is always fine.
+pub fn validate_period(
+    start: Option<&PartialDate>,
+    end: Option<&PartialDate>,
+) -> Vec<ValidationError> {
+    let start = match start {
+        Some(start) => start,
+        None => return vec![ValidationError::new("start", "Is required")],
+    };
+    match end {
+        Some(end) if end.is_before(start) => {
+            vec![ValidationError::new("end", "Must not be before start")]
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[derive(Debug)]
+pub struct PasswordPolicy {
+    min_length: usize,
+    min_score: u8,
+    blocklist: HashSet<String>,
+}
+
+impl PasswordPolicy {
+    /// Reads `PASSWORD_MIN_LENGTH`, `PASSWORD_MIN_SCORE` (0 to 4) and
+    /// `PASSWORD_BLOCKLIST_FILE` (one password per line).
+    pub fn from_env() -> Self {
+        let mut blocklist: HashSet<String> = COMMON_PASSWORDS
+            .iter()
+            .map(|password| password.to_string())
+            .collect();
+        if let Ok(path) = dotenv::var("PASSWORD_BLOCKLIST_FILE") {
+            let contents =
+                fs::read_to_string(&path).expect("error reading PASSWORD_BLOCKLIST_FILE");
+            blocklist.extend(
+                contents
+                    .lines()
+                    .map(|line| line.trim().to_lowercase())
+                    .filter(|line| !line.is_empty()),
+            );
+            info!("Loaded password blocklist from {}", path);
+        }
+
+        PasswordPolicy {
+            min_length: env_or("PASSWORD_MIN_LENGTH", DEFAULT_MIN_LENGTH),
+            min_score: env_or("PASSWORD_MIN_SCORE", DEFAULT_MIN_SCORE).min(4),
+            blocklist,
+        }
+    }
+
+    /// Checks `password` against the policy. `email` is used to reject
+    /// passwords derived from the account's own address.
+    pub fn validate(&self, password: &str, email: Option<&str>) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        let lowercase = password.to_lowercase();
+
+        if password.chars().count() < self.min_length {
+            errors.push(ValidationError::new(
+                "password",
+                &format!("Must be at least {} characters long", self.min_length),
+            ));
+        }
+        if self.blocklist.contains(&lowercase) {
+            errors.push(ValidationError::new("password", "Is too common"));
+        }
+        if let Some(local_part) = email
+            .and_then(|email| email.split('@').next())
+            .filter(|local_part| local_part.len() >= 3)
+        {
+            if lowercase.contains(&local_part.to_lowercase()) {
+                errors.push(ValidationError::new(
+                    "password",
+                    "Must not contain the email address",
+                ));
+            }
+        }
+        let score = self.score(&lowercase, password);
+        if score < self.min_score {
+            errors.push(ValidationError::new(
+                "password",
+                &format!(
+                    "Is too easy to guess (strength {} of 4, at least {} required)",
+                    score, self.min_score
+                ),
+            ));
+        }
+
+        errors
+    }
+
+    /// Estimates the strength of a password from 0 (trivially guessable) to 4
+    /// (very hard to guess), using the same guess thresholds as zxcvbn.
+    fn score(&self, lowercase: &str, password: &str) -> u8 {
+        let mut guesses_log10 = estimate_guesses_log10(password);
+
+        // A common password embedded in a longer one only adds the guesses
+        // needed to pick it from the blocklist.
+        if let Some(word) = self
+            .blocklist
+            .iter()
+            .filter(|word| word.len() >= 4 && lowercase.contains(word.as_str()))
+            .max_by_key(|word| word.len())
+        {
+            let rest = lowercase.replacen(word.as_str(), "", 1);
+            guesses_log10 =
+                estimate_guesses_log10(&rest) + (self.blocklist.len().max(1) as f64).log10();
+        }
+
+        match guesses_log10 {
+            x if x < 3.0 => 0,
+            x if x < 6.0 => 1,
+            x if x < 8.0 => 2,
+            x if x < 10.0 => 3,
+            _ => 4,
+        }
+    }
+}
+
+/// Brute force estimate over the character classes in use, where characters
+/// that only repeat or continue a sequence (`aaa`, `abc`, `321`) count for
+/// little.
+fn estimate_guesses_log10(password: &str) -> f64 {
+    let chars: Vec<char> = password.chars().collect();
+    let mut charset = 0;
+    if chars.iter().any(|c| c.is_ascii_lowercase()) {
+        charset += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_uppercase()) {
+        charset += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_digit()) {
+        charset += 10;
+    }
+    if chars.iter().any(|c| !c.is_ascii_alphanumeric()) {
+        charset += 33;
+    }
+    if charset == 0 {
+        return 0.0;
+    }
+
+    let mut effective_length = 0.0;
+    for (index, c) in chars.iter().enumerate() {
+        let predictable = index > 0 && {
+            let step = *c as i64 - chars[index - 1] as i64;
+            step.abs() <= 1
+        };
+        effective_length += if predictable { 0.25 } else { 1.0 };
+    }
+
+    effective_length * (charset as f64).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: DEFAULT_MIN_LENGTH,
+            min_score: DEFAULT_MIN_SCORE,
+            blocklist: COMMON_PASSWORDS
+                .iter()
+                .map(|password| password.to_string())
+                .collect(),
+        }
+    }
+
+    fn messages(errors: Vec<ValidationError>) -> Vec<String> {
+        errors.into_iter().map(|error| error.message).collect()
+    }
+
+    #[test]
+    fn accepts_long_unpredictable_passwords() {
+        for password in ["correct horse battery staple", "Zq8#vTw3!kPm"] {
+            assert!(
+                policy().validate(password, Some("alice@x.io")).is_empty(),
+                "{}",
+                password
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_short_and_common_passwords() {
+        let errors = messages(policy().validate("Zq8#vT", None));
+        assert!(errors.contains(&"Must be at least 10 characters long".to_owned()));
+
+        let errors = messages(policy().validate("PASSWORD1", None));
+        assert!(errors.contains(&"Is too common".to_owned()));
+    }
+
+    #[test]
+    fn rejects_predictable_passwords() {
+        for password in [
+            "aaaaaaaaaaaaaaa",
+            "abcdefghijklmno",
+            "9876543210987",
+            "Password123456",
+        ] {
+            let errors = messages(policy().validate(password, None));
+            assert!(
+                errors
+                    .iter()
+                    .any(|error| error.starts_with("Is too easy to guess")),
+                "{}: {:?}",
+                password,
+                errors
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_passwords_containing_the_email() {
+        let errors = messages(policy().validate("Alice.Smith-Zq8#vT", Some("alice.smith@x.io")));
+        assert_eq!(errors, ["Must not contain the email address"]);
+
+        // Too short a local part would reject too much.
+        assert!(policy()
+            .validate("al-Zq8#vTw3!kP", Some("al@x.io"))
+            .is_empty());
+    }
+
+    #[test]
+    fn honours_configured_thresholds() {
+        let lenient = PasswordPolicy {
+            min_length: 4,
+            min_score: 0,
+            ..policy()
+        };
+        assert!(lenient.validate("abcd", None).is_empty());
+        assert!(!lenient.validate("abc", None).is_empty());
+    }
+
+    #[test]
+    fn normalizes_and_validates_emails() {
+        assert_eq!(normalize_email("  Alice@X.io "), "alice@x.io");
+        assert!(validate_email("alice@x.io").is_empty());
+        for email in ["alice", "alice@", "@x.io", ""] {
+            assert!(!validate_email(email).is_empty(), "{}", email);
+        }
+    }
+
+    #[test]
+    fn checks_periods() {
+        let date = |date: &str| date.parse::<PartialDate>().unwrap();
+        let fields = |errors: Vec<ValidationError>| -> Vec<(String, String)> {
+            errors
+                .into_iter()
+                .map(|error| (error.field, error.message))
+                .collect()
+        };
+        assert_eq!(
+            fields(validate_period(None, Some(&date("2021-03")))),
+            [("start".to_owned(), "Is required".to_owned())]
+        );
+        assert_eq!(
+            fields(validate_period(
+                Some(&date("2021-03")),
+                Some(&date("2021-02-28"))
+            )),
+            [("end".to_owned(), "Must not be before start".to_owned())]
+        );
+        assert!(validate_period(Some(&date("2021-03")), None).is_empty());
+        assert!(validate_period(Some(&date("2021-03-09")), Some(&date("2021-03"))).is_empty());
+        assert!(validate_period(Some(&date("2021-03")), Some(&date("2021-03-01"))).is_empty());
+    }
+}